@@ -0,0 +1,377 @@
+//! High & low temperature alarms.
+//!
+//! An alarm is raised once the temperature stayed past a threshold for the delay, and cleared once
+//! it is back within the threshold by [`HYSTERESIS`]. The thresholds are independent of the
+//! controller's target, so a broken controller or a wrong target still sets off the alarm.
+
+use crate::thermometer::Temperature;
+
+/// Distance the temperature has to be back within a threshold to clear its alarm, 0.5 °C
+pub const HYSTERESIS: Temperature = Temperature::from_bits(1 << 3);
+
+/// Thresholds of the alarms
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AlarmConfig {
+    pub low: Temperature,
+    pub high: Temperature,
+    /// Seconds the temperature has to stay past a threshold to raise the alarm
+    pub delay: u32,
+}
+
+impl AlarmConfig {
+    pub const DEFAULT: Self = Self {
+        low: Temperature::ZERO,
+        high: Temperature::const_from_int(10),
+        delay: 5 * 60,
+    };
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Threshold an alarm is about
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AlarmKind {
+    Low = 0,
+    High,
+}
+
+impl AlarmKind {
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Low,
+            _ => Self::High,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::High => "high",
+        }
+    }
+}
+
+/// State of the alarms
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AlarmState {
+    Normal,
+    /// The temperature is past a threshold since `since` seconds, waiting for the delay
+    Pending {
+        kind: AlarmKind,
+        since: u32,
+    },
+    Active {
+        kind: AlarmKind,
+        acknowledged: bool,
+    },
+}
+
+/// Change of an alarm after a measurement
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Transition {
+    Raised(AlarmKind),
+    Cleared(AlarmKind),
+}
+
+/// The alarms, fed with every measured temperature
+#[derive(Debug, Clone)]
+pub struct Alarm {
+    pub config: AlarmConfig,
+    state: AlarmState,
+    /// Seconds until which the alert output is silenced
+    silenced_until: u32,
+}
+
+impl Alarm {
+    pub const fn new(config: AlarmConfig) -> Self {
+        Self {
+            config,
+            state: AlarmState::Normal,
+            silenced_until: 0,
+        }
+    }
+
+    pub const fn state(&self) -> AlarmState {
+        self.state
+    }
+
+    /// Kind of the raised alarm, if any
+    pub const fn active(&self) -> Option<AlarmKind> {
+        match self.state {
+            AlarmState::Active { kind, .. } => Some(kind),
+            _ => None,
+        }
+    }
+
+    /// Threshold of an alarm
+    pub const fn threshold(&self, kind: AlarmKind) -> Temperature {
+        match kind {
+            AlarmKind::Low => self.config.low,
+            AlarmKind::High => self.config.high,
+        }
+    }
+
+    /// Feeds a temperature measured at `secs`, returning whether an alarm was raised or cleared
+    pub fn update(&mut self, secs: u32, temp: Temperature) -> Option<Transition> {
+        let past = if temp > self.config.high {
+            Some(AlarmKind::High)
+        } else if temp < self.config.low {
+            Some(AlarmKind::Low)
+        } else {
+            None
+        };
+
+        match self.state {
+            AlarmState::Active { kind, .. } => {
+                let cleared = match kind {
+                    AlarmKind::Low => temp >= self.config.low.saturating_add(HYSTERESIS),
+                    AlarmKind::High => temp <= self.config.high.saturating_sub(HYSTERESIS),
+                };
+                if !cleared {
+                    return None;
+                }
+                self.state = AlarmState::Normal;
+                Some(Transition::Cleared(kind))
+            }
+            AlarmState::Pending { kind, since } if past == Some(kind) => {
+                if secs.wrapping_sub(since) < self.config.delay {
+                    return None;
+                }
+                self.state = AlarmState::Active {
+                    kind,
+                    acknowledged: false,
+                };
+                Some(Transition::Raised(kind))
+            }
+            AlarmState::Normal | AlarmState::Pending { .. } => {
+                self.state = past.map_or(AlarmState::Normal, |kind| AlarmState::Pending {
+                    kind,
+                    since: secs,
+                });
+                // Without a delay, the alarm is raised right away
+                if past.is_some() && self.config.delay == 0 {
+                    return self.update(secs, temp);
+                }
+                None
+            }
+        }
+    }
+
+    /// Acknowledges the raised alarm, silencing the alert output until it clears
+    ///
+    /// Returns the kind of the acknowledged alarm, `None` if none is raised or it already was
+    /// acknowledged.
+    pub const fn acknowledge(&mut self) -> Option<AlarmKind> {
+        match &mut self.state {
+            AlarmState::Active {
+                kind,
+                acknowledged: acknowledged @ false,
+            } => {
+                *acknowledged = true;
+                Some(*kind)
+            }
+            _ => None,
+        }
+    }
+
+    /// Silences the alert output until `until` seconds, for alarms raised meanwhile as well
+    pub const fn silence(&mut self, until: u32) {
+        self.silenced_until = until;
+    }
+
+    /// Seconds left of silencing the alert output at `secs`
+    pub const fn silenced(&self, secs: u32) -> u32 {
+        self.silenced_until.saturating_sub(secs)
+    }
+
+    /// Whether the alert output should be on at `secs`, for a raised alarm that is neither
+    /// acknowledged nor silenced
+    pub const fn sounding(&self, secs: u32) -> bool {
+        matches!(
+            self.state,
+            AlarmState::Active {
+                acknowledged: false,
+                ..
+            }
+        ) && self.silenced(secs) == 0
+    }
+}
+
+impl AlarmState {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Pending { .. } => "pending",
+            Self::Active {
+                acknowledged: false,
+                ..
+            } => "active",
+            Self::Active {
+                acknowledged: true, ..
+            } => "acknowledged",
+        }
+    }
+
+    /// Kind of the pending or raised alarm, if any
+    pub const fn kind(self) -> Option<AlarmKind> {
+        match self {
+            Self::Normal => None,
+            Self::Pending { kind, .. } | Self::Active { kind, .. } => Some(kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(temp: f32) -> Temperature {
+        Temperature::from_num(temp)
+    }
+
+    /// Alarms at 0 & 10 °C after `delay` seconds
+    fn alarm(delay: u32) -> Alarm {
+        Alarm::new(AlarmConfig {
+            delay,
+            ..AlarmConfig::DEFAULT
+        })
+    }
+
+    #[test]
+    fn raised_after_delay() {
+        let mut alarm = alarm(60);
+        assert_eq!(alarm.update(100, t(11.0)), None);
+        assert_eq!(
+            alarm.state(),
+            AlarmState::Pending {
+                kind: AlarmKind::High,
+                since: 100
+            }
+        );
+        assert_eq!(alarm.update(159, t(12.0)), None);
+        assert_eq!(alarm.active(), None);
+        assert_eq!(
+            alarm.update(160, t(11.0)),
+            Some(Transition::Raised(AlarmKind::High))
+        );
+        assert_eq!(alarm.active(), Some(AlarmKind::High));
+        assert!(alarm.sounding(160));
+        // Raised only once
+        assert_eq!(alarm.update(170, t(11.0)), None);
+    }
+
+    #[test]
+    fn pending_reset_within_thresholds() {
+        let mut alarm = alarm(60);
+        alarm.update(0, t(-1.0));
+        // At the threshold is within it
+        assert_eq!(alarm.update(30, t(0.0)), None);
+        assert_eq!(alarm.state(), AlarmState::Normal);
+        // The delay starts over
+        alarm.update(40, t(-1.0));
+        assert_eq!(alarm.update(99, t(-1.0)), None);
+        assert_eq!(
+            alarm.update(100, t(-1.0)),
+            Some(Transition::Raised(AlarmKind::Low))
+        );
+    }
+
+    #[test]
+    fn without_delay() {
+        let mut alarm = alarm(0);
+        assert_eq!(alarm.update(5, t(10.0)), None);
+        assert_eq!(
+            alarm.update(5, t(10.0625)),
+            Some(Transition::Raised(AlarmKind::High))
+        );
+        assert_eq!(alarm.active(), Some(AlarmKind::High));
+    }
+
+    #[test]
+    fn switching_pending_kind() {
+        let mut alarm = alarm(60);
+        alarm.update(0, t(11.0));
+        alarm.update(50, t(-1.0));
+        assert_eq!(
+            alarm.state(),
+            AlarmState::Pending {
+                kind: AlarmKind::Low,
+                since: 50
+            }
+        );
+        // The delay of the high alarm doesn't count for the low one
+        assert_eq!(alarm.update(100, t(-1.0)), None);
+        assert_eq!(
+            alarm.update(110, t(-1.0)),
+            Some(Transition::Raised(AlarmKind::Low))
+        );
+    }
+
+    #[test]
+    fn cleared_with_hysteresis() {
+        let mut alarm = alarm(0);
+        assert_eq!(
+            alarm.update(0, t(11.0)),
+            Some(Transition::Raised(AlarmKind::High))
+        );
+        assert_eq!(alarm.update(1, t(10.0)), None);
+        assert_eq!(alarm.update(2, t(9.5625)), None);
+        assert_eq!(
+            alarm.update(3, t(9.5)),
+            Some(Transition::Cleared(AlarmKind::High))
+        );
+        assert_eq!(alarm.state(), AlarmState::Normal);
+
+        assert_eq!(
+            alarm.update(4, t(-0.0625)),
+            Some(Transition::Raised(AlarmKind::Low))
+        );
+        assert_eq!(alarm.update(5, t(0.4375)), None);
+        assert_eq!(
+            alarm.update(6, t(0.5)),
+            Some(Transition::Cleared(AlarmKind::Low))
+        );
+    }
+
+    #[test]
+    fn acknowledged_until_cleared() {
+        let mut alarm = alarm(0);
+        assert_eq!(alarm.acknowledge(), None);
+        alarm.update(0, t(11.0));
+        assert_eq!(alarm.acknowledge(), Some(AlarmKind::High));
+        assert_eq!(alarm.acknowledge(), None);
+        assert!(!alarm.sounding(1));
+        assert_eq!(alarm.state().as_str(), "acknowledged");
+        // Still raised, but quiet
+        assert_eq!(alarm.active(), Some(AlarmKind::High));
+
+        assert_eq!(
+            alarm.update(2, t(5.0)),
+            Some(Transition::Cleared(AlarmKind::High))
+        );
+        assert_eq!(
+            alarm.update(3, t(11.0)),
+            Some(Transition::Raised(AlarmKind::High))
+        );
+        assert!(alarm.sounding(3));
+    }
+
+    #[test]
+    fn silence_expires() {
+        let mut alarm = alarm(0);
+        alarm.silence(100);
+        assert_eq!(alarm.silenced(40), 60);
+        // Also for alarms raised while silenced
+        alarm.update(50, t(11.0));
+        assert_eq!(alarm.active(), Some(AlarmKind::High));
+        assert!(!alarm.sounding(99));
+        assert_eq!(alarm.silenced(100), 0);
+        assert!(alarm.sounding(100));
+        assert_eq!(alarm.silenced(200), 0);
+    }
+}
@@ -0,0 +1,56 @@
+//! Controller to manage a cooler to keep a constant temperature.
+
+use crate::thermometer::Temperature;
+
+pub mod pid;
+
+// Controllers are only ever driven from a single executor, so the returned future doesn't need to
+// be `Send`.
+#[allow(async_fn_in_trait)]
+pub trait Controller {
+    type Error;
+
+    /// Set the target temperature in degrees Celsius
+    fn set_target(&mut self, target: Temperature);
+
+    /// Get the target temperature in degrees Celsius
+    fn get_target(&self) -> Temperature;
+
+    /// Run the controller for a single tick
+    ///
+    /// Returns 0 if cooler should be completely off, 255 if cooler should be completely on, or
+    /// somewhere in between.
+    async fn run(&mut self, temp: Temperature) -> Result<u8, Self::Error>;
+}
+
+/// How the cooler is driven
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum CoolerMode {
+    /// The controller turns the cooler on & off
+    #[default]
+    Auto = 0,
+    /// The cooler is kept on
+    On,
+    /// The cooler is kept off
+    Off,
+}
+
+impl CoolerMode {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::On => "on",
+            Self::Off => "off",
+        }
+    }
+
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Auto),
+            1 => Some(Self::On),
+            2 => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
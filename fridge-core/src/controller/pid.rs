@@ -0,0 +1,131 @@
+use core::convert::Infallible;
+
+use pid::Pid;
+
+use crate::thermometer::Temperature;
+
+/// Target temperature the fridge starts with
+pub const TARGET_TEMP: Temperature = Temperature::const_from_int(5);
+/// Default proportional gain
+pub const KP: Temperature = Temperature::from_bits(1 << 4);
+/// Default integral gain
+pub const KI: Temperature = Temperature::from_bits(1 << 2);
+/// Default derivative gain
+pub const KD: Temperature = Temperature::from_bits(1 << 1);
+
+/// Limit of each term & the output of the PID controller
+const LIMIT: Temperature = Temperature::const_from_int(128);
+
+/// Gains of a [`PidController`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PidGains {
+    pub kp: Temperature,
+    pub ki: Temperature,
+    pub kd: Temperature,
+}
+
+impl PidGains {
+    pub const DEFAULT: Self = Self {
+        kp: KP,
+        ki: KI,
+        kd: KD,
+    };
+}
+
+impl Default for PidGains {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Terms of the last output of a [`PidController`], each limited to ±128
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PidTerms {
+    pub p: Temperature,
+    pub i: Temperature,
+    pub d: Temperature,
+    /// Sum of the terms, before mapping to the output of the controller
+    pub output: Temperature,
+}
+
+pub struct PidController {
+    pid: Pid<Temperature>,
+    terms: PidTerms,
+}
+
+impl PidController {
+    pub fn new(
+        target: impl Into<Temperature>,
+        kp: impl Into<Temperature>,
+        ki: impl Into<Temperature>,
+        kd: impl Into<Temperature>,
+    ) -> Self {
+        let mut pid = Pid::new(target, LIMIT);
+        pid.p(kp, LIMIT);
+        pid.i(ki, LIMIT);
+        pid.d(kd, LIMIT);
+
+        Self {
+            pid,
+            terms: PidTerms::default(),
+        }
+    }
+
+    /// Get the gains of the controller
+    pub const fn gains(&self) -> PidGains {
+        PidGains {
+            kp: self.pid.kp,
+            ki: self.pid.ki,
+            kd: self.pid.kd,
+        }
+    }
+
+    /// Get the terms of the last output
+    pub const fn terms(&self) -> PidTerms {
+        self.terms
+    }
+
+    /// Whether the integral term of the last output is at its limit
+    ///
+    /// The error kept adding up until the term was clamped, so the cooler can't reach the target or
+    /// the gains don't suit it.
+    pub fn is_wound_up(&self) -> bool {
+        self.terms.i.abs() >= LIMIT
+    }
+
+    /// Set the gains of the controller
+    pub fn set_gains(&mut self, gains: PidGains) {
+        self.pid.p(gains.kp, LIMIT);
+        self.pid.i(gains.ki, LIMIT);
+        self.pid.d(gains.kd, LIMIT);
+    }
+}
+
+impl super::Controller for PidController {
+    type Error = Infallible;
+
+    fn set_target(&mut self, target: Temperature) {
+        self.pid.setpoint = target;
+    }
+
+    fn get_target(&self) -> Temperature {
+        self.pid.setpoint
+    }
+
+    async fn run(&mut self, temp: Temperature) -> Result<u8, Self::Error> {
+        let output = self.pid.next_control_output(temp);
+        self.terms = PidTerms {
+            p: output.p,
+            i: output.i,
+            d: output.d,
+            output: output.output,
+        };
+
+        // Map output from range (-128, 128) to (0, 255)
+        let output = output
+            .output
+            .saturating_add(Temperature::const_from_int(128))
+            .saturating_to_num();
+        Ok(output)
+    }
+}
@@ -0,0 +1,608 @@
+//! Delta-compressed encoding of temperature logs.
+//!
+//! Temperatures are stored at a fixed period & rarely change by more than a step in between, so
+//! most of a [`TempRecord`] is redundant. A compressed log is a sequence of entries, each starting
+//! with a varint (LEB128) code:
+//!
+//! | Code  | Entry                                                                             |
+//! |-------|-----------------------------------------------------------------------------------|
+//! | 0     | Block header: [`TempFormat`], period, seconds since startup & zigzag encoded value |
+//! | 1     | Gap: the next sample comes a varint number of seconds later than a period         |
+//! | 2     | Reset: the clock restarted, a block header follows                                |
+//! | 3     | Run: a varint number of samples equal to the previous one                         |
+//! | 4     | Pack: a byte of 4 samples as 2 bit deltas of 0, 1 or -1, the first in the low bits |
+//! | 5 ... | A single sample, its zigzag encoded delta plus 5                                  |
+//!
+//! Values & deltas are in steps of the resolution of the format. A block header is written every
+//! [`BLOCK_SAMPLES`] samples, so a log can be cut at a block without losing the timestamps after
+//! it. A stable temperature takes well under a byte per sample, a slowly changing one a quarter to
+//! half a byte.
+
+use crate::record::{StoredTemp, TempFormat, TempRecord};
+
+/// Maximum number of samples in a block, after its header
+pub const BLOCK_SAMPLES: u32 = 256;
+
+// Entry codes
+const HEADER: u32 = 0;
+const GAP: u32 = 1;
+const RESET: u32 = 2;
+const RUN: u32 = 3;
+const PACK: u32 = 4;
+const SINGLE: u32 = 5;
+
+/// Samples in a [`PACK`] entry
+const PACKED: u8 = 4;
+/// Runs shorter than this are written as packed zero deltas
+const MIN_RUN: u32 = 4;
+
+/// Error decoding a compressed log
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The log ends in the middle of an entry
+    Truncated,
+    /// A sample comes before the first block header, or right after a reset
+    MissingHeader,
+    /// A delta or gap takes a sample out of the range of the format
+    OutOfRange,
+    /// A block header has a different [`TempFormat`] than the records being decoded
+    Format,
+}
+
+impl Error {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Truncated => "truncated",
+            Self::MissingHeader => "missing header",
+            Self::OutOfRange => "out of range",
+            Self::Format => "wrong record format",
+        }
+    }
+}
+
+/// Compresses a stream of temperature records
+///
+/// Bytes are passed to `out` as soon as they are known. Small deltas are held back until there
+/// are enough to pack, so [`Encoder::flush`] must be called at the end of the log.
+#[derive(Debug, Clone)]
+pub struct Encoder<R = StoredTemp> {
+    /// Expected seconds between samples
+    period: u32,
+    /// Last sample, `None` before the first block
+    last: Option<R>,
+    /// Samples in the current block
+    block: u32,
+    /// Zero deltas not written yet, only counted while nothing is packed
+    zeros: u32,
+    /// Small deltas not written yet, 2 bits each
+    pack: u8,
+    /// Number of deltas in `pack`
+    packed: u8,
+}
+
+impl<R: TempRecord> Encoder<R> {
+    /// Creates an encoder for samples `period` seconds apart
+    pub const fn new(period: u32) -> Self {
+        Self {
+            period,
+            last: None,
+            block: 0,
+            zeros: 0,
+            pack: 0,
+            packed: 0,
+        }
+    }
+
+    /// Encodes the next sample
+    ///
+    /// Samples later than a period are written with a gap. Samples earlier than a period start a
+    /// new block, & samples older than the previous one are written after a reset.
+    pub fn push(&mut self, temp: R, out: &mut impl FnMut(u8)) {
+        let Some(last) = self.last else {
+            self.header(temp, out);
+            return;
+        };
+
+        let Some(elapsed) = temp.secs().checked_sub(last.secs()) else {
+            self.flush(out);
+            write_varint(RESET, out);
+            self.header(temp, out);
+            return;
+        };
+        if elapsed < self.period || self.block >= BLOCK_SAMPLES {
+            self.flush(out);
+            self.header(temp, out);
+            return;
+        }
+        if elapsed > self.period {
+            self.flush(out);
+            write_varint(GAP, out);
+            write_varint(elapsed - self.period, out);
+        }
+
+        self.block += 1;
+        self.last = Some(temp);
+        self.delta(temp.bits() - last.bits(), out);
+    }
+
+    /// Writes the deltas held back, must be called once the log ends
+    ///
+    /// The encoder can be used afterwards, e.g. to flush after every sample written to flash.
+    pub fn flush(&mut self, out: &mut impl FnMut(u8)) {
+        self.flush_zeros(out);
+        for _ in 0..self.packed {
+            let delta = unpack(self.pack).unwrap_or_else(|| unreachable!());
+            write_varint(zigzag(delta) + SINGLE, out);
+            self.pack >>= 2;
+        }
+        self.pack = 0;
+        self.packed = 0;
+    }
+
+    fn header(&mut self, temp: R, out: &mut impl FnMut(u8)) {
+        write_varint(HEADER, out);
+        write_varint(u32::from(R::FORMAT as u8), out);
+        write_varint(self.period, out);
+        write_varint(temp.secs(), out);
+        write_varint(zigzag(temp.bits()), out);
+        self.last = Some(temp);
+        self.block = 0;
+    }
+
+    fn delta(&mut self, delta: i32, out: &mut impl FnMut(u8)) {
+        if delta == 0 && self.packed == 0 {
+            self.zeros += 1;
+            return;
+        }
+        self.flush_zeros(out);
+
+        if (-1..=1).contains(&delta) {
+            self.push_packed(delta, out);
+        } else {
+            self.flush(out);
+            write_varint(zigzag(delta) + SINGLE, out);
+        }
+    }
+
+    fn push_packed(&mut self, delta: i32, out: &mut impl FnMut(u8)) {
+        let code: u8 = match delta {
+            0 => 0b00,
+            1 => 0b01,
+            _ => 0b10,
+        };
+        self.pack |= code << (2 * self.packed);
+        self.packed += 1;
+
+        if self.packed == PACKED {
+            write_varint(PACK, out);
+            out(self.pack);
+            self.pack = 0;
+            self.packed = 0;
+        }
+    }
+
+    fn flush_zeros(&mut self, out: &mut impl FnMut(u8)) {
+        if self.zeros >= MIN_RUN {
+            write_varint(RUN, out);
+            write_varint(self.zeros, out);
+        } else {
+            // Nothing is packed while zeros are counted, so these don't fill the pack
+            for _ in 0..self.zeros {
+                self.push_packed(0, out);
+            }
+        }
+        self.zeros = 0;
+    }
+}
+
+/// Format of the records of a compressed log, from its first block header
+pub fn format(bytes: &[u8]) -> Option<TempFormat> {
+    match *bytes {
+        [0, format, ..] => TempFormat::from_u8(format),
+        _ => None,
+    }
+}
+
+/// Decompresses a log of records of type `R`, yielding its samples in order
+///
+/// Stops at the first error, see [`Decoder::error`]. Use [`format`] to find the type of a log.
+pub const fn decode<R: TempRecord>(bytes: &[u8]) -> Decoder<'_, R> {
+    Decoder {
+        bytes,
+        period: 0,
+        last: None,
+        gap: 0,
+        run: 0,
+        pack: 0,
+        packed: 0,
+        error: None,
+    }
+}
+
+/// Iterator over the samples of a compressed log
+///
+/// Created by [`decode`].
+#[derive(Debug, Clone)]
+pub struct Decoder<'a, R> {
+    bytes: &'a [u8],
+    period: u32,
+    /// Last sample, `None` before a block header
+    last: Option<R>,
+    /// Seconds to add to the period before the next sample
+    gap: u32,
+    /// Samples left of a run
+    run: u32,
+    /// Deltas left of a pack, 2 bits each
+    pack: u8,
+    packed: u8,
+    error: Option<Error>,
+}
+
+impl<R: TempRecord> Decoder<'_, R> {
+    /// The error that stopped decoding, if any
+    pub const fn error(&self) -> Option<Error> {
+        self.error
+    }
+
+    fn next_sample(&mut self) -> Result<Option<R>, Error> {
+        loop {
+            if self.run > 0 {
+                self.run -= 1;
+                return self.step(0).map(Some);
+            }
+            if self.packed > 0 {
+                let delta = unpack(self.pack).ok_or(Error::OutOfRange)?;
+                self.pack >>= 2;
+                self.packed -= 1;
+                return self.step(delta).map(Some);
+            }
+            if self.bytes.is_empty() {
+                return Ok(None);
+            }
+
+            match self.varint()? {
+                HEADER => {
+                    if self.varint()? != u32::from(R::FORMAT as u8) {
+                        return Err(Error::Format);
+                    }
+                    self.period = self.varint()?;
+                    let secs = self.varint()?;
+                    let bits = unzigzag(self.varint()?);
+                    let temp = R::from_bits(secs, bits).ok_or(Error::OutOfRange)?;
+                    self.last = Some(temp);
+                    self.gap = 0;
+                    return Ok(Some(temp));
+                }
+                GAP => self.gap = self.gap.saturating_add(self.varint()?),
+                RESET => self.last = None,
+                RUN => self.run = self.varint()?,
+                PACK => {
+                    [self.pack] = self.array()?;
+                    self.packed = PACKED;
+                }
+                code => return self.step(unzigzag(code - SINGLE)).map(Some),
+            }
+        }
+    }
+
+    /// Produces the sample `delta` steps from the last one, a period (& any gap) later
+    fn step(&mut self, delta: i32) -> Result<R, Error> {
+        let last = self.last.ok_or(Error::MissingHeader)?;
+        let secs = last
+            .secs()
+            .checked_add(self.period)
+            .and_then(|s| s.checked_add(self.gap))
+            .ok_or(Error::OutOfRange)?;
+        // The delta comes from the log, so it may be anything in a malformed one
+        let bits = last.bits().checked_add(delta).ok_or(Error::OutOfRange)?;
+        let temp = R::from_bits(secs, bits).ok_or(Error::OutOfRange)?;
+        self.last = Some(temp);
+        self.gap = 0;
+        Ok(temp)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let (bytes, rest) = self.bytes.split_first_chunk().ok_or(Error::Truncated)?;
+        self.bytes = rest;
+        Ok(*bytes)
+    }
+
+    fn varint(&mut self) -> Result<u32, Error> {
+        let mut value = 0u32;
+        for shift in (0..32).step_by(7) {
+            let [b] = self.array()?;
+            // The last byte only has 4 bits left
+            if shift == 28 && b & 0x70 != 0 {
+                return Err(Error::OutOfRange);
+            }
+            value |= u32::from(b & 0x7F) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::OutOfRange)
+    }
+}
+
+impl<R: TempRecord> Iterator for Decoder<'_, R> {
+    type Item = R;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_sample() {
+            Ok(temp) => temp,
+            Err(e) => {
+                self.error = Some(e);
+                self.bytes = &[];
+                self.run = 0;
+                self.packed = 0;
+                None
+            }
+        }
+    }
+}
+
+/// Delta of the lowest 2 bits of a pack, `None` for the unused code
+const fn unpack(pack: u8) -> Option<i32> {
+    match pack & 0b11 {
+        0b00 => Some(0),
+        0b01 => Some(1),
+        0b10 => Some(-1),
+        _ => None,
+    }
+}
+
+const fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)).cast_unsigned()
+}
+
+const fn unzigzag(value: u32) -> i32 {
+    (value >> 1).cast_signed() ^ -((value & 1).cast_signed())
+}
+
+fn write_varint(mut value: u32, out: &mut impl FnMut(u8)) {
+    while value >= 0x80 {
+        // Masked to 7 bits
+        #[allow(clippy::cast_possible_truncation)]
+        out((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    // Less than 0x80
+    #[allow(clippy::cast_possible_truncation)]
+    out(value as u8);
+}
+
+#[cfg(test)]
+// Entry codes & the values of the tests fit a byte
+#[allow(clippy::cast_possible_truncation)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::record::PreciseTemp;
+
+    const PERIOD: u32 = 2;
+
+    /// Round-trips samples of `(secs, bits)` in one of the formats, returning the encoding
+    type RoundTrip<'a> = &'a dyn Fn(&[(u32, i32)]) -> Vec<u8>;
+
+    /// Records of `(secs, bits)`
+    fn temps<R: TempRecord>(samples: &[(u32, i32)]) -> Vec<R> {
+        samples
+            .iter()
+            .map(|&(secs, bits)| R::from_bits(secs, bits).unwrap())
+            .collect()
+    }
+
+    /// Records a period apart from 100 s on, of the given bits
+    fn periodic<R: TempRecord>(bits: &[i32]) -> Vec<R> {
+        let samples: Vec<_> = (100..)
+            .step_by(PERIOD as usize)
+            .zip(bits.iter().copied())
+            .collect();
+        temps(&samples)
+    }
+
+    fn encode<R: TempRecord>(temps: &[R]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = Encoder::<R>::new(PERIOD);
+        for &temp in temps {
+            encoder.push(temp, &mut |b| bytes.push(b));
+        }
+        encoder.flush(&mut |b| bytes.push(b));
+        bytes
+    }
+
+    /// Encodes & decodes records, checking they come back unchanged, returning the encoding
+    fn round_trip<R: TempRecord>(temps: &[R]) -> Vec<u8> {
+        let bytes = encode(temps);
+        let mut decoder = decode::<R>(&bytes);
+        let samples: Vec<R> = decoder.by_ref().collect();
+        assert_eq!(decoder.error(), None);
+        assert_eq!(samples, temps);
+        bytes
+    }
+
+    /// Runs a test for both record formats
+    fn both(test: fn(RoundTrip)) {
+        test(&|samples| round_trip(&temps::<StoredTemp>(samples)));
+        test(&|samples| round_trip(&temps::<PreciseTemp>(samples)));
+    }
+
+    fn periodic_samples(bits: &[i32]) -> Vec<(u32, i32)> {
+        periodic::<PreciseTemp>(bits)
+            .iter()
+            .map(|t| (t.secs(), t.bits()))
+            .collect()
+    }
+
+    #[test]
+    fn empty() {
+        assert!(encode::<StoredTemp>(&[]).is_empty());
+        assert_eq!(decode::<StoredTemp>(&[]).next(), None);
+    }
+
+    #[test]
+    fn runs() {
+        both(|round_trip| {
+            let bytes = round_trip(&periodic_samples(&[20; 100]));
+            // A header & a single run
+            assert!(bytes.len() < 12, "{bytes:?}");
+            // Runs too short to be worth it are packed
+            round_trip(&periodic_samples(&[20, 20, 20, 21, 21, 21, 21, 21, 22]));
+        });
+    }
+
+    #[test]
+    fn packs() {
+        both(|round_trip| {
+            let bits: Vec<i32> = (0..50).map(|i| [0, 1, 0, -1][i % 4] + 20).collect();
+            let bytes = round_trip(&periodic_samples(&bits));
+            // 2 bytes per pack of 4 samples, after the header
+            assert!(bytes.len() < 8 + 50 / 2 + 4, "{bytes:?}");
+            // Partial packs are written as single deltas by the flush
+            round_trip(&periodic_samples(&[20, 21, 22]));
+            round_trip(&periodic_samples(&[20, 20, 21, 21, 21, 21, 21, 21]));
+        });
+    }
+
+    #[test]
+    fn single_deltas() {
+        both(|round_trip| {
+            round_trip(&periodic_samples(&[0, 5, -5, 100, -100, 127, -128, 1, 3]));
+            // Between packed deltas
+            round_trip(&periodic_samples(&[0, 1, 0, 40, -1, 0, 1, 1, 1, 1]));
+        });
+    }
+
+    #[test]
+    fn gaps() {
+        both(|round_trip| {
+            round_trip(&[(10, 1), (12, 1), (20, 1), (22, 2), (1000, 3), (1002, 3)]);
+            // A gap in the middle of a run & of a pack
+            round_trip(&[(0, 5), (2, 5), (4, 5), (6, 5), (8, 5), (30, 5), (32, 5)]);
+            round_trip(&[(0, 5), (2, 6), (4, 5), (9, 6), (11, 5)]);
+        });
+    }
+
+    #[test]
+    fn resets() {
+        both(|round_trip| {
+            // Older than the previous sample, e.g. after a reboot
+            round_trip(&[(100, 1), (102, 1), (104, 2), (0, 7), (2, 7), (4, 8)]);
+            // Earlier than a period, a new block
+            round_trip(&[(100, 1), (102, 1), (103, 1), (105, 2)]);
+            // Twice the same time
+            round_trip(&[(100, 1), (100, 2), (102, 2)]);
+        });
+    }
+
+    #[test]
+    fn block_boundaries() {
+        both(|round_trip| {
+            let block = BLOCK_SAMPLES as usize;
+            for len in [block, block + 1, block + 2, 2 * block + 3, 3 * block + 5] {
+                let bits: Vec<i32> = (0..len).map(|i| [0, 1, 1, 0, -1][i % 5]).collect();
+                round_trip(&periodic_samples(&bits));
+                round_trip(&periodic_samples(&std::vec![7; len]));
+            }
+        });
+    }
+
+    #[test]
+    fn blocks_decode_on_their_own() {
+        let temps = periodic::<StoredTemp>(&[3; 3 * BLOCK_SAMPLES as usize]);
+        let bytes = encode(&temps);
+        // Cut the log at the header of the second block
+        let header = [HEADER as u8, TempFormat::Compact as u8, PERIOD as u8];
+        let start = bytes.windows(3).skip(1).position(|w| w == header).unwrap() + 1;
+        let decoded: Vec<StoredTemp> = decode(&bytes[start..]).collect();
+        assert_eq!(decoded, temps[temps.len() - decoded.len()..]);
+        assert!(decoded.len() < temps.len());
+    }
+
+    #[test]
+    fn format_of_log() {
+        let compact = encode(&periodic::<StoredTemp>(&[1, 2]));
+        let precise = encode(&periodic::<PreciseTemp>(&[1, 2]));
+        assert_eq!(format(&compact), Some(TempFormat::Compact));
+        assert_eq!(format(&precise), Some(TempFormat::Precise));
+        assert_eq!(format(&[]), None);
+        assert_eq!(format(&[RUN as u8, 1]), None);
+    }
+
+    #[test]
+    fn wrong_format() {
+        let bytes = encode(&periodic::<StoredTemp>(&[1, 2, 3]));
+        let mut decoder = decode::<PreciseTemp>(&bytes);
+        assert_eq!(decoder.next(), None);
+        assert_eq!(decoder.error(), Some(Error::Format));
+    }
+
+    #[test]
+    fn truncated() {
+        let temps = periodic::<PreciseTemp>(&[1000, 1000, 1300]);
+        let bytes = encode(&temps);
+        // In the middle of the varint of the last delta
+        let mut decoder = decode::<PreciseTemp>(&bytes[..bytes.len() - 1]);
+        let samples: Vec<PreciseTemp> = decoder.by_ref().collect();
+        assert_eq!(samples, temps[..2]);
+        assert_eq!(decoder.error(), Some(Error::Truncated));
+        // In the middle of the header
+        let mut decoder = decode::<PreciseTemp>(&bytes[..3]);
+        assert_eq!(decoder.next(), None);
+        assert_eq!(decoder.error(), Some(Error::Truncated));
+        // A pack without its byte
+        let mut decoder = decode::<PreciseTemp>(&[HEADER as u8, 2, 2, 0, 0, PACK as u8]);
+        assert_eq!(decoder.by_ref().count(), 1);
+        assert_eq!(decoder.error(), Some(Error::Truncated));
+    }
+
+    #[test]
+    fn malformed() {
+        let header = [HEADER as u8, TempFormat::Precise as u8, 2, 0, 0];
+        let decode_with = |entry: &[u8]| {
+            let bytes = [&header[..], entry].concat();
+            let mut decoder = decode::<PreciseTemp>(&bytes);
+            let count = decoder.by_ref().count();
+            (count, decoder.error())
+        };
+
+        // Deltas of about ±2^31, overflowing a negative & a positive value
+        for (value, delta) in [(31, 0xFA), (32, 0xFB)] {
+            let bytes = [HEADER as u8, 2, 2, 0, value, delta, 0xFF, 0xFF, 0xFF, 0x0F];
+            let mut decoder = decode::<PreciseTemp>(&bytes);
+            assert_eq!(decoder.by_ref().count(), 1);
+            assert_eq!(decoder.error(), Some(Error::OutOfRange));
+        }
+        // Out of the range of the format
+        assert_eq!(
+            decode_with(&[0x85, 0x80, 0x04]),
+            (1, Some(Error::OutOfRange))
+        );
+        // A varint longer than 32 bits
+        assert_eq!(
+            decode_with(&[0xFF, 0xFF, 0xFF, 0xFF, 0x7F]),
+            (1, Some(Error::OutOfRange))
+        );
+        // The unused code of a pack
+        assert_eq!(
+            decode_with(&[PACK as u8, 0b11]),
+            (1, Some(Error::OutOfRange))
+        );
+        // A gap taking the seconds out of range
+        assert_eq!(
+            decode_with(&[GAP as u8, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, SINGLE as u8]),
+            (1, Some(Error::OutOfRange))
+        );
+        // Samples without a header
+        let mut decoder = decode::<PreciseTemp>(&[SINGLE as u8]);
+        assert_eq!(decoder.next(), None);
+        assert_eq!(decoder.error(), Some(Error::MissingHeader));
+        let bytes = [&header[..], &[RESET as u8, RUN as u8, 2]].concat();
+        let mut decoder = decode::<PreciseTemp>(&bytes);
+        assert_eq!(decoder.by_ref().count(), 1);
+        assert_eq!(decoder.error(), Some(Error::MissingHeader));
+    }
+}
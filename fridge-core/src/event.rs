@@ -0,0 +1,818 @@
+//! Events stored next to the temperatures
+//!
+//! An event is stored as an [`EventCode`] & a [`PAYLOAD_SIZE`] byte payload holding the fields of
+//! the [`Event`] in little endian, unused bytes zeroed. Temperatures are the raw bits of a
+//! [`Temperature`], as in the binary protocol.
+//!
+//! Every code has a [`Severity`] to filter by, & a [minimum interval](EventCode::min_interval)
+//! between records so a failing sensor can't flood the log.
+//!
+//! Repeats of an event are counted in the last [`REPEAT_SIZE`] bytes of its payload, which the
+//! fields of codes that [coalesce](EventCode::coalesces) leave unused.
+
+use crate::{
+    alarm::AlarmKind,
+    controller::{pid::PidGains, CoolerMode},
+    thermometer::Temperature,
+};
+
+/// Size of the payload of an event
+pub const PAYLOAD_SIZE: usize = 12;
+/// Bytes at the end of a payload counting repeats of the event: the seconds from the first to
+/// the last occurrence (u16) & the number of occurrences (u8)
+pub const REPEAT_SIZE: usize = 3;
+/// Bytes kept of the source file name of an [`Event::Panic`]
+pub const PANIC_FILE_SIZE: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventCode {
+    /// Unknown event type
+    Unknown = 0,
+    /// Temperature sensor error
+    TempSensorError,
+    /// Temperature sensor resolution changed
+    TempSensorResolutionChanged,
+    /// PID controller error
+    PidError,
+    /// PID controller target changed
+    PidTargetChanged,
+    /// PID parameters changed
+    PidParamsChanged,
+    /// Startup, with the boot count & reset cause
+    Boot,
+    /// Panic reported by the next boot, with the source location
+    Panic,
+    /// The cooler was switched on or off
+    CoolerSwitched,
+    /// The cooler mode was changed
+    CoolerModeChanged,
+    /// The thermometer responds, at startup or again after it was lost
+    TempSensorFound,
+    /// The thermometer stopped responding
+    TempSensorLost,
+    /// Another thermometer was selected
+    TempSensorSelected,
+    /// The real-time clock was set
+    ClockSet,
+    /// The stored records were erased
+    StorageErased,
+    /// A task missed its heartbeat deadline & the watchdog reset the MCU, reported by the next boot
+    TaskStalled,
+    /// The temperature stayed past an alarm threshold for the delay
+    AlarmRaised,
+    /// The temperature is back within the threshold of the raised alarm
+    AlarmCleared,
+    /// The raised alarm was acknowledged
+    AlarmAcknowledged,
+    /// The alert output was silenced
+    AlarmSilenced,
+    /// The alarm thresholds were changed
+    AlarmThresholdsChanged,
+}
+
+/// Severity of an event, from the least to the most severe
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Severity {
+    Info = 0,
+    Warning,
+    Error,
+}
+
+/// An event with the fields of its code
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    Unknown,
+    TempSensorError {
+        error: SensorError,
+        /// 1-Wire address of the thermometer
+        address: u64,
+    },
+    TempSensorResolutionChanged {
+        bits: u8,
+        /// 1-Wire address of the thermometer
+        address: u64,
+    },
+    PidError,
+    PidTargetChanged {
+        old: Temperature,
+        new: Temperature,
+    },
+    PidParamsChanged {
+        old: PidGains,
+        new: PidGains,
+    },
+    Boot {
+        /// Number of boots including this one
+        count: u32,
+        cause: ResetCause,
+    },
+    Panic {
+        /// Start of the source file name, null-terminated unless it fills the array
+        file: [u8; PANIC_FILE_SIZE],
+        line: u32,
+    },
+    CoolerSwitched {
+        on: bool,
+        /// Percentage of controller ticks the cooler was on since startup
+        duty: u8,
+    },
+    CoolerModeChanged {
+        old: CoolerMode,
+        new: CoolerMode,
+    },
+    TempSensorFound {
+        /// 1-Wire address of the thermometer
+        address: u64,
+    },
+    TempSensorLost {
+        /// Error of the first failed measurement
+        error: SensorError,
+        /// 1-Wire address of the thermometer
+        address: u64,
+    },
+    TempSensorSelected {
+        /// 1-Wire address of the thermometer
+        address: u64,
+    },
+    ClockSet {
+        /// Unix time before, 0 if the clock wasn't set
+        old: u32,
+        new: u32,
+    },
+    StorageErased,
+    TaskStalled {
+        task: Task,
+    },
+    AlarmRaised {
+        kind: AlarmKind,
+        temp: Temperature,
+        threshold: Temperature,
+    },
+    AlarmCleared {
+        kind: AlarmKind,
+        temp: Temperature,
+    },
+    AlarmAcknowledged {
+        kind: AlarmKind,
+    },
+    AlarmSilenced {
+        secs: u32,
+    },
+    AlarmThresholdsChanged {
+        low: Temperature,
+        high: Temperature,
+        /// Seconds past a threshold before raising its alarm
+        delay: u32,
+    },
+}
+
+/// Error of a 1-Wire thermometer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SensorError {
+    Unknown = 0,
+    /// The bus wasn't pulled high by the pull-up resistor
+    BusNotHigh,
+    /// The bus pin failed
+    Pin,
+    /// A device answered a command unexpectedly, e.g. it was added or removed meanwhile
+    UnexpectedResponse,
+    /// The device isn't a thermometer
+    FamilyCodeMismatch,
+    CrcMismatch,
+    Timeout,
+}
+
+/// Task of the firmware, e.g. the one running when it panicked
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Task {
+    Unknown,
+    Init,
+    Idle,
+    Blinky,
+    Watchdog,
+    TempController,
+    Storage,
+    Terminal,
+    ModbusSlave,
+    Usart2,
+    Alarm,
+}
+
+/// Cause of the last reset
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ResetCause {
+    Unknown = 0,
+    PowerOn,
+    /// The reset pin was pulled low
+    Pin,
+    /// The independent watchdog expired
+    Watchdog,
+    /// The window watchdog expired
+    WindowWatchdog,
+    /// Reset by the firmware, e.g. the `reset` command
+    Software,
+    /// Entering standby or stop mode while it is disallowed by the option bytes
+    LowPower,
+    /// The option bytes were reloaded
+    OptionBytes,
+}
+
+impl EventCode {
+    /// Converts a stored code, unknown codes to [`EventCode::Unknown`]
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::TempSensorError,
+            2 => Self::TempSensorResolutionChanged,
+            3 => Self::PidError,
+            4 => Self::PidTargetChanged,
+            5 => Self::PidParamsChanged,
+            6 => Self::Boot,
+            7 => Self::Panic,
+            8 => Self::CoolerSwitched,
+            9 => Self::CoolerModeChanged,
+            10 => Self::TempSensorFound,
+            11 => Self::TempSensorLost,
+            12 => Self::TempSensorSelected,
+            13 => Self::ClockSet,
+            14 => Self::StorageErased,
+            15 => Self::TaskStalled,
+            16 => Self::AlarmRaised,
+            17 => Self::AlarmCleared,
+            18 => Self::AlarmAcknowledged,
+            19 => Self::AlarmSilenced,
+            20 => Self::AlarmThresholdsChanged,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub const fn severity(self) -> Severity {
+        match self {
+            Self::Unknown | Self::TempSensorLost | Self::StorageErased | Self::AlarmSilenced => {
+                Severity::Warning
+            }
+            Self::TempSensorError
+            | Self::PidError
+            | Self::Panic
+            | Self::TaskStalled
+            | Self::AlarmRaised => Severity::Error,
+            Self::TempSensorResolutionChanged
+            | Self::PidTargetChanged
+            | Self::PidParamsChanged
+            | Self::Boot
+            | Self::CoolerSwitched
+            | Self::CoolerModeChanged
+            | Self::TempSensorFound
+            | Self::TempSensorSelected
+            | Self::ClockSet
+            | Self::AlarmCleared
+            | Self::AlarmAcknowledged
+            | Self::AlarmThresholdsChanged => Severity::Info,
+        }
+    }
+
+    /// Minimum seconds between the first occurrences of records of the code
+    ///
+    /// Repeats of the most recent event are counted in its record regardless, if the code
+    /// [coalesces](Self::coalesces).
+    pub const fn min_interval(self) -> u32 {
+        match self {
+            Self::TempSensorError | Self::PidError => 60,
+            _ => 0,
+        }
+    }
+
+    /// Whether repeats of the code are counted in the record of the event, as its fields leave
+    /// [`REPEAT_SIZE`] bytes of the payload unused
+    pub const fn coalesces(self) -> bool {
+        !matches!(self, Self::PidParamsChanged | Self::Panic)
+    }
+
+    /// Whether a new record of the code is dropped at `now`, the last record of the code having
+    /// first occurred at `last`
+    ///
+    /// Events may be stored out of order, so a `last` after `now` isn't limited.
+    pub fn is_rate_limited(self, last: u32, now: u32) -> bool {
+        now.checked_sub(last)
+            .is_some_and(|elapsed| elapsed < self.min_interval())
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "Unknown",
+            Self::TempSensorError => "Temperature sensor error",
+            Self::TempSensorResolutionChanged => "Temperature sensor resolution changed",
+            Self::PidError => "PID controller error",
+            Self::PidTargetChanged => "PID controller target changed",
+            Self::PidParamsChanged => "PID parameters changed",
+            Self::Boot => "Boot",
+            Self::Panic => "Panic",
+            Self::CoolerSwitched => "Cooler switched",
+            Self::CoolerModeChanged => "Cooler mode changed",
+            Self::TempSensorFound => "Temperature sensor found",
+            Self::TempSensorLost => "Temperature sensor lost",
+            Self::TempSensorSelected => "Temperature sensor selected",
+            Self::ClockSet => "Clock set",
+            Self::StorageErased => "Storage erased",
+            Self::TaskStalled => "Task stalled",
+            Self::AlarmRaised => "Alarm raised",
+            Self::AlarmCleared => "Alarm cleared",
+            Self::AlarmAcknowledged => "Alarm acknowledged",
+            Self::AlarmSilenced => "Alarm silenced",
+            Self::AlarmThresholdsChanged => "Alarm thresholds changed",
+        }
+    }
+}
+
+impl Event {
+    /// A panic at a line of a source file, keeping the start of the file name
+    pub fn panic(file: &str, line: u32) -> Self {
+        let mut bytes = [0; PANIC_FILE_SIZE];
+        let len = file.len().min(PANIC_FILE_SIZE);
+        bytes[..len].copy_from_slice(&file.as_bytes()[..len]);
+        Self::Panic { file: bytes, line }
+    }
+
+    pub const fn code(&self) -> EventCode {
+        match self {
+            Self::Unknown => EventCode::Unknown,
+            Self::TempSensorError { .. } => EventCode::TempSensorError,
+            Self::TempSensorResolutionChanged { .. } => EventCode::TempSensorResolutionChanged,
+            Self::PidError => EventCode::PidError,
+            Self::PidTargetChanged { .. } => EventCode::PidTargetChanged,
+            Self::PidParamsChanged { .. } => EventCode::PidParamsChanged,
+            Self::Boot { .. } => EventCode::Boot,
+            Self::Panic { .. } => EventCode::Panic,
+            Self::CoolerSwitched { .. } => EventCode::CoolerSwitched,
+            Self::CoolerModeChanged { .. } => EventCode::CoolerModeChanged,
+            Self::TempSensorFound { .. } => EventCode::TempSensorFound,
+            Self::TempSensorLost { .. } => EventCode::TempSensorLost,
+            Self::TempSensorSelected { .. } => EventCode::TempSensorSelected,
+            Self::ClockSet { .. } => EventCode::ClockSet,
+            Self::StorageErased => EventCode::StorageErased,
+            Self::TaskStalled { .. } => EventCode::TaskStalled,
+            Self::AlarmRaised { .. } => EventCode::AlarmRaised,
+            Self::AlarmCleared { .. } => EventCode::AlarmCleared,
+            Self::AlarmAcknowledged { .. } => EventCode::AlarmAcknowledged,
+            Self::AlarmSilenced { .. } => EventCode::AlarmSilenced,
+            Self::AlarmThresholdsChanged { .. } => EventCode::AlarmThresholdsChanged,
+        }
+    }
+
+    pub fn to_payload(&self) -> [u8; PAYLOAD_SIZE] {
+        let mut w = Writer::new();
+        match *self {
+            Self::Unknown | Self::PidError | Self::StorageErased => {}
+            Self::TempSensorError { error, address } | Self::TempSensorLost { error, address } => {
+                w.bytes(&[error as u8]);
+                w.bytes(&address.to_le_bytes());
+            }
+            Self::TempSensorResolutionChanged { bits, address } => {
+                w.bytes(&[bits]);
+                w.bytes(&address.to_le_bytes());
+            }
+            Self::PidTargetChanged { old, new } => {
+                w.bytes(&old.to_le_bytes());
+                w.bytes(&new.to_le_bytes());
+            }
+            Self::PidParamsChanged { old, new } => {
+                w.gains(old);
+                w.gains(new);
+            }
+            Self::Boot { count, cause } => {
+                w.bytes(&count.to_le_bytes());
+                w.bytes(&[cause as u8]);
+            }
+            Self::Panic { file, line } => {
+                w.bytes(&file);
+                w.bytes(&line.to_le_bytes());
+            }
+            Self::CoolerSwitched { on, duty } => w.bytes(&[u8::from(on), duty]),
+            Self::CoolerModeChanged { old, new } => w.bytes(&[old as u8, new as u8]),
+            Self::TempSensorFound { address } | Self::TempSensorSelected { address } => {
+                w.bytes(&address.to_le_bytes());
+            }
+            Self::ClockSet { old, new } => {
+                w.bytes(&old.to_le_bytes());
+                w.bytes(&new.to_le_bytes());
+            }
+            Self::TaskStalled { task } => w.bytes(&[task as u8]),
+            Self::AlarmRaised {
+                kind,
+                temp,
+                threshold,
+            } => {
+                w.bytes(&[kind as u8]);
+                w.bytes(&temp.to_le_bytes());
+                w.bytes(&threshold.to_le_bytes());
+            }
+            Self::AlarmCleared { kind, temp } => {
+                w.bytes(&[kind as u8]);
+                w.bytes(&temp.to_le_bytes());
+            }
+            Self::AlarmAcknowledged { kind } => w.bytes(&[kind as u8]),
+            Self::AlarmSilenced { secs } => w.bytes(&secs.to_le_bytes()),
+            Self::AlarmThresholdsChanged { low, high, delay } => {
+                w.bytes(&low.to_le_bytes());
+                w.bytes(&high.to_le_bytes());
+                w.bytes(&delay.to_le_bytes());
+            }
+        }
+        w.buf
+    }
+
+    /// Reads the fields of an event of a code from its payload
+    pub const fn from_payload(code: EventCode, payload: &[u8; PAYLOAD_SIZE]) -> Self {
+        let mut r = Reader { buf: payload };
+        match code {
+            EventCode::Unknown => Self::Unknown,
+            EventCode::TempSensorError => Self::TempSensorError {
+                error: SensorError::from_u8(r.u8()),
+                address: u64::from_le_bytes(r.array()),
+            },
+            EventCode::TempSensorResolutionChanged => Self::TempSensorResolutionChanged {
+                bits: r.u8(),
+                address: u64::from_le_bytes(r.array()),
+            },
+            EventCode::PidError => Self::PidError,
+            EventCode::PidTargetChanged => Self::PidTargetChanged {
+                old: r.temp(),
+                new: r.temp(),
+            },
+            EventCode::PidParamsChanged => Self::PidParamsChanged {
+                old: r.gains(),
+                new: r.gains(),
+            },
+            EventCode::Boot => Self::Boot {
+                count: u32::from_le_bytes(r.array()),
+                cause: ResetCause::from_u8(r.u8()),
+            },
+            EventCode::Panic => Self::Panic {
+                file: r.array(),
+                line: u32::from_le_bytes(r.array()),
+            },
+            EventCode::CoolerSwitched => Self::CoolerSwitched {
+                on: r.u8() != 0,
+                duty: r.u8(),
+            },
+            EventCode::CoolerModeChanged => Self::CoolerModeChanged {
+                old: r.mode(),
+                new: r.mode(),
+            },
+            EventCode::TempSensorFound => Self::TempSensorFound {
+                address: u64::from_le_bytes(r.array()),
+            },
+            EventCode::TempSensorLost => Self::TempSensorLost {
+                error: SensorError::from_u8(r.u8()),
+                address: u64::from_le_bytes(r.array()),
+            },
+            EventCode::TempSensorSelected => Self::TempSensorSelected {
+                address: u64::from_le_bytes(r.array()),
+            },
+            EventCode::ClockSet => Self::ClockSet {
+                old: u32::from_le_bytes(r.array()),
+                new: u32::from_le_bytes(r.array()),
+            },
+            EventCode::StorageErased => Self::StorageErased,
+            EventCode::TaskStalled => Self::TaskStalled {
+                task: Task::from_u8(r.u8()),
+            },
+            EventCode::AlarmRaised => Self::AlarmRaised {
+                kind: AlarmKind::from_u8(r.u8()),
+                temp: r.temp(),
+                threshold: r.temp(),
+            },
+            EventCode::AlarmCleared => Self::AlarmCleared {
+                kind: AlarmKind::from_u8(r.u8()),
+                temp: r.temp(),
+            },
+            EventCode::AlarmAcknowledged => Self::AlarmAcknowledged {
+                kind: AlarmKind::from_u8(r.u8()),
+            },
+            EventCode::AlarmSilenced => Self::AlarmSilenced {
+                secs: u32::from_le_bytes(r.array()),
+            },
+            EventCode::AlarmThresholdsChanged => Self::AlarmThresholdsChanged {
+                low: r.temp(),
+                high: r.temp(),
+                delay: u32::from_le_bytes(r.array()),
+            },
+        }
+    }
+}
+
+/// Text of a null-terminated string field, e.g. the file of an [`Event::Panic`]
+///
+/// Stops at the first byte that isn't valid UTF-8.
+pub fn text(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len])
+        .unwrap_or_else(|e| core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default())
+}
+
+impl Severity {
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Info),
+            1 => Some(Self::Warning),
+            2 => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl SensorError {
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::BusNotHigh,
+            2 => Self::Pin,
+            3 => Self::UnexpectedResponse,
+            4 => Self::FamilyCodeMismatch,
+            5 => Self::CrcMismatch,
+            6 => Self::Timeout,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::BusNotHigh => "bus not high",
+            Self::Pin => "pin error",
+            Self::UnexpectedResponse => "unexpected response",
+            Self::FamilyCodeMismatch => "family code mismatch",
+            Self::CrcMismatch => "CRC mismatch",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+impl Task {
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Init,
+            2 => Self::Idle,
+            3 => Self::Blinky,
+            4 => Self::Watchdog,
+            5 => Self::TempController,
+            6 => Self::Storage,
+            7 => Self::Terminal,
+            8 => Self::ModbusSlave,
+            9 => Self::Usart2,
+            10 => Self::Alarm,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Init => "init",
+            Self::Idle => "idle",
+            Self::Blinky => "blinky",
+            Self::Watchdog => "watchdog",
+            Self::TempController => "temp_controller",
+            Self::Storage => "storage",
+            Self::Terminal => "terminal",
+            Self::ModbusSlave => "modbus_slave",
+            Self::Usart2 => "usart2",
+            Self::Alarm => "alarm",
+        }
+    }
+}
+
+impl ResetCause {
+    pub const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::PowerOn,
+            2 => Self::Pin,
+            3 => Self::Watchdog,
+            4 => Self::WindowWatchdog,
+            5 => Self::Software,
+            6 => Self::LowPower,
+            7 => Self::OptionBytes,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::PowerOn => "power-on",
+            Self::Pin => "pin",
+            Self::Watchdog => "watchdog",
+            Self::WindowWatchdog => "window watchdog",
+            Self::Software => "software",
+            Self::LowPower => "low-power",
+            Self::OptionBytes => "option bytes",
+        }
+    }
+}
+
+/// Seconds from the first occurrence of an event at `first` to a repeat at `secs`, `None` if its
+/// record can't count the repeat
+///
+/// Events may be stored out of order, a repeat before the first occurrence isn't counted either.
+pub fn repeat_delta(first: u32, secs: u32) -> Option<u16> {
+    u16::try_from(secs.checked_sub(first)?).ok()
+}
+
+/// Writes the fields of a payload
+struct Writer {
+    buf: [u8; PAYLOAD_SIZE],
+    len: usize,
+}
+
+impl Writer {
+    const fn new() -> Self {
+        Self {
+            buf: [0; PAYLOAD_SIZE],
+            len: 0,
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn gains(&mut self, gains: PidGains) {
+        self.bytes(&gains.kp.to_le_bytes());
+        self.bytes(&gains.ki.to_le_bytes());
+        self.bytes(&gains.kd.to_le_bytes());
+    }
+}
+
+/// Reads the fields of a payload, as zeros past its end
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl Reader<'_> {
+    const fn array<const N: usize>(&mut self) -> [u8; N] {
+        match self.buf.split_first_chunk() {
+            Some((bytes, rest)) => {
+                self.buf = rest;
+                *bytes
+            }
+            None => [0; N],
+        }
+    }
+
+    const fn u8(&mut self) -> u8 {
+        let [b] = self.array();
+        b
+    }
+
+    const fn temp(&mut self) -> Temperature {
+        Temperature::from_le_bytes(self.array())
+    }
+
+    /// Reads a cooler mode, unknown ones as [`CoolerMode::Auto`]
+    const fn mode(&mut self) -> CoolerMode {
+        match CoolerMode::from_u8(self.u8()) {
+            Some(mode) => mode,
+            None => CoolerMode::Auto,
+        }
+    }
+
+    const fn gains(&mut self) -> PidGains {
+        PidGains {
+            kp: self.temp(),
+            ki: self.temp(),
+            kd: self.temp(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An event of every code, with the largest fields of the code
+    fn events() -> [Event; EventCode::AlarmThresholdsChanged as usize + 1] {
+        let temp = Temperature::MIN;
+        let gains = PidGains {
+            kp: temp,
+            ki: temp,
+            kd: temp,
+        };
+        let address = u64::MAX;
+        [
+            Event::Unknown,
+            Event::TempSensorError {
+                error: SensorError::Timeout,
+                address,
+            },
+            Event::TempSensorResolutionChanged { bits: 12, address },
+            Event::PidError,
+            Event::PidTargetChanged {
+                old: temp,
+                new: temp,
+            },
+            Event::PidParamsChanged {
+                old: gains,
+                new: gains,
+            },
+            Event::Boot {
+                count: u32::MAX,
+                cause: ResetCause::OptionBytes,
+            },
+            Event::Panic {
+                file: *b"main.rs\0",
+                line: u32::MAX,
+            },
+            Event::CoolerSwitched {
+                on: true,
+                duty: 100,
+            },
+            Event::CoolerModeChanged {
+                old: CoolerMode::On,
+                new: CoolerMode::Off,
+            },
+            Event::TempSensorFound { address },
+            Event::TempSensorLost {
+                error: SensorError::CrcMismatch,
+                address,
+            },
+            Event::TempSensorSelected { address },
+            Event::ClockSet {
+                old: u32::MAX,
+                new: u32::MAX,
+            },
+            Event::StorageErased,
+            Event::TaskStalled { task: Task::Alarm },
+            Event::AlarmRaised {
+                kind: AlarmKind::High,
+                temp,
+                threshold: temp,
+            },
+            Event::AlarmCleared {
+                kind: AlarmKind::High,
+                temp,
+            },
+            Event::AlarmAcknowledged {
+                kind: AlarmKind::High,
+            },
+            Event::AlarmSilenced { secs: u32::MAX },
+            Event::AlarmThresholdsChanged {
+                low: temp,
+                high: temp,
+                delay: u32::MAX,
+            },
+        ]
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        for (i, event) in events().into_iter().enumerate() {
+            assert_eq!(event.code() as usize, i);
+            assert_eq!(
+                Event::from_payload(event.code(), &event.to_payload()),
+                event
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_order_events() {
+        // A queued event stamped before the one stored directly
+        assert_eq!(repeat_delta(100, 99), None);
+        assert_eq!(repeat_delta(100, 100), Some(0));
+        assert_eq!(repeat_delta(100, 100 + 65_535), Some(u16::MAX));
+        assert_eq!(repeat_delta(100, 100 + 65_536), None);
+        assert_eq!(repeat_delta(u32::MAX, 0), None);
+
+        let code = EventCode::TempSensorError;
+        assert!(code.is_rate_limited(100, 100));
+        assert!(code.is_rate_limited(100, 159));
+        assert!(!code.is_rate_limited(100, 160));
+        // The last record is newer than the current time
+        assert!(!code.is_rate_limited(100, 99));
+        assert!(!code.is_rate_limited(u32::MAX, 0));
+        assert!(!EventCode::Boot.is_rate_limited(100, 100));
+    }
+
+    #[test]
+    fn coalescing_leaves_room_for_repeats() {
+        for event in events() {
+            let payload = event.to_payload();
+            let unused = payload[PAYLOAD_SIZE - REPEAT_SIZE..]
+                .iter()
+                .all(|&b| b == 0);
+            assert_eq!(unused, event.code().coalesces(), "{event:?}");
+        }
+    }
+}
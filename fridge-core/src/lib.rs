@@ -0,0 +1,17 @@
+//! Platform independent parts of the fridge, shared between the firmware and the host tools.
+
+#![no_std]
+#![warn(clippy::pedantic, clippy::nursery)]
+#![allow(clippy::module_name_repetitions, clippy::must_use_candidate)]
+
+pub mod alarm;
+pub mod controller;
+pub mod delta;
+pub mod event;
+pub mod modbus;
+pub mod protocol;
+pub mod record;
+pub mod replay;
+pub mod stats;
+pub mod thermometer;
+pub mod time;
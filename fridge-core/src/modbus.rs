@@ -0,0 +1,464 @@
+//! Modbus RTU slave
+//!
+//! Decodes requests addressed to the slave & encodes the responses. The register map itself is
+//! provided through [`Registers`], splitting frames on the inter-frame gap is up to the caller.
+//!
+//! Supported functions:
+//! - `0x01` Read Coils
+//! - `0x03` Read Holding Registers
+//! - `0x04` Read Input Registers
+//! - `0x05` Write Single Coil
+//! - `0x06` Write Single Register
+//! - `0x10` Write Multiple Registers
+
+use crate::thermometer::Temperature;
+
+/// Maximum size of a request or response frame
+///
+/// Much smaller than the 256 bytes allowed by Modbus, which is plenty for the registers of the
+/// fridge.
+pub const MAX_ADU: usize = 40;
+/// Maximum number of registers read or written by a single request
+pub const MAX_REGISTERS: u16 = 16;
+/// Address of requests sent to all slaves, which are never answered
+pub const BROADCAST: u8 = 0;
+
+const READ_COILS: u8 = 0x01;
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_COIL: u8 = 0x05;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// Address, function code & CRC
+const ADU_OVERHEAD: usize = 4;
+/// Maximum number of bytes of data in a read response
+const MAX_DATA: usize = MAX_ADU - ADU_OVERHEAD - 1;
+
+/// Minimum silence between frames in microseconds, 3.5 characters
+pub const fn frame_gap_us(baud: u32) -> u32 {
+    // Fixed above 19200 baud, as timing that precisely is too much of a burden
+    if baud > 19_200 {
+        1_750
+    } else {
+        // 3.5 characters of 11 bits
+        38_500_000 / baud
+    }
+}
+
+/// Exception codes returned to the master
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Exception {
+    IllegalFunction = 1,
+    IllegalDataAddress,
+    IllegalDataValue,
+    SlaveDeviceFailure,
+}
+
+/// Register map of a slave
+///
+/// Unknown addresses are rejected with [`Exception::IllegalDataAddress`], values out of range with
+/// [`Exception::IllegalDataValue`].
+#[allow(clippy::missing_errors_doc)]
+pub trait Registers {
+    fn read_coil(&mut self, addr: u16) -> Result<bool, Exception>;
+    fn write_coil(&mut self, addr: u16, value: bool) -> Result<(), Exception>;
+    fn read_input(&mut self, addr: u16) -> Result<u16, Exception>;
+    fn read_holding(&mut self, addr: u16) -> Result<u16, Exception>;
+    /// Checks that `value` can be written to a holding register, without writing it
+    fn check_holding(&mut self, addr: u16, value: u16) -> Result<(), Exception>;
+    fn write_holding(&mut self, addr: u16, value: u16) -> Result<(), Exception>;
+}
+
+/// Calculates the CRC-16/MODBUS of the input data.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for byte in data {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 0 {
+                crc >> 1
+            } else {
+                (crc >> 1) ^ 0xA001
+            };
+        }
+    }
+    crc
+}
+
+/// Handles a request frame addressed to `unit`, writing the response into `response`
+///
+/// Returns the length of the response, or `None` if the request must not be answered: it is
+/// corrupt, addressed to another slave, or broadcast.
+pub fn handle<R: Registers>(
+    regs: &mut R,
+    unit: u8,
+    request: &[u8],
+    response: &mut [u8; MAX_ADU],
+) -> Option<usize> {
+    let (body, crc) = request.split_last_chunk::<2>()?;
+    let (&addr, pdu) = body.split_first()?;
+    let &function = pdu.first()?;
+    if u16::from_le_bytes(*crc) != crc16(body) || (addr != unit && addr != BROADCAST) {
+        return None;
+    }
+
+    let result = run(regs, pdu, &mut response[1..MAX_ADU - 2]);
+    if addr == BROADCAST {
+        return None;
+    }
+
+    response[0] = unit;
+    let len = match result {
+        Ok(len) => len + 1,
+        Err(e) => {
+            response[1] = function | 0x80;
+            response[2] = e as u8;
+            3
+        }
+    };
+    let crc = crc16(&response[..len]).to_le_bytes();
+    response[len..len + 2].copy_from_slice(&crc);
+
+    Some(len + 2)
+}
+
+/// Runs the request PDU, writing the response PDU into `out`
+fn run<R: Registers>(regs: &mut R, pdu: &[u8], out: &mut [u8]) -> Result<usize, Exception> {
+    let (&function, data) = pdu.split_first().ok_or(Exception::IllegalFunction)?;
+    let word = |idx: usize| {
+        data.get(idx..idx + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or(Exception::IllegalDataValue)
+    };
+    out[0] = function;
+
+    match function {
+        READ_COILS => {
+            let (addr, count) = (word(0)?, word(2)?);
+            let bytes = usize::from(count).div_ceil(8);
+            if count == 0 || bytes > MAX_DATA {
+                return Err(Exception::IllegalDataValue);
+            }
+
+            out[2..2 + bytes].fill(0);
+            for i in 0..count {
+                let addr = addr.checked_add(i).ok_or(Exception::IllegalDataAddress)?;
+                if regs.read_coil(addr)? {
+                    out[2 + usize::from(i / 8)] |= 1 << (i % 8);
+                }
+            }
+            // Fits in MAX_DATA
+            #[allow(clippy::cast_possible_truncation)]
+            let len = bytes as u8;
+            out[1] = len;
+            Ok(2 + bytes)
+        }
+        READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+            let (addr, count) = (word(0)?, word(2)?);
+            if count == 0 || count > MAX_REGISTERS {
+                return Err(Exception::IllegalDataValue);
+            }
+
+            for i in 0..count {
+                let addr = addr.checked_add(i).ok_or(Exception::IllegalDataAddress)?;
+                let value = if function == READ_HOLDING_REGISTERS {
+                    regs.read_holding(addr)?
+                } else {
+                    regs.read_input(addr)?
+                };
+                let idx = 2 + usize::from(i) * 2;
+                out[idx..idx + 2].copy_from_slice(&value.to_be_bytes());
+            }
+            // At most 2 * MAX_REGISTERS
+            #[allow(clippy::cast_possible_truncation)]
+            let len = (count * 2) as u8;
+            out[1] = len;
+            Ok(2 + usize::from(count) * 2)
+        }
+        WRITE_SINGLE_COIL => {
+            let value = match word(2)? {
+                0xFF00 => true,
+                0x0000 => false,
+                _ => return Err(Exception::IllegalDataValue),
+            };
+            regs.write_coil(word(0)?, value)?;
+            // Echo the request
+            out[1..5].copy_from_slice(&data[..4]);
+            Ok(5)
+        }
+        WRITE_SINGLE_REGISTER => {
+            regs.write_holding(word(0)?, word(2)?)?;
+            // Echo the request
+            out[1..5].copy_from_slice(&data[..4]);
+            Ok(5)
+        }
+        WRITE_MULTIPLE_REGISTERS => {
+            let (addr, count) = (word(0)?, word(2)?);
+            let bytes = data.get(4).copied().ok_or(Exception::IllegalDataValue)?;
+            if count == 0
+                || count > MAX_REGISTERS
+                || usize::from(bytes) != usize::from(count) * 2
+                || data.len() != 5 + usize::from(bytes)
+            {
+                return Err(Exception::IllegalDataValue);
+            }
+
+            // Check every address & value before writing anything
+            for i in 0..count {
+                let addr = addr.checked_add(i).ok_or(Exception::IllegalDataAddress)?;
+                regs.check_holding(addr, word(5 + usize::from(i) * 2)?)?;
+            }
+            for i in 0..count {
+                regs.write_holding(addr + i, word(5 + usize::from(i) * 2)?)?;
+            }
+
+            out[1..5].copy_from_slice(&data[..4]);
+            Ok(5)
+        }
+        _ => Err(Exception::IllegalFunction),
+    }
+}
+
+/// Encodes a temperature as a register in hundredths of a degree
+#[allow(clippy::cast_possible_truncation)]
+pub const fn temp_to_register(temp: Temperature) -> u16 {
+    const ONE: i32 = 1 << Temperature::FRAC_NBITS;
+
+    // Round to the nearest hundredth & saturate to the range of an i16
+    let centi = (temp.to_bits() as i32 * 100 + ONE / 2).div_euclid(ONE);
+    let centi = if centi > i16::MAX as i32 {
+        i16::MAX
+    } else if centi < i16::MIN as i32 {
+        i16::MIN
+    } else {
+        centi as i16
+    };
+    centi.cast_unsigned()
+}
+
+/// Decodes a temperature from a register in hundredths of a degree
+#[allow(clippy::cast_possible_truncation)]
+pub const fn register_to_temp(reg: u16) -> Temperature {
+    const ONE: i32 = 1 << Temperature::FRAC_NBITS;
+
+    let centi = reg.cast_signed() as i32;
+    // Always fits in an i16, as the bits are only 16/100 of the register
+    let bits = (centi * ONE + 50).div_euclid(100);
+    Temperature::from_bits(bits as i16)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    const UNIT: u8 = 7;
+
+    /// Coils 0 to 2, input registers 0 to 2 & holding registers 0 to 3, the last one only taking
+    /// values up to 2
+    #[derive(Default)]
+    struct Regs {
+        coils: [bool; 3],
+        holding: [u16; 4],
+    }
+
+    impl Registers for Regs {
+        fn read_coil(&mut self, addr: u16) -> Result<bool, Exception> {
+            self.coils
+                .get(usize::from(addr))
+                .copied()
+                .ok_or(Exception::IllegalDataAddress)
+        }
+
+        fn write_coil(&mut self, addr: u16, value: bool) -> Result<(), Exception> {
+            let coil = self
+                .coils
+                .get_mut(usize::from(addr))
+                .ok_or(Exception::IllegalDataAddress)?;
+            *coil = value;
+            Ok(())
+        }
+
+        fn read_input(&mut self, addr: u16) -> Result<u16, Exception> {
+            match addr {
+                0..=2 => Ok(0x100 + addr),
+                _ => Err(Exception::IllegalDataAddress),
+            }
+        }
+
+        fn read_holding(&mut self, addr: u16) -> Result<u16, Exception> {
+            self.holding
+                .get(usize::from(addr))
+                .copied()
+                .ok_or(Exception::IllegalDataAddress)
+        }
+
+        fn check_holding(&mut self, addr: u16, value: u16) -> Result<(), Exception> {
+            match addr {
+                0..=2 => Ok(()),
+                3 if value <= 2 => Ok(()),
+                3 => Err(Exception::IllegalDataValue),
+                _ => Err(Exception::IllegalDataAddress),
+            }
+        }
+
+        fn write_holding(&mut self, addr: u16, value: u16) -> Result<(), Exception> {
+            self.check_holding(addr, value)?;
+            self.holding[usize::from(addr)] = value;
+            Ok(())
+        }
+    }
+
+    /// Frames a request PDU to `unit`
+    fn frame(unit: u8, pdu: &[u8]) -> Vec<u8> {
+        let mut frame = std::vec![unit];
+        frame.extend_from_slice(pdu);
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes());
+        frame
+    }
+
+    /// Handles a request frame, returning the response frame
+    fn handle_frame(regs: &mut Regs, frame: &[u8]) -> Option<Vec<u8>> {
+        let mut response = [0; MAX_ADU];
+        let len = handle(regs, UNIT, frame, &mut response)?;
+        Some(response[..len].to_vec())
+    }
+
+    /// Handles a request PDU, returning the response PDU after checking its framing
+    fn request(regs: &mut Regs, pdu: &[u8]) -> Vec<u8> {
+        let response = handle_frame(regs, &frame(UNIT, pdu)).unwrap();
+        let (body, crc) = response.split_last_chunk::<2>().unwrap();
+        assert_eq!(u16::from_le_bytes(*crc), crc16(body));
+        assert_eq!(body[0], UNIT);
+        body[1..].to_vec()
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(b"123456789"), 0x4B37);
+        // Read 10 holding registers from slave 1
+        let frame = frame(1, &[0x03, 0x00, 0x00, 0x00, 0x0A]);
+        assert_eq!(frame[6..], [0xC5, 0xCD]);
+    }
+
+    #[test]
+    fn read_coils() {
+        let mut regs = Regs {
+            coils: [true, false, true],
+            ..Regs::default()
+        };
+        assert_eq!(request(&mut regs, &[0x01, 0, 0, 0, 3]), [0x01, 1, 0b101]);
+        assert_eq!(request(&mut regs, &[0x01, 0, 1, 0, 2]), [0x01, 1, 0b10]);
+    }
+
+    #[test]
+    fn read_registers() {
+        let mut regs = Regs {
+            holding: [1, 0x1234, 0xFFFF, 2],
+            ..Regs::default()
+        };
+        assert_eq!(
+            request(&mut regs, &[0x03, 0, 1, 0, 2]),
+            [0x03, 4, 0x12, 0x34, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            request(&mut regs, &[0x04, 0, 0, 0, 3]),
+            [0x04, 6, 0x01, 0x00, 0x01, 0x01, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn write_single() {
+        let mut regs = Regs::default();
+        let pdu = [0x05, 0, 2, 0xFF, 0x00];
+        assert_eq!(request(&mut regs, &pdu), pdu);
+        assert_eq!(regs.coils, [false, false, true]);
+        let pdu = [0x05, 0, 2, 0x00, 0x00];
+        assert_eq!(request(&mut regs, &pdu), pdu);
+        assert_eq!(regs.coils, [false; 3]);
+
+        let pdu = [0x06, 0, 1, 0xAB, 0xCD];
+        assert_eq!(request(&mut regs, &pdu), pdu);
+        assert_eq!(regs.holding, [0, 0xABCD, 0, 0]);
+    }
+
+    #[test]
+    fn write_multiple() {
+        let mut regs = Regs::default();
+        let pdu = [0x10, 0, 1, 0, 3, 6, 0x12, 0x34, 0x56, 0x78, 0x00, 0x02];
+        assert_eq!(request(&mut regs, &pdu), pdu[..5]);
+        assert_eq!(regs.holding, [0, 0x1234, 0x5678, 2]);
+    }
+
+    #[test]
+    fn write_multiple_checks_every_value_first() {
+        let mut regs = Regs::default();
+        // The second value is out of range, so the first isn't written either
+        let pdu = [0x10, 0, 2, 0, 2, 4, 0x00, 0x07, 0x00, 0x03];
+        assert_eq!(request(&mut regs, &pdu), [0x90, 3]);
+        assert_eq!(regs.holding, [0; 4]);
+        // As for addresses
+        let pdu = [0x10, 0, 3, 0, 2, 4, 0x00, 0x01, 0x00, 0x01];
+        assert_eq!(request(&mut regs, &pdu), [0x90, 2]);
+        assert_eq!(regs.holding, [0; 4]);
+    }
+
+    #[test]
+    fn illegal_function() {
+        let mut regs = Regs::default();
+        assert_eq!(request(&mut regs, &[0x2B, 0x0E, 1, 0]), [0xAB, 1]);
+        assert_eq!(request(&mut regs, &[0x02, 0, 0, 0, 1]), [0x82, 1]);
+    }
+
+    #[test]
+    fn illegal_data_address() {
+        let mut regs = Regs::default();
+        assert_eq!(request(&mut regs, &[0x01, 0, 3, 0, 1]), [0x81, 2]);
+        // Reading past the last register
+        assert_eq!(request(&mut regs, &[0x03, 0, 2, 0, 3]), [0x83, 2]);
+        assert_eq!(request(&mut regs, &[0x04, 0xFF, 0xFF, 0, 2]), [0x84, 2]);
+        assert_eq!(request(&mut regs, &[0x05, 0, 3, 0xFF, 0]), [0x85, 2]);
+        assert_eq!(request(&mut regs, &[0x06, 0, 4, 0, 0]), [0x86, 2]);
+    }
+
+    #[test]
+    fn illegal_data_value() {
+        let mut regs = Regs::default();
+        assert_eq!(request(&mut regs, &[0x01, 0, 0, 0, 0]), [0x81, 3]);
+        assert_eq!(request(&mut regs, &[0x03, 0, 0, 0, 0]), [0x83, 3]);
+        assert_eq!(request(&mut regs, &[0x04, 0, 0, 0, 17]), [0x84, 3]);
+        assert_eq!(request(&mut regs, &[0x05, 0, 0, 0x12, 0x34]), [0x85, 3]);
+        assert_eq!(request(&mut regs, &[0x06, 0, 3, 0, 3]), [0x86, 3]);
+        // Truncated request
+        assert_eq!(request(&mut regs, &[0x03, 0, 0]), [0x83, 3]);
+        // Byte count not matching the number of registers
+        assert_eq!(
+            request(&mut regs, &[0x10, 0, 0, 0, 1, 4, 0, 1, 0, 1]),
+            [0x90, 3]
+        );
+        assert_eq!(regs.holding, [0; 4]);
+    }
+
+    #[test]
+    fn unanswered() {
+        let mut regs = Regs::default();
+        let pdu = [0x06, 0, 0, 0, 5];
+
+        let mut corrupt = frame(UNIT, &pdu);
+        corrupt[3] ^= 1;
+        assert_eq!(handle_frame(&mut regs, &corrupt), None);
+        assert_eq!(handle_frame(&mut regs, &frame(UNIT + 1, &pdu)), None);
+        assert_eq!(handle_frame(&mut regs, &[UNIT]), None);
+        assert_eq!(regs.holding, [0; 4]);
+
+        // Broadcasts are run, but not answered
+        assert_eq!(handle_frame(&mut regs, &frame(BROADCAST, &pdu)), None);
+        assert_eq!(regs.holding, [5, 0, 0, 0]);
+    }
+}
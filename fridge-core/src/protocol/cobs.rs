@@ -0,0 +1,81 @@
+//! Consistent Overhead Byte Stuffing
+//!
+//! Encodes data so that it doesn't contain any zero bytes, which leaves zero free to be used as a
+//! frame delimiter.
+
+/// Maximum length of `len` bytes once encoded
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// Encodes `src` into `dst`, returning the length of the encoded data.
+///
+/// Returns `None` if `dst` is too small, see [`max_encoded_len`].
+pub fn encode(src: &[u8], dst: &mut [u8]) -> Option<usize> {
+    // Index of the code byte of the current block
+    let mut code_idx = 0;
+    let mut code = 1u8;
+    let mut len = 1;
+
+    *dst.get_mut(code_idx)? = 0;
+
+    for &b in src {
+        if b != 0 {
+            *dst.get_mut(len)? = b;
+            len += 1;
+            code += 1;
+        }
+
+        if b == 0 || code == 0xFF {
+            // Finish the block & start a new one
+            dst[code_idx] = code;
+            code_idx = len;
+            code = 1;
+            *dst.get_mut(len)? = 0;
+            len += 1;
+        }
+    }
+
+    dst[code_idx] = code;
+    Some(len)
+}
+
+/// Decodes `buf` in place, returning the length of the decoded data.
+///
+/// Returns `None` if `buf` is not valid COBS data.
+pub fn decode_in_place(buf: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+
+    while read < buf.len() {
+        let code = buf[read];
+        if code == 0 {
+            return None;
+        }
+        read += 1;
+
+        let end = read + usize::from(code) - 1;
+        if end > buf.len() {
+            return None;
+        }
+
+        // The decoded data is never longer than the encoded data, so writing never overtakes
+        // reading.
+        while read < end {
+            if buf[read] == 0 {
+                return None;
+            }
+            buf[write] = buf[read];
+            write += 1;
+            read += 1;
+        }
+
+        // Every block but the last & full blocks is followed by a zero
+        if code != 0xFF && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+
+    Some(write)
+}
@@ -0,0 +1,529 @@
+//! Messages of the binary host protocol
+//!
+//! The first byte of a payload is the message type, followed by its fields in little endian.
+//! Temperatures are sent as the raw bits of a [`Temperature`].
+
+use heapless::Vec;
+
+use super::{decode_frame, encode_frame, Error, MAX_FRAME, MAX_PAYLOAD};
+use crate::{
+    controller::{pid::PidGains, CoolerMode},
+    event::{Event, EventCode},
+    record::{PreciseTemp, StoredAggregate, StoredTemp, Tier},
+    thermometer::Temperature,
+};
+
+/// Maximum number of temperature records in a single [`Response::Temps`]
+pub const TEMPS_PER_FRAME: usize = 8;
+/// Maximum number of temperature records in a single [`Response::PreciseTemps`]
+pub const PRECISE_TEMPS_PER_FRAME: usize = 7;
+/// Maximum number of aggregate records in a single [`Response::Aggregates`]
+pub const AGGREGATES_PER_FRAME: usize = 6;
+
+// Request types
+const GET_TEMP: u8 = 0x01;
+const GET_TEMPS: u8 = 0x02;
+const GET_EVENTS: u8 = 0x03;
+const GET_TARGET: u8 = 0x04;
+const SET_TARGET: u8 = 0x05;
+const GET_GAINS: u8 = 0x06;
+const SET_GAINS: u8 = 0x07;
+const GET_RESOLUTION: u8 = 0x08;
+const SET_RESOLUTION: u8 = 0x09;
+const GET_COOLER: u8 = 0x0A;
+const SET_COOLER: u8 = 0x0B;
+const GET_AGGREGATES: u8 = 0x0C;
+const GET_TIME: u8 = 0x0D;
+const SET_TIME: u8 = 0x0E;
+
+// Response types
+const OK: u8 = 0x80;
+const ERROR: u8 = 0x81;
+const TEMP: u8 = 0x82;
+const TEMPS: u8 = 0x83;
+const EVENT: u8 = 0x84;
+const END: u8 = 0x85;
+const TARGET: u8 = 0x86;
+const GAINS: u8 = 0x87;
+const RESOLUTION: u8 = 0x88;
+const COOLER: u8 = 0x89;
+const AGGREGATES: u8 = 0x8A;
+const PRECISE_TEMPS: u8 = 0x8B;
+const TIME: u8 = 0x8C;
+
+/// Request sent by the host
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// Get the most recent temperature, answered with [`Response::Temp`]
+    GetTemp,
+    /// Get all stored temperatures, answered with [`Response::Temps`] or
+    /// [`Response::PreciseTemps`], depending on the format the fridge stores, until
+    /// [`Response::End`]
+    GetTemps,
+    /// Get all stored events, answered with [`Response::Event`] until [`Response::End`]
+    GetEvents,
+    /// Answered with [`Response::Target`]
+    GetTarget,
+    SetTarget(Temperature),
+    /// Answered with [`Response::Gains`]
+    GetGains,
+    SetGains(PidGains),
+    /// Answered with [`Response::Resolution`]
+    GetResolution,
+    /// Set the thermometer resolution in bits, 9 to 12
+    SetResolution(u8),
+    /// Answered with [`Response::Cooler`]
+    GetCooler,
+    SetCooler(CoolerMode),
+    /// Get all stored aggregates of a tier, answered with [`Response::Aggregates`] until
+    /// [`Response::End`]
+    GetAggregates(Tier),
+    /// Answered with [`Response::Time`]
+    GetTime,
+    /// Set the real-time clock to a Unix time, e.g. to sync it with the host
+    SetTime(u32),
+}
+
+/// Response sent by the fridge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// A set request succeeded
+    Ok,
+    /// The request failed
+    Error(ErrorCode),
+    /// A temperature measured at `secs` since startup
+    Temp {
+        secs: u32,
+        temp: Temperature,
+    },
+    /// Part of a stream of stored temperatures, oldest first
+    ///
+    /// Records keep only the low 24 bits of their timestamps, restored by
+    /// [`crate::record::extend_secs`] with the `secs` of [`Response::Time`].
+    Temps(Vec<StoredTemp, TEMPS_PER_FRAME>),
+    /// Part of a stream of stored events, oldest first
+    ///
+    /// Sent as the seconds of the first & last occurrences, the number of occurrences, & the
+    /// code & the payload of [`crate::event`].
+    Event {
+        secs: u32,
+        /// Seconds of the last occurrence, `secs` unless the event repeated
+        last: u32,
+        /// Number of occurrences, saturating
+        count: u8,
+        event: Event,
+    },
+    /// Ends a stream of `count` records
+    End {
+        count: u16,
+    },
+    Target(Temperature),
+    Gains(PidGains),
+    /// Thermometer resolution in bits
+    Resolution(u8),
+    Cooler {
+        mode: CoolerMode,
+        on: bool,
+    },
+    /// Part of a stream of stored aggregates, oldest first
+    Aggregates(Vec<StoredAggregate, AGGREGATES_PER_FRAME>),
+    /// Part of a stream of stored temperatures in the precise format, oldest first
+    PreciseTemps(Vec<PreciseTemp, PRECISE_TEMPS_PER_FRAME>),
+    /// Seconds since startup & the Unix time at the same moment, `None` if the clock isn't set
+    ///
+    /// Records are timestamped in seconds since startup, `unix - secs` converts them to Unix
+    /// times. Sent as 0 if the clock isn't set.
+    Time {
+        secs: u32,
+        unix: Option<u32>,
+    },
+}
+
+/// Error codes sent in [`Response::Error`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ErrorCode {
+    /// The request frame couldn't be decoded
+    BadFrame = 1,
+    /// The request type isn't known
+    UnknownRequest,
+    /// A field of the request has an invalid value
+    InvalidValue,
+    /// The requested data isn't available (yet)
+    Missing,
+}
+
+impl ErrorCode {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::BadFrame => "bad frame",
+            Self::UnknownRequest => "unknown request",
+            Self::InvalidValue => "invalid value",
+            Self::Missing => "missing",
+        }
+    }
+
+    const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::BadFrame),
+            2 => Some(Self::UnknownRequest),
+            3 => Some(Self::InvalidValue),
+            4 => Some(Self::Missing),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for ErrorCode {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Cobs | Error::Crc | Error::Length => Self::BadFrame,
+            Error::UnknownType(_) => Self::UnknownRequest,
+            Error::InvalidValue => Self::InvalidValue,
+        }
+    }
+}
+
+impl Request {
+    /// Encodes the request, returning the length of the payload
+    pub fn encode(&self, buf: &mut [u8; MAX_PAYLOAD]) -> usize {
+        let mut w = Writer::new(buf);
+        match *self {
+            Self::GetTemp => w.u8(GET_TEMP),
+            Self::GetTemps => w.u8(GET_TEMPS),
+            Self::GetEvents => w.u8(GET_EVENTS),
+            Self::GetTarget => w.u8(GET_TARGET),
+            Self::SetTarget(target) => {
+                w.u8(SET_TARGET);
+                w.temp(target);
+            }
+            Self::GetGains => w.u8(GET_GAINS),
+            Self::SetGains(gains) => {
+                w.u8(SET_GAINS);
+                w.gains(gains);
+            }
+            Self::GetResolution => w.u8(GET_RESOLUTION),
+            Self::SetResolution(bits) => {
+                w.u8(SET_RESOLUTION);
+                w.u8(bits);
+            }
+            Self::GetCooler => w.u8(GET_COOLER),
+            Self::SetCooler(mode) => {
+                w.u8(SET_COOLER);
+                w.u8(mode as u8);
+            }
+            Self::GetAggregates(tier) => {
+                w.u8(GET_AGGREGATES);
+                w.u8(tier as u8);
+            }
+            Self::GetTime => w.u8(GET_TIME),
+            Self::SetTime(unix) => {
+                w.u8(SET_TIME);
+                w.u32(unix);
+            }
+        }
+        w.len
+    }
+
+    /// Decodes a request from its payload
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload isn't a valid request.
+    pub fn decode(payload: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(payload);
+        let request = match r.u8()? {
+            GET_TEMP => Self::GetTemp,
+            GET_TEMPS => Self::GetTemps,
+            GET_EVENTS => Self::GetEvents,
+            GET_TARGET => Self::GetTarget,
+            SET_TARGET => Self::SetTarget(r.temp()?),
+            GET_GAINS => Self::GetGains,
+            SET_GAINS => Self::SetGains(r.gains()?),
+            GET_RESOLUTION => Self::GetResolution,
+            SET_RESOLUTION => Self::SetResolution(r.u8()?),
+            GET_COOLER => Self::GetCooler,
+            SET_COOLER => Self::SetCooler(CoolerMode::from_u8(r.u8()?).ok_or(Error::InvalidValue)?),
+            GET_AGGREGATES => {
+                Self::GetAggregates(Tier::from_u8(r.u8()?).ok_or(Error::InvalidValue)?)
+            }
+            GET_TIME => Self::GetTime,
+            SET_TIME => Self::SetTime(r.u32()?),
+            t => return Err(Error::UnknownType(t)),
+        };
+        r.finish()?;
+        Ok(request)
+    }
+
+    /// Encodes the request into a frame, returning the length of the frame
+    pub fn to_frame(&self, frame: &mut [u8; MAX_FRAME]) -> usize {
+        let mut buf = [0; MAX_PAYLOAD];
+        let len = self.encode(&mut buf);
+        encode_frame(&buf[..len], frame)
+    }
+
+    /// Decodes a request from a frame without delimiters, in place
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame is corrupt or isn't a valid request.
+    pub fn from_frame(frame: &mut [u8]) -> Result<Self, Error> {
+        Self::decode(decode_frame(frame)?)
+    }
+}
+
+impl Response {
+    /// Encodes the response, returning the length of the payload
+    pub fn encode(&self, buf: &mut [u8; MAX_PAYLOAD]) -> usize {
+        let mut w = Writer::new(buf);
+        match self {
+            Self::Ok => w.u8(OK),
+            Self::Error(code) => {
+                w.u8(ERROR);
+                w.u8(*code as u8);
+            }
+            Self::Temp { secs, temp } => {
+                w.u8(TEMP);
+                w.u32(*secs);
+                w.temp(*temp);
+            }
+            Self::Temps(temps) => {
+                w.u8(TEMPS);
+                for temp in temps {
+                    w.bytes(&temp.to_bytes());
+                }
+            }
+            Self::Event {
+                secs,
+                last,
+                count,
+                event,
+            } => {
+                w.u8(EVENT);
+                w.u32(*secs);
+                w.u32(*last);
+                w.u8(*count);
+                w.u8(event.code() as u8);
+                w.bytes(&event.to_payload());
+            }
+            Self::End { count } => {
+                w.u8(END);
+                w.bytes(&count.to_le_bytes());
+            }
+            Self::Target(target) => {
+                w.u8(TARGET);
+                w.temp(*target);
+            }
+            Self::Gains(gains) => {
+                w.u8(GAINS);
+                w.gains(*gains);
+            }
+            Self::Resolution(bits) => {
+                w.u8(RESOLUTION);
+                w.u8(*bits);
+            }
+            Self::Cooler { mode, on } => {
+                w.u8(COOLER);
+                w.u8(*mode as u8);
+                w.u8(u8::from(*on));
+            }
+            Self::Aggregates(aggregates) => {
+                w.u8(AGGREGATES);
+                for aggregate in aggregates {
+                    w.bytes(&aggregate.to_bytes());
+                }
+            }
+            Self::PreciseTemps(temps) => {
+                w.u8(PRECISE_TEMPS);
+                for temp in temps {
+                    w.bytes(&temp.to_bytes());
+                }
+            }
+            Self::Time { secs, unix } => {
+                w.u8(TIME);
+                w.u32(*secs);
+                w.u32(unix.unwrap_or(0));
+            }
+        }
+        w.len
+    }
+
+    /// Decodes a response from its payload
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload isn't a valid response.
+    pub fn decode(payload: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(payload);
+        let response = match r.u8()? {
+            OK => Self::Ok,
+            ERROR => Self::Error(ErrorCode::from_u8(r.u8()?).ok_or(Error::InvalidValue)?),
+            TEMP => Self::Temp {
+                secs: r.u32()?,
+                temp: r.temp()?,
+            },
+            TEMPS => {
+                let mut temps = Vec::new();
+                while let Some(bytes) = r.array() {
+                    temps
+                        .push(StoredTemp::from_bytes(bytes))
+                        .map_err(|_| Error::Length)?;
+                }
+                Self::Temps(temps)
+            }
+            EVENT => Self::Event {
+                secs: r.u32()?,
+                last: r.u32()?,
+                count: r.u8()?,
+                event: Event::from_payload(
+                    EventCode::from_u8(r.u8()?),
+                    &r.array().ok_or(Error::Length)?,
+                ),
+            },
+            END => Self::End {
+                count: u16::from_le_bytes(r.array().ok_or(Error::Length)?),
+            },
+            TARGET => Self::Target(r.temp()?),
+            GAINS => Self::Gains(r.gains()?),
+            RESOLUTION => Self::Resolution(r.u8()?),
+            COOLER => Self::Cooler {
+                mode: CoolerMode::from_u8(r.u8()?).ok_or(Error::InvalidValue)?,
+                on: r.u8()? != 0,
+            },
+            AGGREGATES => {
+                let mut aggregates = Vec::new();
+                while let Some(bytes) = r.array() {
+                    aggregates
+                        .push(StoredAggregate::from_bytes(bytes))
+                        .map_err(|_| Error::Length)?;
+                }
+                Self::Aggregates(aggregates)
+            }
+            PRECISE_TEMPS => {
+                let mut temps = Vec::new();
+                while let Some(bytes) = r.array() {
+                    temps
+                        .push(PreciseTemp::from_bytes(bytes))
+                        .map_err(|_| Error::Length)?;
+                }
+                Self::PreciseTemps(temps)
+            }
+            TIME => Self::Time {
+                secs: r.u32()?,
+                unix: Some(r.u32()?).filter(|unix| *unix != 0),
+            },
+            t => return Err(Error::UnknownType(t)),
+        };
+        r.finish()?;
+        Ok(response)
+    }
+
+    /// Encodes the response into a frame, returning the length of the frame
+    pub fn to_frame(&self, frame: &mut [u8; MAX_FRAME]) -> usize {
+        let mut buf = [0; MAX_PAYLOAD];
+        let len = self.encode(&mut buf);
+        encode_frame(&buf[..len], frame)
+    }
+
+    /// Decodes a response from a frame without delimiters, in place
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame is corrupt or isn't a valid response.
+    pub fn from_frame(frame: &mut [u8]) -> Result<Self, Error> {
+        Self::decode(decode_frame(frame)?)
+    }
+
+    /// Whether this is the last response to a request
+    pub const fn is_last(&self) -> bool {
+        !matches!(
+            self,
+            Self::Temps(_) | Self::PreciseTemps(_) | Self::Aggregates(_) | Self::Event { .. }
+        )
+    }
+}
+
+/// Writes fields into a payload
+///
+/// Every message fits in [`MAX_PAYLOAD`], so writes don't need to be checked.
+struct Writer<'a> {
+    buf: &'a mut [u8; MAX_PAYLOAD],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    const fn new(buf: &'a mut [u8; MAX_PAYLOAD]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn temp(&mut self, value: Temperature) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn gains(&mut self, gains: PidGains) {
+        self.temp(gains.kp);
+        self.temp(gains.ki);
+        self.temp(gains.kd);
+    }
+}
+
+/// Reads fields from a payload
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    const fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.buf.split_first_chunk()?;
+        self.buf = rest;
+        Some(*bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        self.array().map(|[b]| b).ok_or(Error::Length)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        self.array().map(u32::from_le_bytes).ok_or(Error::Length)
+    }
+
+    fn temp(&mut self) -> Result<Temperature, Error> {
+        self.array()
+            .map(Temperature::from_le_bytes)
+            .ok_or(Error::Length)
+    }
+
+    fn gains(&mut self) -> Result<PidGains, Error> {
+        Ok(PidGains {
+            kp: self.temp()?,
+            ki: self.temp()?,
+            kd: self.temp()?,
+        })
+    }
+
+    /// Checks that the whole payload was read
+    const fn finish(&self) -> Result<(), Error> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Length)
+        }
+    }
+}
@@ -0,0 +1,510 @@
+//! Binary host protocol
+//!
+//! Messages are sent as frames next to the text console on the same serial port. A frame is the
+//! message payload followed by its CRC-16 (little endian), COBS encoded so it contains no zero
+//! bytes, and surrounded by zero delimiters:
+//!
+//! ```text
+//! 00 | COBS(payload | crc16) | 00
+//! ```
+//!
+//! Text never contains a zero byte, so the leading delimiter switches the receiver from the text
+//! console to the binary protocol until the frame ends. Every frame must start with its own
+//! delimiter, even when frames are sent back to back. A frame that grows too long or pauses for
+//! [`FRAME_TIMEOUT_MILLIS`] is dropped & the receiver is back at the console, so a stray zero
+//! byte can't swallow what is typed next.
+//!
+//! The host sends a [`Request`], the fridge answers with one or more [`Response`]s. Requests for
+//! stored data are answered with a stream of records, ended by [`Response::End`].
+
+pub mod cobs;
+mod crc;
+mod message;
+
+use heapless::Vec;
+
+pub use self::{
+    crc::crc16,
+    message::{
+        ErrorCode, Request, Response, AGGREGATES_PER_FRAME, PRECISE_TEMPS_PER_FRAME,
+        TEMPS_PER_FRAME,
+    },
+};
+
+/// Frame delimiter
+pub const DELIMITER: u8 = 0;
+/// Maximum size of a message payload
+pub const MAX_PAYLOAD: usize = 40;
+/// Size of the CRC following the payload
+const CRC_SIZE: usize = 2;
+/// Maximum size of an encoded frame, without delimiters
+pub const MAX_ENCODED: usize = cobs::max_encoded_len(MAX_PAYLOAD + CRC_SIZE);
+/// Maximum size of a frame, including both delimiters
+pub const MAX_FRAME: usize = MAX_ENCODED + 2;
+/// Milliseconds between the bytes of a frame after which it is dropped
+///
+/// Hosts send a frame at once, so a pause means the delimiter was a stray byte rather than the
+/// start of a frame. Frames can't be ended at a line break instead, as COBS data may contain any
+/// byte but zero.
+pub const FRAME_TIMEOUT_MILLIS: u32 = 100;
+
+/// Error decoding a frame or message
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The frame isn't valid COBS data
+    Cobs,
+    /// The frame is too short or the CRC doesn't match
+    Crc,
+    /// Unknown message type
+    UnknownType(u8),
+    /// The message is too short or too long for its type
+    Length,
+    /// A field of the message has an invalid value
+    InvalidValue,
+}
+
+impl Error {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Cobs => "invalid COBS data",
+            Self::Crc => "CRC mismatch",
+            Self::UnknownType(_) => "unknown message type",
+            Self::Length => "invalid message length",
+            Self::InvalidValue => "invalid value",
+        }
+    }
+}
+
+/// Encodes a payload into a frame, including the CRC & both delimiters
+///
+/// Returns the length of the frame.
+pub fn encode_frame(payload: &[u8], frame: &mut [u8; MAX_FRAME]) -> usize {
+    let mut buf = [0u8; MAX_PAYLOAD + CRC_SIZE];
+    let len = payload.len();
+    buf[..len].copy_from_slice(payload);
+    buf[len..len + CRC_SIZE].copy_from_slice(&crc16(payload).to_le_bytes());
+
+    frame[0] = DELIMITER;
+    // The frame is sized for the largest payload, so encoding can't fail
+    let encoded = cobs::encode(&buf[..len + CRC_SIZE], &mut frame[1..]).unwrap_or_default();
+    frame[encoded + 1] = DELIMITER;
+
+    encoded + 2
+}
+
+/// Decodes a frame without its delimiters in place, returning the payload
+///
+/// # Errors
+///
+/// Returns an error if the frame isn't valid COBS data or the CRC doesn't match.
+pub fn decode_frame(frame: &mut [u8]) -> Result<&[u8], Error> {
+    let len = cobs::decode_in_place(frame).ok_or(Error::Cobs)?;
+    if len < CRC_SIZE {
+        return Err(Error::Crc);
+    }
+
+    let (payload, crc) = frame[..len].split_at(len - CRC_SIZE);
+    if crc16(payload).to_le_bytes() != crc {
+        return Err(Error::Crc);
+    }
+
+    Ok(payload)
+}
+
+/// Result of feeding a byte to a [`FrameReceiver`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Feed {
+    /// The byte isn't part of a frame
+    Text,
+    /// The byte was consumed as part of a frame
+    Consumed,
+    /// A frame was completed, see [`FrameReceiver::frame`]
+    Frame,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// Outside of a frame
+    Idle,
+    /// Received the leading delimiter
+    Receiving,
+}
+
+/// Splits frames from a byte stream shared with text
+pub struct FrameReceiver {
+    buf: Vec<u8, MAX_ENCODED>,
+    state: State,
+    /// Milliseconds of the last received byte
+    last: u32,
+}
+
+impl FrameReceiver {
+    pub const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            state: State::Idle,
+            last: 0,
+        }
+    }
+
+    /// Feeds a byte received at `millis` to the receiver
+    ///
+    /// A frame longer than [`MAX_ENCODED`] or pausing for more than [`FRAME_TIMEOUT_MILLIS`] is
+    /// dropped, the byte & the following ones are text again.
+    pub fn feed(&mut self, b: u8, millis: u32) -> Feed {
+        if self.state == State::Receiving && millis.wrapping_sub(self.last) > FRAME_TIMEOUT_MILLIS {
+            self.state = State::Idle;
+        }
+        self.last = millis;
+
+        match (self.state, b) {
+            (State::Idle, DELIMITER) => {
+                self.buf.clear();
+                self.state = State::Receiving;
+                Feed::Consumed
+            }
+            (State::Idle, _) => Feed::Text,
+            // Repeated delimiters between frames are ignored
+            (State::Receiving, DELIMITER) if self.buf.is_empty() => Feed::Consumed,
+            (State::Receiving, DELIMITER) => {
+                self.state = State::Idle;
+                Feed::Frame
+            }
+            (State::Receiving, _) => {
+                if self.buf.push(b).is_err() {
+                    self.state = State::Idle;
+                    return Feed::Text;
+                }
+                Feed::Consumed
+            }
+        }
+    }
+
+    /// The last completed frame, without delimiters
+    pub const fn frame(&self) -> &Vec<u8, MAX_ENCODED> {
+        &self.buf
+    }
+}
+
+impl Default for FrameReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use fixed::types::I6F2;
+
+    use super::*;
+    use crate::{
+        controller::{pid::PidGains, CoolerMode},
+        event::{Event, ResetCause, SensorError, PANIC_FILE_SIZE},
+        record::{PreciseTemp, StoredAggregate, StoredTemp, Tier},
+        thermometer::Temperature,
+    };
+
+    /// Feeds bytes received at `millis`, returning the text & frames split from them
+    fn feed(rx: &mut FrameReceiver, bytes: &[u8], millis: u32) -> (Vec<u8>, Vec<Vec<u8>>) {
+        let mut text = Vec::new();
+        let mut frames = Vec::new();
+        for &b in bytes {
+            match rx.feed(b, millis) {
+                Feed::Text => text.push(b),
+                Feed::Consumed => {}
+                Feed::Frame => frames.push(rx.frame().to_vec()),
+            }
+        }
+        (text, frames)
+    }
+
+    #[test]
+    fn frames_between_text() {
+        let mut rx = FrameReceiver::new();
+        let (text, frames) = feed(&mut rx, b"help\r\n\0\x02a\0\0\x01\0ok\r\n", 0);
+        assert_eq!(text, b"help\r\nok\r\n");
+        assert_eq!(frames, [b"\x02a".to_vec(), b"\x01".to_vec()]);
+    }
+
+    #[test]
+    fn frames_may_contain_line_breaks() {
+        let mut rx = FrameReceiver::new();
+        let (text, frames) = feed(&mut rx, b"\0\x03\r\n\0", 0);
+        assert!(text.is_empty());
+        assert_eq!(frames, [b"\x03\r\n".to_vec()]);
+    }
+
+    #[test]
+    fn stray_delimiter_times_out() {
+        let mut rx = FrameReceiver::new();
+        assert_eq!(feed(&mut rx, b"\0", 1000), (Vec::new(), Vec::new()));
+        let (text, _) = feed(&mut rx, b"h", 1000 + FRAME_TIMEOUT_MILLIS);
+        assert!(text.is_empty());
+
+        let (text, frames) = feed(&mut rx, b"elp\r\n", 1001 + 2 * FRAME_TIMEOUT_MILLIS);
+        assert_eq!(text, b"elp\r\n");
+        assert!(frames.is_empty());
+        // Frames are received again afterwards
+        let (_, frames) = feed(&mut rx, b"\0\x01\0", 2000);
+        assert_eq!(frames, [b"\x01".to_vec()]);
+    }
+
+    #[test]
+    fn timeout_across_wrap() {
+        let mut rx = FrameReceiver::new();
+        feed(&mut rx, b"\0\x02", u32::MAX);
+        let (text, frames) = feed(&mut rx, b"a\0", FRAME_TIMEOUT_MILLIS - 1);
+        assert!(text.is_empty());
+        assert_eq!(frames, [b"\x02a".to_vec()]);
+    }
+
+    #[test]
+    fn overflow_returns_to_text() {
+        let mut rx = FrameReceiver::new();
+        let mut bytes = std::vec![DELIMITER];
+        bytes.extend([b'x'; MAX_ENCODED]);
+        bytes.extend(b"help\r\n");
+        let (text, frames) = feed(&mut rx, &bytes, 0);
+        assert_eq!(text, b"help\r\n");
+        assert!(frames.is_empty());
+    }
+
+    /// Encodes `src` & decodes it again, checking that the encoded data contains no zero bytes
+    fn cobs_round_trip(src: &[u8]) -> Vec<u8> {
+        let mut buf = std::vec![0xAA; cobs::max_encoded_len(src.len())];
+        let len = cobs::encode(src, &mut buf).unwrap();
+        assert!(!buf[..len].contains(&0));
+        let encoded = buf[..len].to_vec();
+        let decoded = cobs::decode_in_place(&mut buf[..len]).unwrap();
+        assert_eq!(&buf[..decoded], src);
+        encoded
+    }
+
+    #[test]
+    fn cobs_encode() {
+        assert_eq!(cobs_round_trip(&[]), [0x01]);
+        assert_eq!(cobs_round_trip(&[0x00]), [0x01, 0x01]);
+        assert_eq!(cobs_round_trip(&[0x00, 0x00]), [0x01, 0x01, 0x01]);
+        assert_eq!(
+            cobs_round_trip(&[0x11, 0x22, 0x00, 0x33]),
+            [0x03, 0x11, 0x22, 0x02, 0x33]
+        );
+        assert_eq!(cobs_round_trip(&[0x11, 0x00]), [0x02, 0x11, 0x01]);
+    }
+
+    #[test]
+    fn cobs_long_runs() {
+        // A run of 254 non-zero bytes fills a whole block
+        let run: Vec<u8> = (1..=254).collect();
+        let encoded = cobs_round_trip(&run);
+        assert_eq!(encoded.len(), cobs::max_encoded_len(run.len()));
+        assert_eq!(encoded[0], 0xFF);
+        assert_eq!(&encoded[1..255], run);
+
+        let mut longer = run.clone();
+        longer.push(0xFF);
+        let encoded = cobs_round_trip(&longer);
+        assert_eq!(encoded[0], 0xFF);
+        assert_eq!(&encoded[255..], [0x02, 0xFF]);
+
+        let mut zero_after = run;
+        zero_after.push(0);
+        cobs_round_trip(&zero_after);
+    }
+
+    #[test]
+    fn cobs_encode_too_small() {
+        let mut buf = [0; 4];
+        assert_eq!(cobs::encode(&[1, 2, 3, 4], &mut buf), None);
+        assert_eq!(cobs::encode(&[1, 2, 3], &mut buf), Some(4));
+        assert_eq!(cobs::encode(&[], &mut []), None);
+    }
+
+    #[test]
+    fn cobs_decode_invalid() {
+        // Zero code byte
+        assert_eq!(cobs::decode_in_place(&mut [0x00, 0x11]), None);
+        // Zero byte inside a block
+        assert_eq!(cobs::decode_in_place(&mut [0x03, 0x11, 0x00]), None);
+        // Block past the end
+        assert_eq!(cobs::decode_in_place(&mut [0x04, 0x11, 0x22]), None);
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let payload = [0x00, 0x01, 0x00, 0xFF];
+        let mut frame = [0; MAX_FRAME];
+        let len = encode_frame(&payload, &mut frame);
+        assert_eq!(frame[0], DELIMITER);
+        assert_eq!(frame[len - 1], DELIMITER);
+        assert!(!frame[1..len - 1].contains(&DELIMITER));
+        assert_eq!(decode_frame(&mut frame[1..len - 1]), Ok(&payload[..]));
+    }
+
+    #[test]
+    fn frame_errors() {
+        let mut frame = [0; MAX_FRAME];
+        let len = encode_frame(&[0x01, 0x02], &mut frame);
+        let mut corrupt = frame;
+        corrupt[2] ^= 0x40;
+        assert_eq!(decode_frame(&mut corrupt[1..len - 1]), Err(Error::Crc));
+
+        // Too short for a CRC
+        assert_eq!(decode_frame(&mut [0x02, 0x01]), Err(Error::Crc));
+        assert_eq!(decode_frame(&mut [0x00]), Err(Error::Cobs));
+    }
+
+    fn gains() -> PidGains {
+        PidGains {
+            kp: Temperature::from_num(-2.5),
+            ki: Temperature::MAX,
+            kd: Temperature::MIN,
+        }
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let requests = [
+            Request::GetTemp,
+            Request::GetTemps,
+            Request::GetEvents,
+            Request::GetTarget,
+            Request::SetTarget(Temperature::from_num(-4.25)),
+            Request::GetGains,
+            Request::SetGains(gains()),
+            Request::GetResolution,
+            Request::SetResolution(12),
+            Request::GetCooler,
+            Request::SetCooler(CoolerMode::Off),
+            Request::GetAggregates(Tier::Hour),
+            Request::GetTime,
+            Request::SetTime(u32::MAX),
+        ];
+        for request in requests {
+            let mut buf = [0; MAX_PAYLOAD];
+            let len = request.encode(&mut buf);
+            assert_eq!(Request::decode(&buf[..len]), Ok(request));
+
+            let mut frame = [0; MAX_FRAME];
+            let len = request.to_frame(&mut frame);
+            assert_eq!(Request::from_frame(&mut frame[1..len - 1]), Ok(request));
+        }
+    }
+
+    #[test]
+    fn request_errors() {
+        assert_eq!(Request::decode(&[]), Err(Error::Length));
+        assert_eq!(Request::decode(&[0x7F]), Err(Error::UnknownType(0x7F)));
+        // Missing & trailing bytes
+        assert_eq!(Request::decode(&[0x05, 0x10]), Err(Error::Length));
+        assert_eq!(Request::decode(&[0x01, 0x00]), Err(Error::Length));
+        // Unknown cooler mode & tier
+        assert_eq!(Request::decode(&[0x0B, 0x03]), Err(Error::InvalidValue));
+        assert_eq!(Request::decode(&[0x0C, 0x00]), Err(Error::InvalidValue));
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let temp = |secs| StoredTemp::new(secs, I6F2::from_num(-3.75));
+        let precise = |secs| PreciseTemp::new(secs, Temperature::from_num(4.0625));
+        let aggregate =
+            |secs| StoredAggregate::new(secs, I6F2::MIN, I6F2::from_num(1.5), I6F2::MAX);
+        let mut file = [0; PANIC_FILE_SIZE];
+        file.copy_from_slice(b"main.rsx");
+
+        let responses = [
+            Response::Ok,
+            Response::Error(ErrorCode::Missing),
+            Response::Temp {
+                secs: u32::MAX,
+                temp: Temperature::from_num(-0.0625),
+            },
+            Response::Temps((0..).map(temp).take(TEMPS_PER_FRAME).collect()),
+            Response::Temps(heapless::Vec::new()),
+            Response::Event {
+                secs: 100,
+                last: 200,
+                count: 3,
+                event: Event::TempSensorError {
+                    error: SensorError::CrcMismatch,
+                    address: 0x2800_0000_1234_5678,
+                },
+            },
+            Response::Event {
+                secs: 0,
+                last: 0,
+                count: 1,
+                event: Event::Boot {
+                    count: 7,
+                    cause: ResetCause::Watchdog,
+                },
+            },
+            Response::Event {
+                secs: u32::MAX,
+                last: u32::MAX,
+                count: u8::MAX,
+                event: Event::Panic { file, line: 1234 },
+            },
+            Response::Event {
+                secs: 1,
+                last: 1,
+                count: 1,
+                event: Event::PidParamsChanged {
+                    old: gains(),
+                    new: gains(),
+                },
+            },
+            Response::End { count: u16::MAX },
+            Response::Target(Temperature::from_num(4)),
+            Response::Gains(gains()),
+            Response::Resolution(9),
+            Response::Cooler {
+                mode: CoolerMode::Auto,
+                on: true,
+            },
+            Response::Aggregates((0..).map(aggregate).take(AGGREGATES_PER_FRAME).collect()),
+            Response::PreciseTemps((0..).map(precise).take(PRECISE_TEMPS_PER_FRAME).collect()),
+            Response::Time {
+                secs: 42,
+                unix: Some(1_700_000_000),
+            },
+            Response::Time {
+                secs: 42,
+                unix: None,
+            },
+        ];
+        for response in responses {
+            let mut buf = [0; MAX_PAYLOAD];
+            let len = response.encode(&mut buf);
+            assert_eq!(Response::decode(&buf[..len]).as_ref(), Ok(&response));
+
+            let mut frame = [0; MAX_FRAME];
+            let len = response.to_frame(&mut frame);
+            assert_eq!(
+                Response::from_frame(&mut frame[1..len - 1]).as_ref(),
+                Ok(&response)
+            );
+        }
+    }
+
+    #[test]
+    fn response_errors() {
+        assert_eq!(Response::decode(&[0x7F]), Err(Error::UnknownType(0x7F)));
+        assert_eq!(Response::decode(&[0x81, 0x00]), Err(Error::InvalidValue));
+        // A partial record is left over
+        assert_eq!(Response::decode(&[0x83, 0x01, 0x02]), Err(Error::Length));
+        let mut too_many = std::vec![0x83];
+        too_many.extend([0x01; (TEMPS_PER_FRAME + 1) * StoredTemp::SIZE]);
+        assert_eq!(Response::decode(&too_many), Err(Error::Length));
+    }
+}
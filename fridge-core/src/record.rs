@@ -0,0 +1,509 @@
+//! Compact record formats kept in the fridge's storage.
+//!
+//! Raw temperatures are kept in one of the [`TempFormat`]s, as [`StoredTemp`]s by default or as
+//! [`PreciseTemp`]s for the full resolution of the thermometer. To cover days of history in little
+//! RAM, they are also aggregated per minute & per hour into [`StoredAggregate`]s by
+//! [`Aggregator`]s.
+//!
+//! Records keep only the low 24 bits of their seconds since startup, which wrap around after about
+//! 194 days. Use [`extend_secs`] to restore the full seconds.
+
+use core::fmt::Debug;
+
+use fixed::types::I6F2;
+
+use crate::thermometer::Temperature;
+
+/// Period of the 24 bit timestamps of records, about 194 days
+pub const SECS_WRAP: u32 = 1 << 24;
+
+/// Restores the full seconds since startup of a 24 bit timestamp, for a record stored at or before
+/// `now` seconds since startup
+///
+/// A stored log spans far less than [`SECS_WRAP`], so the record is from the last time before
+/// `now` with the same low 24 bits.
+pub const fn extend_secs(stamp: u32, now: u32) -> u32 {
+    let age = now.wrapping_sub(stamp) & (SECS_WRAP - 1);
+    match now.checked_sub(age) {
+        Some(secs) => secs,
+        // Not stored before `now`, e.g. `now` is from another boot
+        None => stamp & (SECS_WRAP - 1),
+    }
+}
+
+/// Version of the format of stored temperature records
+///
+/// Sent along with records that leave the fridge, so host tools can decode either format.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TempFormat {
+    /// [`StoredTemp`], 4 bytes with 0.25 °C steps from -32 to 31.75 °C
+    Compact = 1,
+    /// [`PreciseTemp`], 5 bytes with the full range & resolution of a [`Temperature`]
+    Precise,
+}
+
+impl TempFormat {
+    /// Size of a single record in bytes
+    pub const fn size(self) -> usize {
+        match self {
+            Self::Compact => StoredTemp::SIZE,
+            Self::Precise => PreciseTemp::SIZE,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Compact => "compact",
+            Self::Precise => "precise",
+        }
+    }
+
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Compact),
+            2 => Some(Self::Precise),
+            _ => None,
+        }
+    }
+}
+
+/// A stored temperature record of one of the [`TempFormat`]s
+pub trait TempRecord: Copy + Eq + Debug {
+    const FORMAT: TempFormat;
+
+    /// Stores `temp`, saturating if it is out of the range of the format
+    fn from_temp(secs: u32, temp: Temperature) -> Self;
+    /// Seconds since startup
+    fn secs(self) -> u32;
+    fn value(self) -> Temperature;
+
+    /// Raw bits of the value, in steps of the resolution of the format
+    fn bits(self) -> i32;
+    /// Creates a record from the raw bits of its value, `None` if they are out of range
+    fn from_bits(secs: u32, bits: i32) -> Option<Self>;
+
+    /// Decodes a record from its in-memory representation, `bytes` must be the size of the format
+    fn read_bytes(bytes: &[u8]) -> Self;
+    /// Encodes a record into its in-memory representation, `out` must be the size of the format
+    fn write_bytes(self, out: &mut [u8]);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct StoredTemp {
+    /// Seconds since startup (LSB u24)
+    secs: [u8; 3],
+    /// Reduced precision temperature
+    value: I6F2,
+}
+
+static_assertions::assert_eq_size!(StoredTemp, u32);
+
+impl StoredTemp {
+    /// Size of a single record in bytes
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    #[inline]
+    pub const fn new(secs: u32, value: I6F2) -> Self {
+        let [s0, s1, s2, _] = secs.to_le_bytes();
+        Self {
+            secs: [s0, s1, s2],
+            value,
+        }
+    }
+
+    #[inline]
+    pub fn from_temp(secs: u32, temp: Temperature) -> Self {
+        Self::new(secs, temp.saturating_to_num())
+    }
+
+    /// Decodes a record from its in-memory representation
+    #[inline]
+    pub const fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        Self::new(
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]),
+            I6F2::from_le_bytes([bytes[3]]),
+        )
+    }
+
+    /// Encodes a record into its in-memory representation
+    #[inline]
+    pub const fn to_bytes(self) -> [u8; Self::SIZE] {
+        let [v] = self.value.to_le_bytes();
+        [self.secs[0], self.secs[1], self.secs[2], v]
+    }
+
+    #[inline]
+    pub const fn secs(self) -> u32 {
+        u32::from_le_bytes([self.secs[0], self.secs[1], self.secs[2], 0])
+    }
+
+    #[inline]
+    pub fn value(self) -> Temperature {
+        self.value.to_num()
+    }
+}
+
+impl From<StoredTemp> for (u32, Temperature) {
+    fn from(value: StoredTemp) -> Self {
+        (value.secs(), value.value())
+    }
+}
+
+impl TempRecord for StoredTemp {
+    const FORMAT: TempFormat = TempFormat::Compact;
+
+    fn from_temp(secs: u32, temp: Temperature) -> Self {
+        Self::from_temp(secs, temp)
+    }
+
+    fn secs(self) -> u32 {
+        self.secs()
+    }
+
+    fn value(self) -> Temperature {
+        self.value()
+    }
+
+    fn bits(self) -> i32 {
+        i32::from(self.value.to_bits())
+    }
+
+    fn from_bits(secs: u32, bits: i32) -> Option<Self> {
+        let bits = i8::try_from(bits).ok()?;
+        Some(Self::new(secs, I6F2::from_bits(bits)))
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        let mut array = [0; Self::SIZE];
+        array.copy_from_slice(bytes);
+        Self::from_bytes(array)
+    }
+
+    fn write_bytes(self, out: &mut [u8]) {
+        out.copy_from_slice(&self.to_bytes());
+    }
+}
+
+/// A temperature with the full precision of the thermometer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct PreciseTemp {
+    /// Seconds since startup (LSB u24)
+    secs: [u8; 3],
+    value: Temperature,
+}
+
+static_assertions::assert_eq_size!(PreciseTemp, [u8; 5]);
+
+impl PreciseTemp {
+    /// Size of a single record in bytes
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    #[inline]
+    pub const fn new(secs: u32, value: Temperature) -> Self {
+        let [s0, s1, s2, _] = secs.to_le_bytes();
+        Self {
+            secs: [s0, s1, s2],
+            value,
+        }
+    }
+
+    #[inline]
+    pub const fn from_temp(secs: u32, temp: Temperature) -> Self {
+        Self::new(secs, temp)
+    }
+
+    /// Decodes a record from its in-memory representation
+    #[inline]
+    pub const fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        Self::new(
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]),
+            Temperature::from_le_bytes([bytes[3], bytes[4]]),
+        )
+    }
+
+    /// Encodes a record into its in-memory representation
+    #[inline]
+    pub const fn to_bytes(self) -> [u8; Self::SIZE] {
+        let [v0, v1] = self.value.to_le_bytes();
+        [self.secs[0], self.secs[1], self.secs[2], v0, v1]
+    }
+
+    #[inline]
+    pub const fn secs(self) -> u32 {
+        u32::from_le_bytes([self.secs[0], self.secs[1], self.secs[2], 0])
+    }
+
+    #[inline]
+    pub const fn value(self) -> Temperature {
+        self.value
+    }
+}
+
+impl TempRecord for PreciseTemp {
+    const FORMAT: TempFormat = TempFormat::Precise;
+
+    fn from_temp(secs: u32, temp: Temperature) -> Self {
+        Self::from_temp(secs, temp)
+    }
+
+    fn secs(self) -> u32 {
+        self.secs()
+    }
+
+    fn value(self) -> Temperature {
+        self.value()
+    }
+
+    fn bits(self) -> i32 {
+        i32::from(self.value().to_bits())
+    }
+
+    fn from_bits(secs: u32, bits: i32) -> Option<Self> {
+        let bits = i16::try_from(bits).ok()?;
+        Some(Self::new(secs, Temperature::from_bits(bits)))
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        let mut array = [0; Self::SIZE];
+        array.copy_from_slice(bytes);
+        Self::from_bytes(array)
+    }
+
+    fn write_bytes(self, out: &mut [u8]) {
+        out.copy_from_slice(&self.to_bytes());
+    }
+}
+
+/// Tier of aggregated temperatures
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Tier {
+    Minute = 1,
+    Hour,
+}
+
+impl Tier {
+    /// Seconds covered by a single aggregate
+    pub const fn period(self) -> u32 {
+        match self {
+            Self::Minute => 60,
+            Self::Hour => 60 * 60,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+        }
+    }
+
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Minute),
+            2 => Some(Self::Hour),
+            _ => None,
+        }
+    }
+}
+
+/// Minimum, average & maximum of the temperatures of a period
+///
+/// Always narrowed to 0.25 °C steps between -32 & 31.75 °C like [`StoredTemp`], also with
+/// `precise-temps`: a week of hourly aggregates at full precision would take half again as much
+/// RAM.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C, packed)]
+pub struct StoredAggregate {
+    /// Seconds since startup at the start of the period (LSB u24)
+    secs: [u8; 3],
+    min: I6F2,
+    avg: I6F2,
+    max: I6F2,
+}
+
+static_assertions::assert_eq_size!(StoredAggregate, [u8; 6]);
+
+impl StoredAggregate {
+    /// Size of a single record in bytes
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    #[inline]
+    pub const fn new(secs: u32, min: I6F2, avg: I6F2, max: I6F2) -> Self {
+        let [s0, s1, s2, _] = secs.to_le_bytes();
+        Self {
+            secs: [s0, s1, s2],
+            min,
+            avg,
+            max,
+        }
+    }
+
+    /// Decodes a record from its in-memory representation
+    #[inline]
+    pub const fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        Self::new(
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]),
+            I6F2::from_le_bytes([bytes[3]]),
+            I6F2::from_le_bytes([bytes[4]]),
+            I6F2::from_le_bytes([bytes[5]]),
+        )
+    }
+
+    /// Encodes a record into its in-memory representation
+    #[inline]
+    pub const fn to_bytes(self) -> [u8; Self::SIZE] {
+        let [min] = self.min.to_le_bytes();
+        let [avg] = self.avg.to_le_bytes();
+        let [max] = self.max.to_le_bytes();
+        [self.secs[0], self.secs[1], self.secs[2], min, avg, max]
+    }
+
+    #[inline]
+    pub const fn secs(self) -> u32 {
+        u32::from_le_bytes([self.secs[0], self.secs[1], self.secs[2], 0])
+    }
+
+    #[inline]
+    pub fn min(self) -> Temperature {
+        self.min.to_num()
+    }
+
+    #[inline]
+    pub fn avg(self) -> Temperature {
+        self.avg.to_num()
+    }
+
+    #[inline]
+    pub fn max(self) -> Temperature {
+        self.max.to_num()
+    }
+}
+
+/// Aggregates temperatures over the periods of a [`Tier`]
+///
+/// Periods are aligned to multiples of the period since startup. Periods without any temperature,
+/// e.g. while the sensor is failing, are skipped.
+#[derive(Debug, Clone)]
+pub struct Aggregator {
+    tier: Tier,
+    /// Start of the current period
+    start: u32,
+    count: u32,
+    min: Temperature,
+    max: Temperature,
+    /// Sum of the temperatures of the current period, in bits
+    sum: i32,
+}
+
+impl Aggregator {
+    pub const fn new(tier: Tier) -> Self {
+        Self {
+            tier,
+            start: 0,
+            count: 0,
+            min: Temperature::MAX,
+            max: Temperature::MIN,
+            sum: 0,
+        }
+    }
+
+    /// Adds a temperature measured at `secs` since startup
+    ///
+    /// Returns the aggregate of the previous period once a temperature of a later period comes in.
+    pub fn add(&mut self, secs: u32, temp: Temperature) -> Option<StoredAggregate> {
+        let start = secs - secs % self.tier.period();
+        let done = if start == self.start {
+            None
+        } else {
+            let done = self.finish();
+            self.clear();
+            self.start = start;
+            done
+        };
+
+        self.count += 1;
+        self.min = self.min.min(temp);
+        self.max = self.max.max(temp);
+        self.sum = self.sum.saturating_add(i32::from(temp.to_bits()));
+
+        done
+    }
+
+    /// The aggregate of the current period so far, `None` if it has no temperatures yet
+    pub fn finish(&self) -> Option<StoredAggregate> {
+        let count = i32::try_from(self.count).ok().filter(|c| *c > 0)?;
+        // The average of i16s rounded to the nearest bit fits an i16
+        let avg = (2 * self.sum + count).div_euclid(2 * count);
+        let avg = Temperature::from_bits(i16::try_from(avg).unwrap_or(i16::MAX));
+
+        Some(StoredAggregate::new(
+            self.start,
+            self.min.saturating_to_num(),
+            avg.saturating_to_num(),
+            self.max.saturating_to_num(),
+        ))
+    }
+
+    /// Discards the temperatures of the current period
+    pub const fn clear(&mut self) {
+        *self = Self {
+            start: self.start,
+            ..Self::new(self.tier)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: u32 = SECS_WRAP;
+
+    #[test]
+    fn extend_secs_before_wrap() {
+        assert_eq!(extend_secs(40, 50), 40);
+        assert_eq!(extend_secs(0, 50), 0);
+        // Not stored before `now`, kept as is
+        assert_eq!(extend_secs(60, 50), 60);
+    }
+
+    #[test]
+    fn extend_secs_across_wrap() {
+        // Just before & just after the wrap
+        assert_eq!(extend_secs(W - 1, W + 1), W - 1);
+        assert_eq!(extend_secs(1, W + 1), W + 1);
+        assert_eq!(extend_secs(0, W + 1), W);
+        assert_eq!(extend_secs(W - 10, W + 5), W - 10);
+    }
+
+    #[test]
+    fn extend_secs_now_at_wrap() {
+        assert_eq!(extend_secs(W - 1, W), W - 1);
+        assert_eq!(extend_secs(0, W), W);
+        assert_eq!(extend_secs(1, W - 1), 1);
+    }
+
+    #[test]
+    fn extend_secs_stamp_is_now() {
+        assert_eq!(extend_secs(100, 100), 100);
+        assert_eq!(extend_secs(100, W + 100), W + 100);
+        assert_eq!(extend_secs(W - 1, W - 1), W - 1);
+        assert_eq!(extend_secs(0, 0), 0);
+    }
+
+    #[test]
+    fn extend_secs_later_wrap() {
+        assert_eq!(extend_secs(3, 3 * W + 10), 3 * W + 3);
+        assert_eq!(extend_secs(W - 5, 3 * W + 10), 3 * W - 5);
+        assert_eq!(extend_secs(20, 3 * W + 10), 2 * W + 20);
+        // The stamps of records keep only the low 24 bits of their seconds
+        let secs = 200 * W + 12_345;
+        let stamp = StoredTemp::new(secs, I6F2::ZERO).secs();
+        assert_eq!(extend_secs(stamp, secs + 60), secs);
+        assert_eq!(extend_secs(stamp, u32::MAX), 255 * W + 12_345);
+    }
+}
@@ -0,0 +1,161 @@
+//! Offline replay of recorded temperatures through a [`Controller`].
+//!
+//! Used to compare controller tunings against data recorded by a real fridge (`dump temps`),
+//! without having to touch the fridge itself.
+
+use core::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    controller::Controller,
+    record::{StoredTemp, TempRecord},
+    thermometer::Temperature,
+};
+
+/// A single recorded temperature
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// Seconds since startup
+    pub secs: u32,
+    /// Recorded temperature
+    pub temp: Temperature,
+}
+
+impl Sample {
+    /// Takes the sample of a stored record of any format
+    pub fn from_record<R: TempRecord>(record: R) -> Self {
+        Self {
+            secs: record.secs(),
+            temp: record.value(),
+        }
+    }
+}
+
+impl From<StoredTemp> for Sample {
+    fn from(value: StoredTemp) -> Self {
+        Self::from_record(value)
+    }
+}
+
+/// Parses a single line of a text dump (`<secs> <temp>`), as printed by `dump temps`.
+///
+/// Returns `None` for lines that are not a temperature record, such as the echoed command or
+/// `<ok>`.
+pub fn parse_line(line: &str) -> Option<Sample> {
+    let mut parts = line.split_whitespace();
+    let secs = parts.next()?.parse().ok()?;
+    let temp = parts.next()?.parse().ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(Sample { secs, temp })
+}
+
+/// Iterates over the samples of a text dump, skipping lines that aren't temperature records.
+pub fn parse_text(dump: &str) -> impl Iterator<Item = Sample> + '_ {
+    dump.lines().filter_map(parse_line)
+}
+
+/// Iterates over the samples of a binary dump, a sequence of raw records of type `R`.
+///
+/// Trailing bytes that don't form a whole record are ignored.
+pub fn parse_binary<R: TempRecord>(dump: &[u8]) -> impl Iterator<Item = Sample> + '_ {
+    dump.chunks_exact(R::FORMAT.size())
+        .map(|chunk| Sample::from_record(R::read_bytes(chunk)))
+}
+
+/// The result of running the controller on a single [`Sample`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// Seconds since startup
+    pub secs: u32,
+    /// Recorded temperature
+    pub temp: Temperature,
+    /// Target temperature of the controller
+    pub target: Temperature,
+    /// Output the controller would have produced
+    pub output: u8,
+}
+
+impl Step {
+    /// Whether the firmware would have turned the cooler on for this step
+    pub const fn cooler_on(self) -> bool {
+        self.output > 127
+    }
+}
+
+/// Replays samples through a [`Controller`], yielding the output for each sample.
+///
+/// Created by [`replay`].
+pub struct Replay<C, I> {
+    controller: C,
+    samples: I,
+}
+
+/// Replays `samples` through `controller`.
+///
+/// The controller is run once per sample, in the order given, exactly as the temperature
+/// controller task would have run it.
+pub fn replay<C, I>(controller: C, samples: I) -> Replay<C, I::IntoIter>
+where
+    C: Controller,
+    I: IntoIterator<Item = Sample>,
+{
+    Replay {
+        controller,
+        samples: samples.into_iter(),
+    }
+}
+
+impl<C, I> Replay<C, I> {
+    /// Returns the controller being replayed
+    pub const fn controller(&self) -> &C {
+        &self.controller
+    }
+
+    /// Consumes the replay, returning the controller
+    pub fn into_controller(self) -> C {
+        self.controller
+    }
+}
+
+impl<C, I> Iterator for Replay<C, I>
+where
+    C: Controller,
+    I: Iterator<Item = Sample>,
+{
+    type Item = Result<Step, C::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.samples.next()?;
+        let target = self.controller.get_target();
+
+        Some(
+            block_on(self.controller.run(sample.temp)).map(|output| Step {
+                secs: sample.secs,
+                temp: sample.temp,
+                target,
+                output,
+            }),
+        )
+    }
+}
+
+/// Polls a future to completion.
+///
+/// Controllers don't wait on anything external, so this is expected to complete on the first poll.
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let mut cx = Context::from_waker(Waker::noop());
+
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Statistics over a window of stored temperatures.
+//!
+//! Everything is computed on the raw bits of [`Temperature`]s with integer arithmetic, so the
+//! firmware doesn't pull in any float code. Samples are taken at a fixed interval, so shares of
+//! samples are shares of time.
+
+use crate::thermometer::Temperature;
+
+/// Default half width of the band around the target that counts as on target, 0.5 °C
+pub const DEFAULT_BAND: Temperature = Temperature::from_bits(1 << 3);
+
+/// Statistics of a window of samples
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of samples in the window
+    pub count: u32,
+    pub min: Temperature,
+    pub max: Temperature,
+    pub mean: Temperature,
+    /// Population standard deviation
+    pub stddev: Temperature,
+    /// Percentage of samples within the band around the target
+    pub within: u8,
+    /// Percentage of samples the cooler was on
+    pub duty: u8,
+}
+
+/// Accumulates samples into [`Stats`] in a single pass
+#[derive(Debug, Clone)]
+pub struct Accumulator {
+    target: Temperature,
+    band: Temperature,
+    count: u32,
+    within: u32,
+    on: u32,
+    min: Temperature,
+    max: Temperature,
+    /// Bits of the first sample, the others are summed relative to it so the sums stay small
+    first: i16,
+    /// Sum of the samples relative to `first`, in bits
+    sum: i64,
+    /// Sum of the squares of the samples relative to `first`, in bits squared
+    sum_sq: i64,
+}
+
+impl Accumulator {
+    /// Starts accumulating, counting samples within `target ± band` as on target
+    pub const fn new(target: Temperature, band: Temperature) -> Self {
+        Self {
+            target,
+            band,
+            count: 0,
+            within: 0,
+            on: 0,
+            min: Temperature::MAX,
+            max: Temperature::MIN,
+            first: 0,
+            sum: 0,
+            sum_sq: 0,
+        }
+    }
+
+    /// Adds a sample, `on` being whether the cooler was on
+    pub fn add(&mut self, temp: Temperature, on: bool) {
+        if self.count == 0 {
+            self.first = temp.to_bits();
+        }
+        self.count += 1;
+        self.within += u32::from(temp.abs_diff(self.target) <= self.band.unsigned_abs());
+        self.on += u32::from(on);
+        self.min = self.min.min(temp);
+        self.max = self.max.max(temp);
+
+        let delta = i64::from(temp.to_bits()) - i64::from(self.first);
+        self.sum += delta;
+        self.sum_sq += delta * delta;
+    }
+
+    /// Statistics of the samples added so far, `None` if there are none
+    pub fn finish(&self) -> Option<Stats> {
+        if self.count == 0 {
+            return None;
+        }
+        let count = i64::from(self.count);
+
+        // Rounded to the nearest bit, the mean is within the range of the samples
+        let mean = i64::from(self.first) + div_round(self.sum, count);
+        // n² · variance = n · Σd² - (Σd)², which is never negative
+        let scaled_var = (count * self.sum_sq - self.sum * self.sum).unsigned_abs();
+        // The deviation is at most half the range of the samples
+        let stddev = div_round(isqrt(scaled_var).try_into().unwrap_or(i64::MAX), count);
+
+        Some(Stats {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: Temperature::from_bits(i16::try_from(mean).unwrap_or(i16::MAX)),
+            stddev: Temperature::from_bits(i16::try_from(stddev).unwrap_or(i16::MAX)),
+            within: percent(self.within, self.count),
+            duty: percent(self.on, self.count),
+        })
+    }
+}
+
+/// Divides rounding to the nearest integer, `den` must be positive
+const fn div_round(num: i64, den: i64) -> i64 {
+    (2 * num + den).div_euclid(2 * den)
+}
+
+/// Rounded percentage of `part` in `total`, `part` must be at most `total`
+fn percent(part: u32, total: u32) -> u8 {
+    let percent = (u64::from(part) * 100 + u64::from(total) / 2) / u64::from(total);
+    u8::try_from(percent).unwrap_or(100)
+}
+
+/// Integer square root, rounded down
+const fn isqrt(num: u64) -> u64 {
+    let mut rem = num;
+    let mut root = 0;
+    let mut bit = 1 << 62;
+    while bit > num {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(temp: f32) -> Temperature {
+        Temperature::from_num(temp)
+    }
+
+    fn bits(bits: i16) -> Temperature {
+        Temperature::from_bits(bits)
+    }
+
+    /// Statistics of samples with the cooler state, on target within 5 ± 0.5 °C
+    fn stats(samples: &[(Temperature, bool)]) -> Option<Stats> {
+        let mut acc = Accumulator::new(t(5.0), DEFAULT_BAND);
+        for &(temp, on) in samples {
+            acc.add(temp, on);
+        }
+        acc.finish()
+    }
+
+    /// Mean & standard deviation of samples, in bits
+    fn mean_stddev(samples: &[i16]) -> (i16, i16) {
+        let samples: heapless::Vec<_, 8> = samples.iter().map(|&b| (bits(b), false)).collect();
+        let stats = stats(&samples).unwrap();
+        (stats.mean.to_bits(), stats.stddev.to_bits())
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(stats(&[]), None);
+    }
+
+    #[test]
+    fn hand_computed() {
+        let stats = stats(&[(t(4.0), true), (t(6.0), true), (t(5.0), false)]).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                count: 3,
+                min: t(4.0),
+                max: t(6.0),
+                mean: t(5.0),
+                // √(2 · 16² / 3) = 13.06 bits
+                stddev: bits(13),
+                within: 33,
+                duty: 67,
+            }
+        );
+    }
+
+    #[test]
+    fn negative() {
+        let stats = stats(&[(t(-3.0), false), (t(-1.0), false)]).unwrap();
+        assert_eq!(stats.min, t(-3.0));
+        assert_eq!(stats.max, t(-1.0));
+        assert_eq!(stats.mean, t(-2.0));
+        assert_eq!(stats.stddev, t(1.0));
+        assert_eq!(stats.within, 0);
+        assert_eq!(stats.duty, 0);
+    }
+
+    #[test]
+    fn single_sample() {
+        let stats = stats(&[(t(5.25), true)]).unwrap();
+        assert_eq!(
+            stats,
+            Stats {
+                count: 1,
+                min: t(5.25),
+                max: t(5.25),
+                mean: t(5.25),
+                stddev: t(0.0),
+                within: 100,
+                duty: 100,
+            }
+        );
+    }
+
+    #[test]
+    fn mean_rounding() {
+        assert_eq!(mean_stddev(&[0, 0, 1]).0, 0);
+        assert_eq!(mean_stddev(&[0, 1, 1]).0, 1);
+        // Halves round up, also below zero
+        assert_eq!(mean_stddev(&[1, 2]).0, 2);
+        assert_eq!(mean_stddev(&[-1, -2]).0, -1);
+    }
+
+    #[test]
+    fn stddev_rounding() {
+        // 0.5 bits
+        assert_eq!(mean_stddev(&[0, 1]).1, 1);
+        // 1.3 bits, from a rounded down square root of 27
+        assert_eq!(mean_stddev(&[0, 0, 0, 3]).1, 1);
+        // 1.5 bits
+        assert_eq!(mean_stddev(&[-3, 0, 0, 1]).1, 2);
+        assert_eq!(mean_stddev(&[7, 7, 7]).1, 0);
+    }
+
+    #[test]
+    fn within_band() {
+        // The edges of the band are on target
+        let samples = [
+            (t(4.5), false),
+            (t(5.5), false),
+            (t(4.4375), false),
+            (t(5.5625), false),
+        ];
+        assert_eq!(stats(&samples).unwrap().within, 50);
+    }
+
+    #[test]
+    fn percentages() {
+        assert_eq!(percent(0, 3), 0);
+        assert_eq!(percent(1, 3), 33);
+        assert_eq!(percent(2, 3), 67);
+        assert_eq!(percent(1, 8), 13);
+        assert_eq!(percent(7, 7), 100);
+        assert_eq!(percent(u32::MAX, u32::MAX), 100);
+    }
+
+    #[test]
+    fn division_rounding() {
+        assert_eq!(div_round(4, 3), 1);
+        assert_eq!(div_round(5, 3), 2);
+        assert_eq!(div_round(7, 2), 4);
+        assert_eq!(div_round(-7, 2), -3);
+        assert_eq!(div_round(-8, 3), -3);
+    }
+
+    #[test]
+    fn square_root() {
+        for (num, root) in [
+            (0, 0),
+            (1, 1),
+            (3, 1),
+            (4, 2),
+            (15, 3),
+            (16, 4),
+            (1 << 62, 1 << 31),
+        ] {
+            assert_eq!(isqrt(num), root);
+        }
+        assert_eq!(isqrt(u64::MAX), u64::from(u32::MAX));
+    }
+}
@@ -0,0 +1,107 @@
+//! Calendar dates of Unix times.
+//!
+//! The RTC of the STM32 keeps a calendar rather than a counter, while records & the host protocol
+//! use Unix times, so the firmware converts between the two. Only UTC is supported.
+
+/// Unix time of 2001-01-01, the first time the RTC can hold
+///
+/// The RTC counts years from 2000, & a year of 0 means the calendar was never set.
+pub const MIN_UNIX: u32 = 978_307_200;
+/// Unix time of 2100-01-01, the first time past the 2 digit years of the RTC
+pub const MAX_UNIX: u32 = 4_102_444_800;
+
+const SECS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// A date & time of day in UTC
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Converts a Unix time
+    // Every part is range checked by the calendar arithmetic, so the casts don't truncate
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn from_unix(secs: u32) -> Self {
+        let days = secs / SECS_PER_DAY;
+        let time = secs % SECS_PER_DAY;
+
+        // Days since 0000-03-01, so leap days come at the end of a year
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as u32;
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Converts to a Unix time, `None` if the date is invalid or not representable
+    pub const fn to_unix(self) -> Option<u32> {
+        if self.year < 1970
+            || self.month < 1
+            || self.month > 12
+            || self.day < 1
+            || self.day > days_in_month(self.year, self.month)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 59
+        {
+            return None;
+        }
+
+        let month = self.month as u32;
+        let year = self.year as u32 - (month <= 2) as u32;
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * month + 2) / 5 + self.day as u32 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        let time = self.hour as u32 * 3600 + self.minute as u32 * 60 + self.second as u32;
+        match days.checked_mul(SECS_PER_DAY) {
+            Some(secs) => secs.checked_add(time),
+            None => None,
+        }
+    }
+
+    /// Day of the week, 1 for Monday to 7 for Sunday, as kept by the RTC
+    pub const fn weekday(self) -> u8 {
+        let Some(secs) = self.to_unix() else {
+            return 0;
+        };
+        // 1970-01-01 was a Thursday
+        ((secs / SECS_PER_DAY + 3) % 7) as u8 + 1
+    }
+}
+
+const fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
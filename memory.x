@@ -1,6 +1,7 @@
 MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 32K
+  /* The last 1K page is kept for the boot counter, see src/boot.rs */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 31K
   RAM : ORIGIN = 0x20000000, LENGTH = 6K
 }
@@ -0,0 +1,87 @@
+//! Alarm task, raising the temperature alarms & driving the alert output
+
+use defmt::*;
+use fridge_core::alarm::Transition;
+use rtic::mutex_prelude::*;
+use rtic_monotonics::{
+    stm32::{Tim2 as Mono, *},
+    Monotonic,
+};
+use stm32f0xx_hal::prelude::*;
+
+use crate::{
+    app::alarm::Context,
+    status_led::{self, State},
+    storage::{now_secs, Event, Record, StoredEvent, TempRecord},
+};
+
+/// Number of alerts waiting to be printed by the terminal, e.g. an alarm raised & cleared again
+pub const ALERT_CHAN_SIZE: usize = 2;
+
+/// Checks the latest stored temperature against the alarm thresholds every second
+///
+/// Raised & cleared alarms are stored as events & pushed to the console as an unsolicited alert
+/// line. The alert output is on while an alarm is raised, unless it was acknowledged or silenced.
+#[cfg_attr(feature = "sizing", inline(never))]
+pub async fn alarm(mut cx: Context<'_>) {
+    // Seconds of the last checked temperature, so every measurement is only checked once
+    let mut checked = None;
+
+    loop {
+        let now = now_secs();
+        let temp = cx.shared.storage.lock(|s| s.temp_recent());
+        if let Some(temp) = temp.filter(|t| checked != Some(t.full_secs(now))) {
+            let secs = temp.full_secs(now);
+            checked = Some(secs);
+            let value = temp.value();
+
+            let event = cx.shared.alarm.lock(|alarm| {
+                Some(match alarm.update(secs, value)? {
+                    Transition::Raised(kind) => Event::AlarmRaised {
+                        kind,
+                        temp: value,
+                        threshold: alarm.threshold(kind),
+                    },
+                    Transition::Cleared(kind) => Event::AlarmCleared { kind, temp: value },
+                })
+            });
+            if let Some(event) = event {
+                warn!("{} at {=f32}", event.code().as_str(), value.to_num::<f32>());
+                let event = StoredEvent::now(&event);
+                // The Modbus slave owns the serial port, so alerts are only pushed to the console
+                #[cfg(not(feature = "modbus"))]
+                alert(&mut cx, event.clone());
+                cx.shared.storage.lock(|s| s.write_event(event));
+            }
+        }
+
+        // Also retries an alert the terminal missed, see `alert`
+        #[cfg(not(feature = "modbus"))]
+        if !cx.local.alert_tx.is_empty() {
+            let _ = crate::app::terminal::spawn();
+        }
+
+        let (active, sounding) = cx
+            .shared
+            .alarm
+            .lock(|alarm| (alarm.active().is_some(), alarm.sounding(now)));
+        status_led::set(State::Alarm, active);
+        if let Some(pin) = cx.local.alert_pin {
+            unwrap!(pin.set_state(sounding.into()));
+        }
+
+        Mono::delay(1.secs()).await;
+    }
+}
+
+/// Queues an alert line for the terminal & lets it print it
+///
+/// The terminal may be busy or just finishing, in which case the alert waits for the next spawn.
+/// An alert is dropped if the previous one wasn't printed yet, it is stored as an event regardless.
+#[cfg(not(feature = "modbus"))]
+fn alert(cx: &mut Context<'_>, event: StoredEvent) {
+    if cx.local.alert_tx.try_send(event).is_err() {
+        warn!("Alert dropped, terminal busy");
+    }
+    let _ = crate::app::terminal::spawn();
+}
@@ -0,0 +1,91 @@
+//! Boot counter kept in flash
+//!
+//! The last page of flash is left out of the program in `memory.x` & used as an append-only log of
+//! boot counts: every boot programs the next erased word with the previous count plus 1, so the
+//! page is only erased every 256 boots.
+
+use core::ptr;
+
+use defmt::*;
+use num_traits::AsPrimitive;
+use stm32f0xx_hal::pac::FLASH;
+
+/// Address of the last page of flash
+const PAGE_ADDR: usize = 0x0800_7C00;
+const PAGE_SIZE: usize = 1024;
+/// Number of counts that fit in the page
+const SLOTS: usize = PAGE_SIZE / 4;
+/// Value of an erased word
+const ERASED: u32 = u32::MAX;
+
+/// Counts this boot, returning the number of boots including this one
+///
+/// Must be called once at startup.
+pub fn count(flash: &FLASH) -> u32 {
+    let used = (0..SLOTS)
+        .position(|slot| read(slot) == ERASED)
+        .unwrap_or(SLOTS);
+    let count = used.checked_sub(1).map_or(0, read).wrapping_add(1);
+
+    unlock(flash);
+    let slot = if used == SLOTS {
+        erase(flash);
+        0
+    } else {
+        used
+    };
+    program(flash, slot, count);
+    flash.cr.modify(|_, w| w.lock().set_bit());
+
+    count
+}
+
+fn read(slot: usize) -> u32 {
+    // SAFETY: the page is mapped & never written by the program
+    unsafe { ptr::read_volatile((PAGE_ADDR as *const u32).add(slot)) }
+}
+
+fn unlock(flash: &FLASH) {
+    if flash.cr.read().lock().bit_is_set() {
+        // SAFETY: the key sequence unlocking the flash controller
+        flash.keyr.write(|w| unsafe { w.fkeyr().bits(0x4567_0123) });
+        flash.keyr.write(|w| unsafe { w.fkeyr().bits(0xCDEF_89AB) });
+    }
+}
+
+fn erase(flash: &FLASH) {
+    flash.cr.modify(|_, w| w.per().set_bit());
+    // SAFETY: any address in the page selects it
+    flash.ar.write(|w| unsafe { w.far().bits(PAGE_ADDR.as_()) });
+    flash.cr.modify(|_, w| w.strt().set_bit());
+    wait(flash);
+    flash.cr.modify(|_, w| w.per().clear_bit());
+}
+
+/// Programs a word of the page, a half-word at a time as the flash requires
+fn program(flash: &FLASH, slot: usize, value: u32) {
+    let addr = (PAGE_ADDR as *mut u16).wrapping_add(slot * 2);
+    let halves: [u16; 2] = [value.as_(), (value >> 16).as_()];
+
+    flash.cr.modify(|_, w| w.pg().set_bit());
+    for (i, half) in halves.into_iter().enumerate() {
+        // SAFETY: the slot is in the page & erased
+        unsafe { ptr::write_volatile(addr.add(i), half) };
+        wait(flash);
+    }
+    flash.cr.modify(|_, w| w.pg().clear_bit());
+}
+
+/// Waits for the flash to finish an operation, logging any error
+fn wait(flash: &FLASH) {
+    while flash.sr.read().bsy().bit_is_set() {}
+
+    let sr = flash.sr.read();
+    if sr.pgerr().bit_is_set() || sr.wrprt().bit_is_set() {
+        error!("Failed to program the boot count");
+    }
+    // The flags are cleared by writing 1
+    flash
+        .sr
+        .write(|w| w.eop().set_bit().pgerr().set_bit().wrprt().set_bit());
+}
@@ -2,6 +2,8 @@
 
 use embedded_hal::digital::v2::{OutputPin, StatefulOutputPin};
 
+use crate::{controller::CoolerMode, status_led};
+
 /// Thermo-electric cooler (TEC) driver.
 pub trait Cooler: StatefulOutputPin {}
 
@@ -20,11 +22,15 @@ impl<PIN: StatefulOutputPin> OutputPin for PinCooler<PIN> {
     type Error = PIN::Error;
 
     fn set_low(&mut self) -> Result<(), Self::Error> {
-        self.pin.set_low()
+        self.pin.set_low()?;
+        status_led::set(status_led::State::Cooling, false);
+        Ok(())
     }
 
     fn set_high(&mut self) -> Result<(), Self::Error> {
-        self.pin.set_high()
+        self.pin.set_high()?;
+        status_led::set(status_led::State::Cooling, true);
+        Ok(())
     }
 }
 
@@ -39,3 +45,24 @@ impl<PIN: StatefulOutputPin> StatefulOutputPin for PinCooler<PIN> {
 }
 
 impl<PIN: StatefulOutputPin> Cooler for PinCooler<PIN> {}
+
+/// Switches the cooler for a mode right away, returning whether it was switched on or off
+///
+/// In [`CoolerMode::Auto`] the cooler is left alone until the next tick of the controller.
+pub fn apply_mode<C: StatefulOutputPin>(
+    cooler: &mut C,
+    mode: CoolerMode,
+) -> Result<bool, C::Error> {
+    let on = match mode {
+        CoolerMode::Auto => return Ok(false),
+        CoolerMode::On => true,
+        CoolerMode::Off => false,
+    };
+    let switched = cooler.is_set_high()? != on;
+    if on {
+        cooler.set_high()?;
+    } else {
+        cooler.set_low()?;
+    }
+    Ok(switched)
+}
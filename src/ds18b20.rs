@@ -28,6 +28,12 @@ impl Ds18b20 {
         Self { addr }
     }
 
+    /// Address of the sensor on the 1-Wire bus
+    #[inline]
+    pub const fn address(&self) -> Address {
+        self.addr
+    }
+
     fn read_scratchpad(
         &self,
         wire: &mut OneWire,
@@ -175,6 +181,26 @@ impl Resolution {
         }
     }
 
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            9 => Some(Self::Bits9),
+            10 => Some(Self::Bits10),
+            11 => Some(Self::Bits11),
+            12 => Some(Self::Bits12),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bits of a measurement
+    pub const fn bits(self) -> u8 {
+        match self {
+            Self::Bits9 => 9,
+            Self::Bits10 => 10,
+            Self::Bits11 => 11,
+            Self::Bits12 => 12,
+        }
+    }
+
     pub const fn as_str(self) -> &'static str {
         match self {
             Self::Bits9 => "9",
@@ -0,0 +1,38 @@
+//! Heartbeats of the critical tasks, supervising the watchdog
+//!
+//! Every supervised task calls [`beat`] at least once within its deadline. The `watchdog` task only
+//! feeds the IWDG while [`stalled`] finds every heartbeat fresh, so a task hung e.g. on a 1-Wire
+//! operation resets the MCU instead of being kept alive without controlling the temperature.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{panic::Task, storage::now_secs};
+
+/// Supervised tasks & the seconds they may go without a heartbeat
+const SUPERVISED: [(Task, u32); 2] = [
+    // Ticks every 2 s, a measurement takes up to 750 ms
+    (Task::TempController, 10),
+    // Waits at most a second for a record before beating again
+    (Task::Storage, 5),
+];
+
+/// Seconds since startup of the last heartbeat of each supervised task
+static BEATS: [AtomicU32; SUPERVISED.len()] = [const { AtomicU32::new(0) }; SUPERVISED.len()];
+
+/// Marks a supervised task as alive
+pub fn beat(task: Task) {
+    if let Some(i) = SUPERVISED.iter().position(|(t, _)| *t == task) {
+        BEATS[i].store(now_secs(), Ordering::Relaxed);
+    }
+}
+
+/// Finds a supervised task that missed its deadline
+pub fn stalled() -> Option<Task> {
+    let now = now_secs();
+    SUPERVISED
+        .iter()
+        .zip(&BEATS)
+        // A task preempting this may beat after `now` was taken
+        .find(|((_, deadline), beat)| now.saturating_sub(beat.load(Ordering::Relaxed)) > *deadline)
+        .map(|((task, _), _)| *task)
+}
@@ -4,27 +4,47 @@
 #![warn(clippy::pedantic, clippy::nursery)]
 #![allow(dead_code, clippy::module_name_repetitions, clippy::wildcard_imports)]
 
-mod controller;
+mod alarm;
+mod boot;
 mod cooler;
 mod ds18b20;
+mod heartbeat;
+#[cfg(feature = "modbus")]
+mod modbus;
 mod onewire;
+mod panic;
+mod reset;
+mod rtc;
+mod stack;
+mod status_led;
 mod storage;
 mod temp_controller;
 mod terminal;
-mod thermometer;
+mod uart;
 
 use defmt_rtt as _;
+use fridge_core::{controller, event, stats, thermometer, time};
+#[cfg(not(feature = "panic-persist"))]
 use panic_probe as _;
 
+/// Baud rate of USART2
+const BAUD_RATE: u32 = 115_200;
 const WATER_TEMP_ADDR: onewire::Address = onewire::Address(0x05_00_00_0F_83_FB_60_28);
+/// Maximum number of devices remembered from the 1-Wire bus
+const MAX_DEVICES: usize = 4;
 
-#[rtic::app(device = stm32f0xx_hal::pac, dispatchers = [USART1, TIM14])]
+#[rtic::app(device = stm32f0xx_hal::pac, dispatchers = [USART1, TIM14, SPI1])]
 mod app {
     use defmt::{panic, unreachable, *};
+    use fridge_core::{
+        alarm::{Alarm, AlarmConfig},
+        protocol::{Feed, FrameReceiver, MAX_ENCODED},
+    };
     use futures_util::{
         future::{try_select, Either},
         pin_mut,
     };
+    use num_traits::AsPrimitive;
     use rtic_monotonics::{
         stm32::{Tim2 as Mono, *},
         Monotonic,
@@ -35,11 +55,8 @@ mod app {
     };
     use stm32f0xx_hal::{
         delay::Delay,
-        gpio::{
-            gpioa::{PA15, PA2},
-            Alternate, Output, Pin, PushPull, AF1,
-        },
-        pac::{Interrupt, IWDG, USART2},
+        gpio::{Output, Pin, PushPull},
+        pac::{Interrupt, IWDG},
         prelude::*,
         serial,
         serial::{Event, Serial},
@@ -47,42 +64,107 @@ mod app {
     };
 
     use crate::{
-        controller::pid::PidController,
+        alarm::ALERT_CHAN_SIZE,
+        controller::{
+            pid::{PidController, PidGains, TARGET_TEMP},
+            CoolerMode,
+        },
         cooler::PinCooler,
         ds18b20::{Ds18b20, Resolution},
-        onewire::OneWire,
-        storage::{Storage, StoredEvent, StoredTemp, CHAN_SIZE},
-        terminal::is_newline,
+        onewire::{Address, OneWire},
+        panic::{track, Task},
+        reset::ResetCause,
+        rtc::Rtc,
+        storage::{
+            Event, Storage, StoredEvent, StoredTemp, CHAN_SIZE, EVENT_CHAN_SIZE, EVENT_COUNT,
+            TEMP_COUNT,
+        },
+        temp_controller::{Counters, Tick},
+        terminal::{LineEditor, Mode},
         thermometer::Temperature,
-        WATER_TEMP_ADDR,
+        uart::{TxBuffer, Usart},
+        BAUD_RATE, MAX_DEVICES, WATER_TEMP_ADDR,
     };
 
     #[shared]
     struct Shared {
-        usart: Serial<USART2, PA2<Alternate<AF1>>, PA15<Alternate<AF1>>>,
+        /// Bytes waiting to be transmitted on USART2
+        tx: TxBuffer,
         buffer: heapless::Deque<u8, { crate::terminal::BUFFER_SIZE }>,
+        /// Binary protocol frame waiting to be handled by the terminal
+        frame: Option<heapless::Vec<u8, MAX_ENCODED>>,
         cooler: PinCooler<Pin<Output<PushPull>>>,
+        /// Whether the controller or the user drives the cooler
+        cooler_mode: CoolerMode,
+        /// Last output of the controller, 0 to 255
+        output: u8,
+        /// Counters of the controller since startup
+        counters: Counters,
         resolution: Resolution,
-        storage: Storage<100, 16>,
+        storage: Storage<TEMP_COUNT, EVENT_COUNT>,
+        /// Target temperature of the controller
+        target: Temperature,
+        /// Gains of the PID controller
+        gains: PidGains,
+        /// Address of the water thermometer
+        sensor: Address,
+        /// Devices found on the 1-Wire bus
+        devices: heapless::Vec<Address, MAX_DEVICES>,
+        /// Wall clock, kept through resets
+        rtc: Rtc,
+        /// High & low temperature alarms
+        alarm: Alarm,
+        /// Request frame of the Modbus slave
+        #[cfg(feature = "modbus")]
+        modbus_rx: crate::modbus::Receiver,
     }
 
     #[local]
     struct Local {
         // ds18b20: Ds18b20Thermometer<Delay, 4>,
 
+        // USART2 interrupt
+        usart: Usart,
+
         // Temperature Controller
         wire: OneWire,
         water_temp: Ds18b20,
         pid: PidController,
-        tx: Sender<'static, Temperature, 1>,
-        e_tx: Sender<'static, StoredEvent, 1>,
+        tx: Sender<'static, (Temperature, bool), 1>,
+        e_tx: Sender<'static, StoredEvent, EVENT_CHAN_SIZE>,
+        tick_tx: Sender<'static, Tick, 1>,
+
+        // Alarm
+        /// Buzzer or relay, on while an alarm sounds
+        alert_pin: Option<Pin<Output<PushPull>>>,
+        alert_tx: Sender<'static, StoredEvent, ALERT_CHAN_SIZE>,
 
         // Terminal
         rx: Receiver<'static, StoredTemp, CHAN_SIZE>,
+        event_rx: Receiver<'static, StoredEvent, CHAN_SIZE>,
+        tick_rx: Receiver<'static, Tick, 1>,
+        key_rx: Receiver<'static, (), 1>,
+        alert_rx: Receiver<'static, StoredEvent, ALERT_CHAN_SIZE>,
+        reset_cause: ResetCause,
+        boots: u32,
+        crash: Option<crate::panic::Record>,
+
+        // USART2 interrupt
+        #[cfg(not(feature = "modbus"))]
+        key_tx: Sender<'static, (), 1>,
     }
 
     #[init]
     fn init(mut cx: init::Context) -> (Shared, Local) {
+        let _task = crate::panic::enter(Task::Init);
+        crate::stack::paint();
+        let reset_cause = crate::reset::read(&cx.device.RCC);
+        info!("Reset cause: {}", reset_cause.as_str());
+        let boots = crate::boot::count(&cx.device.FLASH);
+        info!("Boot count: {}", boots);
+        let rtc = Rtc::new(cx.device.RTC, &cx.device.RCC, &cx.device.PWR);
+        info!("RTC clock: {}", rtc.source());
+
         // Set system clock to 8 MHz
         let mut rcc = cx
             .device
@@ -120,7 +202,7 @@ mod app {
                 gpioa.pa2.into_alternate_af1(&cx.cs),
                 gpioa.pa15.into_alternate_af1(&cx.cs),
             ),
-            115_200.bps(),
+            BAUD_RATE.bps(),
             &mut rcc,
         );
         usart.listen(Event::Rxne);
@@ -129,14 +211,25 @@ mod app {
         // Setup cooler
         let cooler = PinCooler::new(gpiob.pb4.into_push_pull_output(&cx.cs).downgrade());
 
+        // Setup alert output
+        #[cfg(feature = "alarm-output")]
+        let alert_pin = Some(gpioa.pa0.into_push_pull_output(&cx.cs).downgrade());
+        #[cfg(not(feature = "alarm-output"))]
+        let alert_pin = None;
+
         // Setup DS18B20
         let mut pa12 = gpioa.pa12.into_open_drain_output(&cx.cs);
         unwrap!(pa12.set_high());
         let mut wire = OneWire::new(pa12.downgrade());
 
+        let mut devices = heapless::Vec::new();
         for device in wire.devices(&mut delay) {
             let device = unwrap!(device);
             info!("Found device: {}", device);
+
+            if devices.push(device).is_err() {
+                warn!("Too many devices, ignoring {}", device);
+            }
         }
 
         let water_temp = Ds18b20::new(WATER_TEMP_ADDR);
@@ -148,39 +241,108 @@ mod app {
         let _ = temp_controller::spawn(delay);
 
         // Setup channels
-        let (tx1, rx1) = make_channel!(Temperature, 1);
+        let (tx1, rx1) = make_channel!((Temperature, bool), 1);
         let (tx2, rx2) = make_channel!(StoredTemp, CHAN_SIZE);
-        let (e_tx, e_rx) = make_channel!(StoredEvent, 1);
+        let (e_tx, e_rx) = make_channel!(StoredEvent, EVENT_CHAN_SIZE);
+        let (event_tx, event_rx) = make_channel!(StoredEvent, CHAN_SIZE);
+        let (tick_tx, tick_rx) = make_channel!(Tick, 1);
+        let (key_tx, key_rx) = make_channel!((), 1);
+        let (alert_tx, alert_rx) = make_channel!(StoredEvent, ALERT_CHAN_SIZE);
+        // Keys are only read by the text console
+        #[cfg(feature = "modbus")]
+        drop(key_tx);
 
         // Setup Storage
-        let storage = Storage::new(tx2);
+        let mut storage = Storage::new(tx2, event_tx);
+        // Records of this boot can only be placed in time if the clock kept running
+        if let Some(unix) = rtc.now() {
+            storage.set_epoch(unix);
+        } else {
+            warn!("RTC not set, records have no wall-clock time");
+        }
+        storage.write_event(StoredEvent::now(&Event::Boot {
+            count: boots,
+            cause: reset_cause,
+        }));
+        let crash = crate::panic::last().map(|(record, new)| {
+            if new && record.is_stall() {
+                error!(
+                    "Task {} stalled {}s after the last boot",
+                    record.task().as_str(),
+                    record.secs
+                );
+                let event = Event::TaskStalled {
+                    task: record.task(),
+                };
+                storage.write_event(StoredEvent::now(&event));
+            } else if new {
+                error!(
+                    "Panicked {}s after the last boot in {} at {}:{}: {}",
+                    record.secs,
+                    record.task().as_str(),
+                    record.file(),
+                    record.line,
+                    record.msg()
+                );
+                let event = Event::panic(record.file_name(), record.line);
+                storage.write_event(StoredEvent::now(&event));
+            }
+            record
+        });
 
-        // Launch storage task
+        // Launch storage & alarm tasks
         let _ = storage::spawn(rx1, e_rx);
+        let _ = alarm::spawn();
 
         (
             Shared {
                 // delay,
-                usart,
+                tx: TxBuffer::new(),
                 buffer: heapless::Deque::new(),
+                frame: None,
                 cooler,
+                cooler_mode: CoolerMode::Auto,
+                output: 0,
+                counters: Counters::new(),
                 resolution: Resolution::Bits12,
                 storage,
+                target: TARGET_TEMP,
+                gains: PidGains::DEFAULT,
+                sensor: WATER_TEMP_ADDR,
+                devices,
+                rtc,
+                alarm: Alarm::new(AlarmConfig::DEFAULT),
+                #[cfg(feature = "modbus")]
+                modbus_rx: crate::modbus::Receiver::new(),
             },
             Local {
                 // ds18b20,
+                usart,
                 wire,
                 water_temp,
                 pid,
                 tx: tx1,
                 e_tx,
+                tick_tx,
+                alert_pin,
+                alert_tx,
                 rx: rx2,
+                event_rx,
+                tick_rx,
+                key_rx,
+                alert_rx,
+                reset_cause,
+                boots,
+                crash,
+                #[cfg(not(feature = "modbus"))]
+                key_tx,
             },
         )
     }
 
     #[idle]
     fn idle(_: idle::Context) -> ! {
+        let _task = crate::panic::enter(Task::Idle);
         rtic::pend(Interrupt::USART2);
 
         loop {
@@ -188,113 +350,240 @@ mod app {
         }
     }
 
+    /// Blinks the pattern of the state of the fridge on the LED, see `status_led`
     #[task(priority = 1)]
     async fn blinky(_: blinky::Context, mut pin: Pin<Output<PushPull>>) {
-        unwrap!(pin.set_low());
-        let mut now = Mono::now();
-        loop {
-            unwrap!(pin.toggle());
-            now += 500.millis();
-            Mono::delay_until(now).await;
-        }
+        track(Task::Blinky, async move {
+            let mut now = Mono::now();
+            let mut shown = crate::status_led::State::Normal;
+            let mut step = 0;
+            loop {
+                // Start a new pattern from its beginning, so it can be told apart
+                let state = crate::status_led::shown();
+                if state != shown {
+                    shown = state;
+                    step = 0;
+                }
+                unwrap!(pin.set_state(shown.is_on(step).into()));
+                step = (step + 1) % crate::status_led::STEPS;
+                now += crate::status_led::STEP_MILLIS.millis();
+                Mono::delay_until(now).await;
+            }
+        })
+        .await;
     }
 
-    #[task(priority = 1)]
+    /// Above every other task, so it still runs when one of them hangs without yielding
+    ///
+    /// The 1-Wire time slots of the controller run with interrupts disabled, so it can't stretch
+    /// them.
+    #[task(priority = 3)]
     async fn watchdog(_: watchdog::Context, wdg: IWDG) {
-        let mut wdg = Watchdog::new(wdg);
-        wdg.start(1.hz());
-
-        loop {
-            wdg.feed();
-            Mono::delay(500.millis()).await;
-        }
+        track(Task::Watchdog, async move {
+            let mut wdg = Watchdog::new(wdg);
+            wdg.start(1.hz());
+
+            // Only fed while the critical tasks are alive, see `heartbeat`
+            loop {
+                if let Some(task) = crate::heartbeat::stalled() {
+                    error!("Task {} stalled, resetting", task.as_str());
+                    crate::panic::record_stall(task);
+                    // Nothing feeds the watchdog anymore, it resets the MCU within a second
+                    return;
+                }
+                wdg.feed();
+                Mono::delay(500.millis()).await;
+            }
+        })
+        .await;
     }
 
-    #[task(priority = 2, local = [wire, water_temp, pid, tx, e_tx], shared = [cooler, resolution])]
+    #[task(priority = 2, local = [wire, water_temp, pid, tx, e_tx, tick_tx], shared = [cooler, cooler_mode, output, counters, resolution, target, gains, sensor])]
     async fn temp_controller(cx: temp_controller::Context, delay: Delay) {
-        crate::temp_controller::temp_controller(cx, delay).await;
+        track(
+            Task::TempController,
+            crate::temp_controller::temp_controller(cx, delay),
+        )
+        .await;
     }
 
     #[task(priority = 1, shared = [storage])]
     async fn storage(
         mut cx: storage::Context,
-        mut rx: Receiver<'static, Temperature, 1>,
-        mut e_rx: Receiver<'static, StoredEvent, 1>,
+        mut rx: Receiver<'static, (Temperature, bool), 1>,
+        mut e_rx: Receiver<'static, StoredEvent, EVENT_CHAN_SIZE>,
     ) {
-        loop {
-            let t_fut = rx.recv();
-            let e_fut = e_rx.recv();
-            pin_mut!(t_fut, e_fut);
-
-            match try_select(t_fut, e_fut).await {
-                Ok(Either::Left((temp, _))) => {
-                    cx.shared.storage.lock(|storage| {
-                        storage.write(temp);
-                    });
-                }
-                Ok(Either::Right((event, _))) => {
-                    cx.shared.storage.lock(|storage| {
-                        storage.write_event(event);
-                    });
-                }
-                Err(e) => {
-                    let (e, _) = e.factor_first();
-                    match e {
-                        ReceiveError::Empty => continue,
-                        ReceiveError::NoSender => unreachable!("Sender dropped"),
+        track(Task::Storage, async move {
+            loop {
+                crate::heartbeat::beat(Task::Storage);
+                let t_fut = rx.recv();
+                let e_fut = e_rx.recv();
+                pin_mut!(t_fut, e_fut);
+
+                // Stay alive for the heartbeat while nothing is received, e.g. the sensor failing
+                let Ok(received) = Mono::timeout_after(1.secs(), try_select(t_fut, e_fut)).await
+                else {
+                    continue;
+                };
+                match received {
+                    Ok(Either::Left(((temp, on), _))) => {
+                        cx.shared.storage.lock(|storage| {
+                            storage.write(temp, on);
+                        });
+                    }
+                    Ok(Either::Right((event, _))) => {
+                        cx.shared.storage.lock(|storage| {
+                            storage.write_event(event);
+                        });
+                    }
+                    Err(e) => {
+                        let (e, _) = e.factor_first();
+                        match e {
+                            ReceiveError::Empty => continue,
+                            ReceiveError::NoSender => unreachable!("Sender dropped"),
+                        }
                     }
                 }
             }
-        }
+        })
+        .await;
+    }
+
+    #[task(priority = 1, local = [alert_pin, alert_tx], shared = [alarm, storage])]
+    async fn alarm(cx: alarm::Context) {
+        track(Task::Alarm, crate::alarm::alarm(cx)).await;
     }
 
-    #[task(priority = 2, local = [rx], shared = [usart, buffer, cooler, resolution, storage])]
+    #[task(
+        priority = 2,
+        local = [
+            rx, event_rx, tick_rx, key_rx, alert_rx, reset_cause, boots, crash,
+            mode: Mode = Mode::Text
+        ],
+        shared = [
+            tx, buffer, frame, cooler, cooler_mode, output, counters, resolution, storage, target,
+            gains, sensor, devices, rtc, alarm
+        ]
+    )]
     async fn terminal(cx: terminal::Context) {
-        crate::terminal::terminal(cx).await;
+        track(Task::Terminal, crate::terminal::terminal(cx)).await;
     }
 
-    #[task(binds = USART2, local = [times: u32 = 0], shared = [usart, buffer])]
-    fn usart2(cx: usart2::Context) {
+    #[cfg(not(feature = "modbus"))]
+    #[task(
+        binds = USART2,
+        local = [
+            usart,
+            key_tx,
+            times: u32 = 0,
+            editor: LineEditor = LineEditor::new(),
+            framer: FrameReceiver = FrameReceiver::new(),
+        ],
+        shared = [tx, buffer, frame]
+    )]
+    fn usart2(mut cx: usart2::Context) {
+        let _task = crate::panic::enter(Task::Usart2);
         *cx.local.times += 1;
 
-        // Read & echo all available bytes from the usart
-        (cx.shared.usart, cx.shared.buffer).lock(|usart, buffer| loop {
-            match usart.read() {
-                Ok(b) => {
-                    // Echo back
-                    if is_newline(b) {
-                        let _ = nb::block!(usart.write(b'\r'));
-                        let _ = nb::block!(usart.write(b'\n'));
-                    } else {
-                        let _ = nb::block!(usart.write(b));
-                    }
+        let usart = cx.local.usart;
+        let editor = cx.local.editor;
+        let framer = cx.local.framer;
+        let mut key = false;
+        let mut received = false;
+        // Frames time out within milliseconds, so wrapping after 49 days is fine
+        let millis: u32 = Mono::now().duration_since_epoch().to_millis().as_();
 
-                    // Append to buffer
-                    if buffer.push_back(b).is_err() {
-                        error!("Buffer overflow");
+        // Read all available bytes from the usart & pass them to the binary protocol or the line
+        // editor
+        (
+            &mut cx.shared.tx,
+            &mut cx.shared.buffer,
+            &mut cx.shared.frame,
+        )
+            .lock(|tx, buffer, frame| loop {
+                match usart.read() {
+                    Ok(b) => {
+                        received = true;
+                        match framer.feed(b, millis) {
+                            Feed::Text => key |= editor.feed(b, tx, buffer),
+                            Feed::Consumed => {}
+                            Feed::Frame if frame.is_some() => {
+                                warn!("Frame dropped, terminal busy");
+                            }
+                            Feed::Frame => *frame = Some(framer.frame().clone()),
+                        }
                     }
+                    Err(nb::Error::WouldBlock) => break,
+                    Err(nb::Error::Other(serial::Error::Framing)) => {
+                        error!("USART error: Framing");
+                    }
+                    Err(nb::Error::Other(serial::Error::Noise)) => error!("USART error: Noise"),
+                    Err(nb::Error::Other(serial::Error::Overrun)) => {
+                        error!("USART error: Overrun");
+                    }
+                    Err(nb::Error::Other(serial::Error::Parity)) => {
+                        error!("USART error: Parity");
+                    }
+
+                    Err(nb::Error::Other(_)) => defmt::error!("USART error: Unknown"),
+                    // Err(nb::Error::Other(e)) => core::panic!("USART error: {:?}", e),
                 }
+            });
+
+        // Let the terminal stop watching
+        if key {
+            let _ = cx.local.key_tx.try_send(());
+        }
+
+        // Transmit echoed & queued output
+        cx.shared.tx.lock(|tx| tx.drain(usart));
+
+        defmt::trace!("USART2 interrupt fired: {}", *cx.local.times);
+
+        // Trigger terminal task to handle input, unless this was a transmit interrupt
+        if received {
+            let _ = terminal::spawn();
+        }
+    }
+
+    #[cfg(feature = "modbus")]
+    #[task(
+        priority = 2,
+        shared = [
+            tx, modbus_rx, cooler, cooler_mode, output, counters, resolution, storage, target,
+            gains
+        ]
+    )]
+    async fn modbus_slave(cx: modbus_slave::Context) {
+        track(Task::ModbusSlave, crate::modbus::modbus_slave(cx)).await;
+    }
+
+    #[cfg(feature = "modbus")]
+    #[task(binds = USART2, local = [usart], shared = [tx, modbus_rx])]
+    fn usart2(mut cx: usart2::Context) {
+        let _task = crate::panic::enter(Task::Usart2);
+        let usart = cx.local.usart;
+
+        // Pass all available bytes from the usart to the Modbus slave
+        cx.shared.modbus_rx.lock(|rx| loop {
+            match usart.read() {
+                Ok(b) => rx.feed(b, Mono::now()),
                 Err(nb::Error::WouldBlock) => break,
-                Err(nb::Error::Other(serial::Error::Framing)) => {
-                    error!("USART error: Framing");
-                }
-                Err(nb::Error::Other(serial::Error::Noise)) => error!("USART error: Noise"),
-                Err(nb::Error::Other(serial::Error::Overrun)) => {
-                    error!("USART error: Overrun");
-                }
-                Err(nb::Error::Other(serial::Error::Parity)) => {
-                    error!("USART error: Parity");
+                Err(nb::Error::Other(_)) => {
+                    error!("USART error, dropping Modbus frame");
+                    rx.discard(Mono::now());
                 }
-
-                Err(nb::Error::Other(_)) => defmt::error!("USART error: Unknown"),
-                // Err(nb::Error::Other(e)) => core::panic!("USART error: {:?}", e),
             }
         });
 
-        defmt::trace!("USART2 interrupt fired: {}", *cx.local.times);
+        // Transmit the queued response
+        cx.shared.tx.lock(|tx| tx.drain(usart));
 
-        // Trigger terminal task to handle input
-        let _ = terminal::spawn();
+        // Trigger Modbus task to wait for the end of the frame, unless this was a transmit
+        // interrupt
+        if cx.shared.modbus_rx.lock(|rx| rx.last().is_some()) {
+            let _ = modbus_slave::spawn();
+        }
     }
 
     timestamp!("{=u64:us}", {
@@ -0,0 +1,331 @@
+//! Modbus RTU slave on USART2, replacing the text console with the `modbus` feature
+//!
+//! Temperatures & gains are in hundredths of a degree, as signed registers.
+//!
+//! Input registers:
+//! - `0` Current temperature, `0x8000` if none was measured yet
+//! - `1` Cooler state, 0 off, 1 on
+//! - `2` Controller output, 0 to 255
+//! - `3` Uptime in seconds, high word
+//! - `4` Uptime in seconds, low word
+//! - `5` Fault flags, see [`FAULT_NO_TEMP`] & [`FAULT_STALE_TEMP`]
+//!
+//! Holding registers:
+//! - `0` Target temperature
+//! - `1` Proportional gain
+//! - `2` Integral gain
+//! - `3` Derivative gain
+//! - `4` Thermometer resolution in bits, 9 to 12
+//! - `5` Cooler mode, 0 auto, 1 on, 2 off
+//!
+//! Coils:
+//! - `0` Cooler, writing switches the cooler mode to on or off
+
+use defmt::*;
+use fridge_core::modbus::{
+    self, frame_gap_us, register_to_temp, temp_to_register, Exception, Registers, MAX_ADU,
+};
+use heapless::Vec;
+use num_traits::AsPrimitive;
+use rtic::mutex_prelude::*;
+use rtic_monotonics::{
+    stm32::{Tim2 as Mono, *},
+    Monotonic,
+};
+use stm32f0xx_hal::prelude::*;
+
+use crate::{
+    app::modbus_slave::Context,
+    controller::CoolerMode,
+    cooler,
+    ds18b20::Resolution,
+    storage::{now_secs, Event, Record, StoredEvent},
+    uart, BAUD_RATE,
+};
+
+/// Address of the fridge on the bus
+const UNIT: u8 = 1;
+
+const INPUT_TEMP: u16 = 0;
+const INPUT_COOLER: u16 = 1;
+const INPUT_OUTPUT: u16 = 2;
+const INPUT_UPTIME_HI: u16 = 3;
+const INPUT_UPTIME_LO: u16 = 4;
+const INPUT_FAULTS: u16 = 5;
+
+const HOLDING_TARGET: u16 = 0;
+const HOLDING_KP: u16 = 1;
+const HOLDING_KI: u16 = 2;
+const HOLDING_KD: u16 = 3;
+const HOLDING_RESOLUTION: u16 = 4;
+const HOLDING_MODE: u16 = 5;
+
+const COIL_COOLER: u16 = 0;
+
+/// Temperature register value when no temperature was measured yet
+const TEMP_INVALID: u16 = 0x8000;
+
+/// No temperature was measured yet
+pub const FAULT_NO_TEMP: u16 = 1 << 0;
+/// The last temperature is older than [`STALE_SECS`], the thermometer is failing
+pub const FAULT_STALE_TEMP: u16 = 1 << 1;
+/// Age of the last temperature after which it is considered stale
+const STALE_SECS: u32 = 10;
+
+type Instant = <Mono as Monotonic>::Instant;
+type Duration = <Mono as Monotonic>::Duration;
+
+/// Silence on the bus ending a frame
+fn frame_gap() -> Duration {
+    u64::from(frame_gap_us(BAUD_RATE)).micros()
+}
+
+/// Collects the bytes of a request frame
+pub struct Receiver {
+    buf: Vec<u8, MAX_ADU>,
+    /// Time the last byte was received, `None` if no frame is in progress
+    last: Option<Instant>,
+    /// Whether the frame overflowed the buffer or a byte was lost
+    corrupt: bool,
+}
+
+impl Receiver {
+    pub const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            last: None,
+            corrupt: false,
+        }
+    }
+
+    /// Feeds a byte received at `now`
+    ///
+    /// Starts a new frame if the bus was silent for longer than the frame gap.
+    pub fn feed(&mut self, b: u8, now: Instant) {
+        if self.last.is_some_and(|last| now - last > frame_gap()) {
+            self.buf.clear();
+            self.corrupt = false;
+        }
+        self.last = Some(now);
+
+        if self.buf.push(b).is_err() {
+            self.corrupt = true;
+        }
+    }
+
+    /// Marks the frame in progress as corrupt, e.g. on a framing error
+    pub fn discard(&mut self, now: Instant) {
+        self.last = Some(now);
+        self.corrupt = true;
+    }
+
+    /// Time the last byte was received
+    pub const fn last(&self) -> Option<Instant> {
+        self.last
+    }
+
+    /// Takes the received frame, unless it is corrupt
+    pub fn take(&mut self) -> Option<Vec<u8, MAX_ADU>> {
+        self.last = None;
+        let frame = core::mem::take(&mut self.buf);
+        (!core::mem::take(&mut self.corrupt)).then_some(frame)
+    }
+}
+
+/// Modbus slave task
+///
+/// Spawned by the USART2 interrupt, waits for the end of the frame & answers it.
+#[cfg_attr(feature = "sizing", inline(never))]
+pub async fn modbus_slave(mut cx: Context<'_>) {
+    loop {
+        let Some(last) = cx.shared.modbus_rx.lock(|rx| rx.last()) else {
+            return;
+        };
+
+        // Wait for the bus to be silent for the frame gap
+        Mono::delay_until(last + frame_gap()).await;
+        if cx.shared.modbus_rx.lock(|rx| rx.last()) != Some(last) {
+            continue;
+        }
+
+        let Some(frame) = cx.shared.modbus_rx.lock(Receiver::take) else {
+            warn!("Corrupt Modbus frame dropped");
+            continue;
+        };
+
+        let mut response = [0; MAX_ADU];
+        let mut map = Map {
+            cx: &mut cx,
+            uptime: now_secs(),
+        };
+        let len = modbus::handle(&mut map, UNIT, &frame, &mut response);
+        if let Some(len) = len {
+            uart::write_all(&mut cx.shared.tx, &response[..len]).await;
+        }
+    }
+}
+
+/// Register map of the fridge
+struct Map<'a, 'b> {
+    cx: &'a mut Context<'b>,
+    /// Seconds since startup at the request, so both words of the uptime are of the same time
+    uptime: u32,
+}
+
+impl Map<'_, '_> {
+    fn cooler_on(&mut self) -> bool {
+        unwrap!(self.cx.shared.cooler.lock(|c| c.is_set_high()))
+    }
+
+    fn set_cooler_mode(&mut self, mode: CoolerMode) {
+        let old = self
+            .cx
+            .shared
+            .cooler_mode
+            .lock(|m| core::mem::replace(m, mode));
+        let switched = unwrap!(self.cx.shared.cooler.lock(|c| cooler::apply_mode(c, mode)));
+        if old != mode {
+            self.log_event(&Event::CoolerModeChanged { old, new: mode });
+        }
+        if switched {
+            let duty = self.cx.shared.counters.lock(|c| c.duty());
+            let on = mode == CoolerMode::On;
+            self.log_event(&Event::CoolerSwitched {
+                on,
+                duty: duty.as_(),
+            });
+        }
+    }
+
+    /// Stores an event, timestamped now
+    fn log_event(&mut self, event: &Event) {
+        self.cx
+            .shared
+            .storage
+            .lock(|s| s.write_event(StoredEvent::now(event)));
+    }
+
+    fn faults(&mut self) -> u16 {
+        let now = now_secs();
+        match self.cx.shared.storage.lock(|s| s.temp_recent()) {
+            None => FAULT_NO_TEMP,
+            Some(temp) if now - temp.full_secs(now) > STALE_SECS => FAULT_STALE_TEMP,
+            Some(_) => 0,
+        }
+    }
+}
+
+impl Registers for Map<'_, '_> {
+    fn read_coil(&mut self, addr: u16) -> Result<bool, Exception> {
+        match addr {
+            COIL_COOLER => Ok(self.cooler_on()),
+            _ => Err(Exception::IllegalDataAddress),
+        }
+    }
+
+    fn write_coil(&mut self, addr: u16, value: bool) -> Result<(), Exception> {
+        match addr {
+            COIL_COOLER => {
+                self.set_cooler_mode(if value {
+                    CoolerMode::On
+                } else {
+                    CoolerMode::Off
+                });
+                Ok(())
+            }
+            _ => Err(Exception::IllegalDataAddress),
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn read_input(&mut self, addr: u16) -> Result<u16, Exception> {
+        Ok(match addr {
+            INPUT_TEMP => self
+                .cx
+                .shared
+                .storage
+                .lock(|s| s.temp_recent())
+                .map_or(TEMP_INVALID, |temp| temp_to_register(temp.value())),
+            INPUT_COOLER => u16::from(self.cooler_on()),
+            INPUT_OUTPUT => u16::from(self.cx.shared.output.lock(|o| *o)),
+            INPUT_UPTIME_HI => (self.uptime >> 16) as u16,
+            INPUT_UPTIME_LO => self.uptime as u16,
+            INPUT_FAULTS => self.faults(),
+            _ => return Err(Exception::IllegalDataAddress),
+        })
+    }
+
+    fn read_holding(&mut self, addr: u16) -> Result<u16, Exception> {
+        let gains = self.cx.shared.gains.lock(|g| *g);
+        Ok(match addr {
+            HOLDING_TARGET => temp_to_register(self.cx.shared.target.lock(|t| *t)),
+            HOLDING_KP => temp_to_register(gains.kp),
+            HOLDING_KI => temp_to_register(gains.ki),
+            HOLDING_KD => temp_to_register(gains.kd),
+            HOLDING_RESOLUTION => u16::from(self.cx.shared.resolution.lock(|r| r.bits())),
+            HOLDING_MODE => u16::from(self.cx.shared.cooler_mode.lock(|m| *m as u8)),
+            _ => return Err(Exception::IllegalDataAddress),
+        })
+    }
+
+    fn check_holding(&mut self, addr: u16, value: u16) -> Result<(), Exception> {
+        match addr {
+            HOLDING_TARGET | HOLDING_KP | HOLDING_KI | HOLDING_KD => Ok(()),
+            HOLDING_RESOLUTION => resolution(value).map(drop),
+            HOLDING_MODE => cooler_mode(value).map(drop),
+            _ => Err(Exception::IllegalDataAddress),
+        }
+    }
+
+    fn write_holding(&mut self, addr: u16, value: u16) -> Result<(), Exception> {
+        let temp = register_to_temp(value);
+        match addr {
+            HOLDING_TARGET => {
+                let old = self.cx.shared.target.lock(|t| core::mem::replace(t, temp));
+                if old != temp {
+                    self.log_event(&Event::PidTargetChanged { old, new: temp });
+                }
+            }
+            HOLDING_KP | HOLDING_KI | HOLDING_KD => {
+                let (old, new) = self.cx.shared.gains.lock(|g| {
+                    let old = *g;
+                    match addr {
+                        HOLDING_KP => g.kp = temp,
+                        HOLDING_KI => g.ki = temp,
+                        _ => g.kd = temp,
+                    }
+                    (old, *g)
+                });
+                if old != new {
+                    self.log_event(&Event::PidParamsChanged { old, new });
+                }
+            }
+            HOLDING_RESOLUTION => {
+                let res = resolution(value)?;
+                self.cx.shared.resolution.lock(|r| *r = res);
+            }
+            HOLDING_MODE => {
+                let mode = cooler_mode(value)?;
+                self.set_cooler_mode(mode);
+            }
+            _ => return Err(Exception::IllegalDataAddress),
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the thermometer resolution register
+fn resolution(value: u16) -> Result<Resolution, Exception> {
+    u8::try_from(value)
+        .ok()
+        .and_then(Resolution::from_bits)
+        .ok_or(Exception::IllegalDataValue)
+}
+
+/// Decodes the cooler mode register
+fn cooler_mode(value: u16) -> Result<CoolerMode, Exception> {
+    u8::try_from(value)
+        .ok()
+        .and_then(CoolerMode::from_u8)
+        .ok_or(Exception::IllegalDataValue)
+}
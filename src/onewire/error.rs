@@ -1,4 +1,5 @@
 use defmt::Format;
+use fridge_core::event::SensorError;
 
 pub type Result<T, E> = core::result::Result<T, Error<E>>;
 
@@ -32,6 +33,19 @@ impl<E> Error<E> {
     }
 }
 
+impl<E> From<&Error<E>> for SensorError {
+    fn from(value: &Error<E>) -> Self {
+        match value {
+            Error::BusNotHigh => Self::BusNotHigh,
+            Error::Pin(_) => Self::Pin,
+            Error::UnexpectedResponse => Self::UnexpectedResponse,
+            Error::FamilyCodeMismatch => Self::FamilyCodeMismatch,
+            Error::CrcMismatch => Self::CrcMismatch,
+            Error::Timeout => Self::Timeout,
+        }
+    }
+}
+
 impl<E> From<E> for Error<E> {
     fn from(value: E) -> Self {
         Self::Pin(value)
@@ -38,12 +38,12 @@ impl OneWire {
         self.pin.set_low()?;
         delay.delay_us(480);
 
-        // Release the bus
-        self.pin.set_high()?;
-        delay.delay_us(70);
-
-        // Read the bus
-        let is_low = self.pin.is_low()?;
+        // Release the bus & read the presence pulse, which an interrupt could make us miss
+        let is_low = cortex_m::interrupt::free(|_| {
+            self.pin.set_high()?;
+            delay.delay_us(70);
+            self.pin.is_low()
+        })?;
         delay.delay_us(410);
 
         if is_low {
@@ -59,32 +59,20 @@ impl OneWire {
         bit: bool,
         delay: &mut impl DelayUs<u32>,
     ) -> Result<(), Infallible> {
-        #[allow(clippy::branches_sharing_code, reason = "it is more readable this way")]
-        if bit {
-            // Write a 1
-
-            // Pull the bus low for 10us
-            self.pin.set_low()?;
-            delay.delay_us(10);
-
-            // Release the bus
-            self.pin.set_high()?;
-
-            // Wait for the end of the timeslot
-            delay.delay_us(55);
-        } else {
-            // Write a 0
+        // Pull the bus low for 10us to write a 1, or for 65us to write a 0
+        let (low, rest) = if bit { (10, 55) } else { (65, 5) };
 
-            // Pull the bus low for 65us
+        // An interrupt stretching the pulse could turn a 1 into a 0
+        cortex_m::interrupt::free(|_| {
             self.pin.set_low()?;
-            delay.delay_us(65);
+            delay.delay_us(low);
 
             // Release the bus
-            self.pin.set_high()?;
+            self.pin.set_high()
+        })?;
 
-            // Wait for the end of the timeslot
-            delay.delay_us(5);
-        }
+        // Wait for the end of the timeslot
+        delay.delay_us(rest);
 
         Ok(())
     }
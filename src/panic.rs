@@ -0,0 +1,280 @@
+//! Panic record kept across resets
+//!
+//! With the `panic-persist` feature, a panic switches the cooler off, writes a [`Record`] to RAM
+//! left out of the startup initialization & resets the MCU. The next boot finds the record with
+//! [`last`], so it can be reported without a debugger attached. A task stalled past its heartbeat
+//! deadline is recorded the same way by [`record_stall`], before the watchdog resets the MCU.
+//!
+//! The running task is tracked for the record: async tasks are wrapped in [`track`], the others
+//! mark themselves with [`enter`].
+
+use core::{
+    fmt::{self, Write},
+    future::Future,
+    mem::{size_of, MaybeUninit},
+    pin::Pin,
+    ptr::{self, addr_of, addr_of_mut},
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+
+pub use fridge_core::event::Task;
+
+/// Marks a valid record, together with the checksum
+const MAGIC: u32 = 0xDEAD_F00D;
+/// Bytes kept of the end of the source file path
+const FILE_LEN: usize = 24;
+/// Bytes kept of the panic message
+const MSG_LEN: usize = 39;
+
+/// The running task, as a [`Task`]
+static CURRENT: AtomicU8 = AtomicU8::new(Task::Unknown as u8);
+
+/// Record of the last panic, not touched by the startup code
+#[link_section = ".uninit.panic"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct Record {
+    magic: u32,
+    /// Seconds since startup
+    pub secs: u32,
+    /// Line in the source file
+    pub line: u32,
+    task: u8,
+    file_len: u8,
+    msg_len: u8,
+    /// Whether a later boot already reported the panic
+    reported: u8,
+    /// Whether the task stalled instead of panicking
+    stalled: u8,
+    file: [u8; FILE_LEN],
+    msg: [u8; MSG_LEN],
+    checksum: u32,
+}
+
+static_assertions::assert_eq_size!(Record, [u8; 84]);
+
+/// Marks a task as running until the returned guard is dropped, then the preempted task again
+pub fn enter(task: Task) -> Entered {
+    // Preemption is nested, so a task preempting between the load & the store has restored the
+    // current task by the time it returns
+    let preempted = CURRENT.load(Ordering::Relaxed);
+    CURRENT.store(task as u8, Ordering::Relaxed);
+    Entered { preempted }
+}
+
+#[must_use = "the task is only marked as running until the guard is dropped"]
+pub struct Entered {
+    preempted: u8,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.store(self.preempted, Ordering::Relaxed);
+    }
+}
+
+/// Wraps an async task, marking it as running whenever it's polled
+pub const fn track<F: Future>(task: Task, future: F) -> Tracked<F> {
+    Tracked { task, future }
+}
+
+pub struct Tracked<F> {
+    task: Task,
+    future: F,
+}
+
+impl<F: Future> Future for Tracked<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let _task = enter(self.task);
+        // SAFETY: the future is never moved out of the pinned wrapper
+        unsafe { self.map_unchecked_mut(|t| &mut t.future) }.poll(cx)
+    }
+}
+
+impl Record {
+    /// A record of a task, without a location or message
+    fn new(task: u8) -> Self {
+        Self {
+            magic: MAGIC,
+            secs: crate::storage::now_secs(),
+            line: 0,
+            task,
+            file_len: 0,
+            msg_len: 0,
+            reported: 0,
+            stalled: 0,
+            file: [0; FILE_LEN],
+            msg: [0; MSG_LEN],
+            checksum: 0,
+        }
+    }
+
+    fn panic(info: &core::panic::PanicInfo) -> Self {
+        let mut record = Self::new(CURRENT.load(Ordering::Relaxed));
+
+        if let Some(location) = info.location() {
+            // Keep the end of the path, the file name matters most
+            let file = location.file();
+            let mut start = file.len().saturating_sub(FILE_LEN);
+            while !file.is_char_boundary(start) {
+                start += 1;
+            }
+            let mut buf = Truncate::new(&mut record.file);
+            let _ = buf.write_str(&file[start..]);
+            record.file_len = buf.len;
+            record.line = location.line();
+        }
+
+        let mut buf = Truncate::new(&mut record.msg);
+        let _ = write!(buf, "{}", info.message());
+        record.msg_len = buf.len;
+
+        record.checksum = record.checksum();
+        record
+    }
+
+    pub const fn task(&self) -> Task {
+        Task::from_u8(self.task)
+    }
+
+    /// Whether the task stalled past its heartbeat deadline instead of panicking
+    pub const fn is_stall(&self) -> bool {
+        self.stalled != 0
+    }
+
+    /// End of the path of the source file
+    pub fn file(&self) -> &str {
+        text(&self.file, self.file_len)
+    }
+
+    /// Name of the source file, without its directories
+    pub fn file_name(&self) -> &str {
+        self.file().rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Start of the panic message
+    pub fn msg(&self) -> &str {
+        text(&self.msg, self.msg_len)
+    }
+
+    /// FNV-1a hash of every field but the checksum itself
+    fn checksum(&self) -> u32 {
+        // SAFETY: the record is plain bytes without padding
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                ptr::from_ref(self).cast::<u8>(),
+                size_of::<Self>() - size_of::<u32>(),
+            )
+        };
+        bytes.iter().fold(0x811C_9DC5, |hash, b| {
+            (hash ^ u32::from(*b)).wrapping_mul(0x0100_0193)
+        })
+    }
+
+    fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.checksum == self.checksum()
+    }
+}
+
+/// Gets the record of the last panic, & whether it wasn't reported by an earlier boot
+///
+/// Must be called once in `init`. The record is kept until the next panic or a power loss.
+pub fn last() -> Option<(Record, bool)> {
+    // SAFETY: only `init`, the panic handler & `record_stall` access the record, the latter two
+    // only after `init`. The RAM keeps its contents through a reset & any bytes are a `Record`, the
+    // checksum tells a real one from garbage.
+    let mut record = unsafe { ptr::read_volatile(addr_of!(RECORD).cast::<Record>()) };
+    if !record.is_valid() {
+        return None;
+    }
+
+    let new = record.reported == 0;
+    if new {
+        record.reported = 1;
+        record.checksum = record.checksum();
+        // SAFETY: as above
+        unsafe { ptr::write_volatile(addr_of_mut!(RECORD).cast::<Record>(), record.clone()) };
+    }
+    Some((record, new))
+}
+
+/// Records a task stalled past its heartbeat deadline, to be reported after the watchdog reset
+pub fn record_stall(task: Task) {
+    let mut record = Record::new(task as u8);
+    record.stalled = 1;
+    let mut buf = Truncate::new(&mut record.msg);
+    let _ = buf.write_str("missed its heartbeat deadline");
+    record.msg_len = buf.len;
+    record.checksum = record.checksum();
+
+    cortex_m::interrupt::free(|_| {
+        // SAFETY: interrupts are disabled & the panic handler, the only other writer, resets
+        unsafe { ptr::write_volatile(addr_of_mut!(RECORD).cast::<Record>(), record) };
+    });
+}
+
+#[cfg(feature = "panic-persist")]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    // The cooler is on PB4, see `init`. Nothing runs anymore to switch it off otherwise.
+    // SAFETY: interrupts are disabled & the write only affects the cooler pin
+    unsafe {
+        (*stm32f0xx_hal::pac::GPIOB::ptr())
+            .bsrr
+            .write(|w| w.br4().set_bit())
+    };
+
+    let record = Record::panic(info);
+    defmt::error!(
+        "Panic in {} at {}:{}: {}",
+        record.task().as_str(),
+        record.file(),
+        record.line,
+        record.msg()
+    );
+    // SAFETY: interrupts are disabled, nothing else accesses the record
+    unsafe { ptr::write_volatile(addr_of_mut!(RECORD).cast::<Record>(), record) };
+
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Valid UTF-8 prefix of a stored string
+fn text(bytes: &[u8], len: u8) -> &str {
+    let bytes = &bytes[..usize::from(len).min(bytes.len())];
+    core::str::from_utf8(bytes).unwrap_or_default()
+}
+
+/// Writes into a buffer, dropping what doesn't fit
+struct Truncate<'a> {
+    buf: &'a mut [u8],
+    len: u8,
+}
+
+impl<'a> Truncate<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+}
+
+impl Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = usize::from(self.len);
+        let mut n = s.len().min(self.buf.len() - start);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.buf[start..start + n].copy_from_slice(&s.as_bytes()[..n]);
+        // The buffers are shorter than 256 bytes
+        #[allow(clippy::cast_possible_truncation)]
+        let n = n as u8;
+        self.len += n;
+        Ok(())
+    }
+}
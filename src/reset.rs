@@ -0,0 +1,32 @@
+//! Cause of the last reset
+
+pub use fridge_core::event::ResetCause;
+use stm32f0xx_hal::pac::RCC;
+
+/// Reads the cause from the reset flags & clears them for the next reset
+///
+/// Must be called before the RCC is configured.
+pub fn read(rcc: &RCC) -> ResetCause {
+    let csr = rcc.csr.read();
+    // Every internal reset pulses the reset pin as well, so the pin is checked last
+    let cause = if csr.lpwrrstf().bit_is_set() {
+        ResetCause::LowPower
+    } else if csr.iwdgrstf().bit_is_set() {
+        ResetCause::Watchdog
+    } else if csr.wwdgrstf().bit_is_set() {
+        ResetCause::WindowWatchdog
+    } else if csr.sftrstf().bit_is_set() {
+        ResetCause::Software
+    } else if csr.porrstf().bit_is_set() {
+        ResetCause::PowerOn
+    } else if csr.oblrstf().bit_is_set() {
+        ResetCause::OptionBytes
+    } else if csr.pinrstf().bit_is_set() {
+        ResetCause::Pin
+    } else {
+        ResetCause::Unknown
+    };
+
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+    cause
+}
@@ -0,0 +1,188 @@
+//! Real-time clock
+//!
+//! The RTC lives in the backup domain, so it keeps counting through resets (but not power loss).
+//! It runs from the 32.768 kHz LSE crystal if one is fitted, or else from the ~40 kHz LSI, which
+//! may be off by a few percent.
+
+use defmt::*;
+use fridge_core::time::{DateTime, MAX_UNIX, MIN_UNIX};
+use stm32f0xx_hal::pac::{PWR, RCC, RTC};
+
+/// Iterations to wait for the LSE to start, about a second at the reset clock
+const LSE_TIMEOUT: u32 = 1_000_000;
+
+/// Clock of the RTC
+#[derive(Debug, Format, Copy, Clone, PartialEq, Eq)]
+pub enum Source {
+    Lse,
+    Lsi,
+}
+
+pub struct Rtc {
+    rtc: RTC,
+    source: Source,
+}
+
+impl Rtc {
+    /// Starts the RTC, unless it kept running through the reset
+    ///
+    /// Must be called before the RCC is configured.
+    pub fn new(rtc: RTC, rcc: &RCC, pwr: &PWR) -> Self {
+        // The backup domain is write protected by the power controller
+        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+        let bdcr = rcc.bdcr.read();
+        let source = if bdcr.rtcen().bit_is_set() {
+            if bdcr.rtcsel().bits() == 0b01 {
+                Source::Lse
+            } else {
+                // The LSI isn't part of the backup domain, so it stops on every reset
+                start_lsi(rcc);
+                Source::Lsi
+            }
+        } else {
+            let source = if start_lse(rcc) {
+                Source::Lse
+            } else {
+                warn!("No LSE crystal, running the RTC from the LSI");
+                start_lsi(rcc);
+                Source::Lsi
+            };
+            let sel = match source {
+                Source::Lse => 0b01,
+                Source::Lsi => 0b10,
+            };
+            // SAFETY: 0b01 & 0b10 select the LSE & LSI
+            rcc.bdcr
+                .modify(|_, w| unsafe { w.rtcsel().bits(sel) }.rtcen().set_bit());
+
+            let mut rtc = Self { rtc, source };
+            rtc.configure(|rtc| {
+                // 1 Hz from the asynchronous & synchronous prescalers, divided by 1 more each
+                let (a, s) = match source {
+                    Source::Lse => (127, 255),
+                    Source::Lsi => (99, 399),
+                };
+                // SAFETY: both fit their fields
+                rtc.prer.write(|w| unsafe { w.prediv_s().bits(s) });
+                rtc.prer.modify(|_, w| unsafe { w.prediv_a().bits(a) });
+            });
+            return rtc;
+        };
+
+        Self { rtc, source }
+    }
+
+    pub const fn source(&self) -> Source {
+        self.source
+    }
+
+    /// The current Unix time, `None` if the clock was never set
+    pub fn now(&self) -> Option<u32> {
+        if self.rtc.isr.read().inits().bit_is_clear() {
+            return None;
+        }
+        // The shadow registers are synchronised within 2 cycles of the RTC clock after a change
+        while self.rtc.isr.read().rsf().bit_is_clear() {}
+
+        // Reading the time locks the date until it is read too, so they are consistent
+        let tr = self.rtc.tr.read();
+        let dr = self.rtc.dr.read();
+        let time = DateTime {
+            year: 2000 + u16::from(bcd(dr.yt().bits(), dr.yu().bits())),
+            month: bcd(u8::from(dr.mt().bit()), dr.mu().bits()),
+            day: bcd(dr.dt().bits(), dr.du().bits()),
+            hour: bcd(tr.ht().bits(), tr.hu().bits()),
+            minute: bcd(tr.mnt().bits(), tr.mnu().bits()),
+            second: bcd(tr.st().bits(), tr.su().bits()),
+        };
+        time.to_unix()
+    }
+
+    /// Sets the clock to a Unix time, returning `false` if the RTC can't hold it
+    pub fn set(&mut self, unix: u32) -> bool {
+        if !(MIN_UNIX..MAX_UNIX).contains(&unix) {
+            return false;
+        }
+
+        let time = DateTime::from_unix(unix);
+        // Between 2001 & 2099
+        let year: u8 = (time.year - 2000).try_into().unwrap_or_default();
+        self.configure(|rtc| {
+            // SAFETY: every field gets a BCD digit in its range
+            rtc.tr.write(|w| unsafe {
+                w.pm()
+                    .clear_bit()
+                    .ht()
+                    .bits(time.hour / 10)
+                    .hu()
+                    .bits(time.hour % 10)
+                    .mnt()
+                    .bits(time.minute / 10)
+                    .mnu()
+                    .bits(time.minute % 10)
+                    .st()
+                    .bits(time.second / 10)
+                    .su()
+                    .bits(time.second % 10)
+            });
+            rtc.dr.write(|w| unsafe {
+                w.yt()
+                    .bits(year / 10)
+                    .yu()
+                    .bits(year % 10)
+                    .wdu()
+                    .bits(time.weekday())
+                    .mt()
+                    .bit(time.month >= 10)
+                    .mu()
+                    .bits(time.month % 10)
+                    .dt()
+                    .bits(time.day / 10)
+                    .du()
+                    .bits(time.day % 10)
+            });
+        });
+        true
+    }
+
+    /// Runs `f` in the initialization mode of the RTC, with its registers unlocked
+    fn configure(&mut self, f: impl FnOnce(&RTC)) {
+        // SAFETY: the keys unlocking the write protection
+        self.rtc.wpr.write(|w| unsafe { w.key().bits(0xCA) });
+        self.rtc.wpr.write(|w| unsafe { w.key().bits(0x53) });
+
+        self.rtc.isr.modify(|_, w| w.init().set_bit());
+        while self.rtc.isr.read().initf().bit_is_clear() {}
+        f(&self.rtc);
+        // The shadow registers are stale until the next synchronisation
+        self.rtc
+            .isr
+            .modify(|_, w| w.init().clear_bit().rsf().clear_bit());
+
+        // SAFETY: any other key locks the registers again
+        self.rtc.wpr.write(|w| unsafe { w.key().bits(0xFF) });
+    }
+}
+
+/// Starts the LSE, returning whether a crystal is fitted
+fn start_lse(rcc: &RCC) -> bool {
+    rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+    for _ in 0..LSE_TIMEOUT {
+        if rcc.bdcr.read().lserdy().bit_is_set() {
+            return true;
+        }
+    }
+    rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+    false
+}
+
+fn start_lsi(rcc: &RCC) {
+    rcc.csr.modify(|_, w| w.lsion().set_bit());
+    while rcc.csr.read().lsirdy().bit_is_clear() {}
+}
+
+const fn bcd(tens: u8, units: u8) -> u8 {
+    tens * 10 + units
+}
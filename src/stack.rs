@@ -0,0 +1,44 @@
+//! Stack usage measurement
+//!
+//! The free RAM between the statics & the stack is painted with a pattern at startup. The stack
+//! grows down into it, so the painted words left at the bottom are stack that was never used.
+
+use core::ptr::{addr_of_mut, read_volatile, write_volatile};
+
+/// Pattern the unused stack is filled with
+const PAINT: u32 = 0xCCCC_CCCC;
+/// Words below the current stack pointer left unpainted, for the frame of [`paint`] itself
+const MARGIN: usize = 16;
+
+extern "C" {
+    /// Start of the free RAM after the statics, provided by `cortex-m-rt`
+    static mut __sheap: u32;
+}
+
+/// Paints the unused stack, must be called early in `init`
+#[inline(never)]
+pub fn paint() {
+    let sp = cortex_m::register::msp::read() as *mut u32;
+    // SAFETY: everything between the statics & the stack pointer is unused
+    unsafe {
+        let mut ptr = addr_of_mut!(__sheap);
+        while ptr < sp.wrapping_sub(MARGIN) {
+            write_volatile(ptr, PAINT);
+            ptr = ptr.add(1);
+        }
+    }
+}
+
+/// Bytes of stack that were never used since [`paint`]
+pub fn headroom() -> u32 {
+    let mut words = 0;
+    // SAFETY: the painted words are never used by anything but the stack, reading them is fine
+    unsafe {
+        let mut ptr = addr_of_mut!(__sheap);
+        while read_volatile(ptr) == PAINT {
+            words += 1;
+            ptr = ptr.add(1);
+        }
+    }
+    words * 4
+}
@@ -0,0 +1,94 @@
+//! State of the fridge shown on the status LED
+//!
+//! Tasks mark the states they are in with [`set`], & the `blinky` task blinks the pattern of the
+//! most important one, so a unit can be diagnosed by looking at it. Patterns repeat every
+//! [`STEPS`] steps of [`STEP_MILLIS`], a 2 s cycle:
+//!
+//! | State          | Pattern                  |
+//! |----------------|--------------------------|
+//! | `Normal`       | a short flash            |
+//! | `Cooling`      | 1 s on, 1 s off          |
+//! | `Autotune`     | three short flashes      |
+//! | `Alarm`        | fast blinking            |
+//! | `SensorFault`  | two short flashes        |
+//! | `Config`       | steady on                |
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Milliseconds of a step of a pattern
+pub const STEP_MILLIS: u32 = 100;
+/// Steps of a pattern
+pub const STEPS: u32 = 20;
+
+/// State of the fridge, from the least to the most important to show
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum State {
+    /// Running without anything else to show
+    Normal = 0,
+    /// The cooler is on
+    Cooling,
+    /// The controller gains are being tuned
+    Autotune,
+    /// A temperature alarm is raised
+    Alarm,
+    /// The thermometer doesn't respond
+    SensorFault,
+    /// Waiting in the bootloader or for configuration
+    Config,
+}
+
+const STATES: usize = State::Config as usize + 1;
+
+/// Whether the fridge is in each state, only stored & loaded as thumbv6m has no atomic
+/// read-modify-write
+static ACTIVE: [AtomicBool; STATES] = [const { AtomicBool::new(false) }; STATES];
+
+/// Marks whether the fridge is in a state
+pub fn set(state: State, active: bool) {
+    ACTIVE[state as usize].store(active, Ordering::Relaxed);
+}
+
+/// The most important state the fridge is in
+pub fn shown() -> State {
+    [
+        State::Config,
+        State::SensorFault,
+        State::Alarm,
+        State::Autotune,
+        State::Cooling,
+    ]
+    .into_iter()
+    .find(|state| ACTIVE[*state as usize].load(Ordering::Relaxed))
+    .unwrap_or(State::Normal)
+}
+
+impl State {
+    /// Steps of the pattern the LED is on, a bit per step starting at the lowest
+    const fn pattern(self) -> u32 {
+        match self {
+            Self::Normal => 0b1,
+            Self::Cooling => 0b11_1111_1111,
+            Self::Autotune => 0b1_0101,
+            Self::Alarm => 0b0101_0101_0101_0101_0101,
+            Self::SensorFault => 0b101,
+            Self::Config => 0b1111_1111_1111_1111_1111,
+        }
+    }
+
+    /// Whether the LED is on at a step of the pattern, repeating every [`STEPS`]
+    pub const fn is_on(self, step: u32) -> bool {
+        self.pattern() >> (step % STEPS) & 1 == 1
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Cooling => "cooling",
+            Self::Autotune => "autotune",
+            Self::Alarm => "alarm",
+            Self::SensorFault => "sensor fault",
+            Self::Config => "config",
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Ring buffer of records that can be dumped in chunks
+//!
+//! Every record written gets a sequence number. A [`Cursor`] remembers the sequence number of the
+//! next record of a dump, so the log can be locked for a single chunk at a time & written to in
+//! between. Records overwritten before the dump reached them are counted as lost.
+
+use core::{iter::Chain, slice};
+
+use fridge_core::record::extend_secs;
+use heapless::Vec;
+use num_traits::AsPrimitive;
+
+use super::{now_secs, StoredAggregate, StoredEvent, StoredTemp};
+
+/// A record with a timestamp
+pub trait Record: Clone {
+    /// Seconds since startup, only the low 24 bits
+    fn secs(&self) -> u32;
+
+    /// Full seconds since startup, for a record stored at or before `now`
+    fn full_secs(&self, now: u32) -> u32 {
+        extend_secs(self.secs(), now)
+    }
+}
+
+impl Record for StoredTemp {
+    fn secs(&self) -> u32 {
+        self.secs()
+    }
+}
+
+impl Record for StoredAggregate {
+    fn secs(&self) -> u32 {
+        self.secs()
+    }
+}
+
+impl Record for StoredEvent {
+    fn secs(&self) -> u32 {
+        self.secs()
+    }
+}
+
+/// Records to dump
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Range {
+    All,
+    /// The most recent records
+    Last(u32),
+    /// Records stored at or after the given seconds since startup
+    Since(u32),
+}
+
+/// Position of a dump in a [`Log`]
+///
+/// The end is fixed when the cursor is made, so a dump ends even while records keep coming in.
+#[derive(Debug, Copy, Clone)]
+pub struct Cursor {
+    /// Sequence number of the next record
+    next: u32,
+    /// Sequence number after the last record
+    end: u32,
+}
+
+impl Cursor {
+    /// Whether every record of the dump was read
+    pub const fn is_done(&self) -> bool {
+        self.next >= self.end
+    }
+}
+
+/// Records read from a [`Log`]
+pub struct Chunk<T, const C: usize> {
+    pub records: Vec<T, C>,
+    /// Number of records overwritten before they could be read, preceding `records`
+    pub lost: u32,
+}
+
+/// Records of a [`Log`] from the oldest to the most recent
+pub type OldestOrdered<'a, T> = Chain<slice::Iter<'a, T>, slice::Iter<'a, T>>;
+
+/// Ring buffer overwriting its oldest record when full
+///
+/// Like [`heapless::HistoryBuffer`], but the most recent record can be changed in place.
+struct Ring<T, const N: usize> {
+    buf: Vec<T, N>,
+    /// Index of the next record, the oldest one once the buffer is full
+    write_at: usize,
+}
+
+impl<T, const N: usize> Ring<T, N> {
+    const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            write_at: 0,
+        }
+    }
+
+    fn write(&mut self, record: T) {
+        if let Err(record) = self.buf.push(record) {
+            self.buf[self.write_at] = record;
+        }
+        self.write_at = (self.write_at + 1) % N;
+    }
+
+    fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Index of the most recent record, past the end while the buffer is empty
+    const fn recent_index(&self) -> usize {
+        match self.write_at.checked_sub(1) {
+            Some(i) => i,
+            None => N - 1,
+        }
+    }
+
+    fn recent(&self) -> Option<&T> {
+        self.buf.get(self.recent_index())
+    }
+
+    fn recent_mut(&mut self) -> Option<&mut T> {
+        let i = self.recent_index();
+        self.buf.get_mut(i)
+    }
+
+    fn clear(&mut self) {
+        self.buf.clear();
+        self.write_at = 0;
+    }
+
+    fn oldest_ordered(&self) -> OldestOrdered<'_, T> {
+        // Until the buffer is full, the next record is written at its end
+        let (recent, oldest) = self.buf.split_at(self.write_at);
+        oldest.iter().chain(recent)
+    }
+}
+
+pub struct Log<T, const N: usize> {
+    buf: Ring<T, N>,
+    /// Number of records ever written, the sequence number of the next record
+    written: u32,
+}
+
+impl<T: Record, const N: usize> Log<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: Ring::new(),
+            written: 0,
+        }
+    }
+
+    pub fn write(&mut self, record: T) {
+        self.buf.write(record);
+        self.written = self.written.saturating_add(1);
+    }
+
+    /// The most recent record, to change it in place, e.g. to count a repeat of it
+    ///
+    /// The record keeps its sequence number, dumps that already read it don't see the change.
+    pub fn recent_mut(&mut self) -> Option<&mut T> {
+        self.buf.recent_mut()
+    }
+
+    /// Erases all records
+    ///
+    /// Sequence numbers keep counting, so dumps in progress see the records as lost.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
+    pub fn oldest_ordered(&self) -> OldestOrdered<'_, T> {
+        self.buf.oldest_ordered()
+    }
+
+    pub fn recent(&self) -> Option<&T> {
+        self.buf.recent()
+    }
+
+    /// Number of records stored
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.len() == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Sequence number of the oldest record
+    fn first(&self) -> u32 {
+        // The length is at most N
+        let len: u32 = self.buf.len().as_();
+        self.written - len
+    }
+
+    /// Starts a dump of `range`
+    pub fn cursor(&self, range: Range) -> Cursor {
+        let first = self.first();
+        let next = match range {
+            Range::All => first,
+            Range::Last(count) => self.written.saturating_sub(count).max(first),
+            Range::Since(secs) => {
+                let now = now_secs();
+                let skip: u32 = self
+                    .oldest_ordered()
+                    .position(|r| r.full_secs(now) >= secs)
+                    .unwrap_or(self.buf.len())
+                    .as_();
+                first + skip
+            }
+        };
+
+        Cursor {
+            next,
+            end: self.written,
+        }
+    }
+
+    /// Reads the next chunk of at most `C` records of a dump, advancing the cursor
+    pub fn read<const C: usize>(&self, cursor: &mut Cursor) -> Chunk<T, C> {
+        let first = self.first();
+        let lost = first.min(cursor.end).saturating_sub(cursor.next);
+        cursor.next += lost;
+
+        let skip: usize = (cursor.next.max(first) - first).as_();
+        let count: usize = cursor.end.saturating_sub(cursor.next).as_();
+        let records: Vec<T, C> = self
+            .oldest_ordered()
+            .skip(skip)
+            .take(count.min(C))
+            .cloned()
+            .collect();
+        // At most C
+        let read: u32 = records.len().as_();
+        cursor.next += read;
+
+        Chunk { records, lost }
+    }
+}
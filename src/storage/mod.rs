@@ -1,185 +1,312 @@
-use fixed::types::I6F2;
-use heapless::{HistoryBuffer, OldestOrdered};
+mod log;
+
+#[cfg(feature = "precise-temps")]
+pub use fridge_core::record::PreciseTemp as StoredTemp;
+#[cfg(not(feature = "precise-temps"))]
+pub use fridge_core::record::StoredTemp;
+use fridge_core::{
+    event::{repeat_delta, PAYLOAD_SIZE, REPEAT_SIZE},
+    record::Aggregator,
+    stats::Accumulator,
+};
+pub use fridge_core::{
+    event::{Event, EventCode},
+    record::{StoredAggregate, TempRecord, Tier},
+    stats::Stats,
+};
 use num_traits::AsPrimitive;
 use rtic_monotonics::{stm32::Tim2 as Mono, Monotonic};
 use rtic_sync::channel::{Sender, TrySendError};
 
+pub use self::log::{Chunk, Cursor, Log, OldestOrdered, Range, Record};
 use crate::thermometer::Temperature;
 
 pub const CHAN_SIZE: usize = 1;
+/// Number of events the controller can queue for storing
+pub const EVENT_CHAN_SIZE: usize = 4;
+/// Number of stored temperatures, 2 minutes at a 2 s period
+///
+/// Older temperatures are kept by the minute tier, so this was cut from 100 records to make room
+/// for the aggregates.
+pub const TEMP_COUNT: usize = 60;
+/// Number of stored per-minute aggregates, an hour
+pub const MINUTE_COUNT: usize = 60;
+/// Number of stored per-hour aggregates, a week
+pub const HOUR_COUNT: usize = 7 * 24;
+/// Number of stored events
+pub const EVENT_COUNT: usize = 16;
+
+/// Bytes of RAM the stored records may take, of the 6K of the MCU
+///
+/// The records took 656 bytes before the aggregates, but a week of hourly minimums, averages &
+/// maximums alone needs 504 bytes. The records take 1864 bytes (1924 with `precise-temps`), under
+/// a third of the RAM, leaving the rest to the stacks & buffers of the tasks.
+const RECORD_BUDGET: usize = 2048;
+const _: () = assert!(
+    TEMP_COUNT * StoredTemp::SIZE
+        + (MINUTE_COUNT + HOUR_COUNT) * StoredAggregate::SIZE
+        + EVENT_COUNT * size_of::<StoredEvent>()
+        <= RECORD_BUDGET,
+    "Stored records exceed their RAM budget"
+);
+/// Number of records read from storage at a time while dumping
+pub const DUMP_CHUNK: usize = 8;
 
 pub struct Storage<const N: usize, const E: usize> {
-    temps: HistoryBuffer<StoredTemp, N>,
-    events: HistoryBuffer<StoredEvent, E>,
+    temps: Log<StoredTemp, N>,
+    /// Whether the cooler was on for each stored temperature, the most recent in the lowest bit
+    cooler_on: u128,
+    minutes: Log<StoredAggregate, MINUTE_COUNT>,
+    hours: Log<StoredAggregate, HOUR_COUNT>,
+    /// Aggregates of the current minute & hour
+    minute: Aggregator,
+    hour: Aggregator,
+    events: Log<StoredEvent, E>,
+    /// Events dropped by the rate limits of their codes
+    dropped_events: u32,
+    /// Unix time at startup, `None` until the real-time clock is known to be set
+    epoch: Option<u32>,
     tx: Sender<'static, StoredTemp, CHAN_SIZE>,
+    event_tx: Sender<'static, StoredEvent, CHAN_SIZE>,
 }
 
 impl<const N: usize, const E: usize> Storage<N, E> {
-    pub const fn new(tx: Sender<'static, StoredTemp, CHAN_SIZE>) -> Self {
+    /// Creates the storage, re-sending written temperatures & events to `tx` & `event_tx` for
+    /// watching
+    pub const fn new(
+        tx: Sender<'static, StoredTemp, CHAN_SIZE>,
+        event_tx: Sender<'static, StoredEvent, CHAN_SIZE>,
+    ) -> Self {
+        const { assert!(N <= u128::BITS as usize, "Cooler states don't fit") };
         Self {
-            temps: HistoryBuffer::new(),
-            events: HistoryBuffer::new(),
+            temps: Log::new(),
+            cooler_on: 0,
+            minutes: Log::new(),
+            hours: Log::new(),
+            minute: Aggregator::new(Tier::Minute),
+            hour: Aggregator::new(Tier::Hour),
+            events: Log::new(),
+            dropped_events: 0,
+            epoch: None,
             tx,
+            event_tx,
         }
     }
 
-    pub fn write(&mut self, temp: Temperature) {
-        let temp = StoredTemp::now_from_temp(temp);
+    /// Stores a temperature & whether the cooler was on, aggregating it into the other tiers
+    pub fn write(&mut self, temp: Temperature, on: bool) {
+        let secs = now_secs();
+        if let Some(minute) = self.minute.add(secs, temp) {
+            self.minutes.write(minute);
+        }
+        if let Some(hour) = self.hour.add(secs, temp) {
+            self.hours.write(hour);
+        }
+
+        let temp = StoredTemp::from_temp(secs, temp);
         self.temps.write(temp);
+        self.cooler_on = (self.cooler_on << 1) | u128::from(on);
 
         match self.tx.try_send(temp) {
             Ok(()) | Err(TrySendError::Full(_)) => (),
             Err(TrySendError::NoReceiver(_)) => unreachable!("No receiver"),
         }
     }
+    /// Stores an event, counting a repeat of the most recent event in its record instead
+    ///
+    /// Only codes that [coalesce](EventCode::coalesces) are counted, & only within 18 h of the
+    /// first occurrence, later repeats start a new record.
+    ///
+    /// New records of a code are limited to one per [`EventCode::min_interval`], other events of
+    /// the code are dropped until then.
     pub fn write_event(&mut self, event: StoredEvent) {
-        self.events.write(event);
-    }
+        let now = now_secs();
+        let record = match self.events.recent_mut() {
+            Some(recent) if recent.is_repeated_by(&event, now) => {
+                recent.repeat(&event, now);
+                recent.clone()
+            }
+            _ => {
+                let limited = self
+                    .events
+                    .oldest_ordered()
+                    .filter(|e| e.code == event.code)
+                    .last()
+                    .is_some_and(|e| event.code.is_rate_limited(e.full_secs(now), now));
+                if limited {
+                    self.dropped_events = self.dropped_events.saturating_add(1);
+                    return;
+                }
+                self.events.write(event.clone());
+                event
+            }
+        };
 
-    pub fn temp_oldest(&self) -> OldestOrdered<'_, StoredTemp, N> {
-        self.temps.oldest_ordered()
-    }
-    pub fn temp_recent(&self) -> Option<StoredTemp> {
-        self.temps.recent().copied()
+        match self.event_tx.try_send(record) {
+            Ok(()) | Err(TrySendError::Full(_)) => (),
+            Err(TrySendError::NoReceiver(_)) => unreachable!("No receiver"),
+        }
     }
 
-    pub fn event_oldest(&self) -> OldestOrdered<'_, StoredEvent, E> {
-        self.events.oldest_ordered()
-    }
-    pub fn event_recent(&self) -> Option<&StoredEvent> {
-        self.events.recent()
+    /// Erases all stored temperatures, aggregates & events
+    pub fn erase(&mut self) {
+        self.temps.clear();
+        self.cooler_on = 0;
+        self.minutes.clear();
+        self.hours.clear();
+        self.minute.clear();
+        self.hour.clear();
+        self.events.clear();
     }
-}
-
-#[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct StoredTemp {
-    /// Seconds since startup (LSB u24)
-    secs: [u8; 3],
-    /// Reduced precision temperature
-    value: I6F2,
-}
 
-static_assertions::assert_eq_size!(StoredTemp, u32);
-
-impl StoredTemp {
-    #[inline]
-    fn new(secs: u32, value: I6F2) -> Self {
-        Self {
-            secs: secs.to_le_bytes()[..3].try_into().unwrap(),
-            value,
+    /// Statistics of the temperatures stored at or after `since` seconds since startup
+    ///
+    /// Temperatures within `target ± band` count as on target. Returns `None` if there are no
+    /// temperatures in the window.
+    pub fn stats(&self, since: u32, target: Temperature, band: Temperature) -> Option<Stats> {
+        let mut acc = Accumulator::new(target, band);
+        let now = now_secs();
+        let len = self.temps.len();
+        for (i, temp) in self.temps.oldest_ordered().enumerate() {
+            if temp.full_secs(now) >= since {
+                let on = (self.cooler_on >> (len - 1 - i)) & 1 != 0;
+                acc.add(temp.value(), on);
+            }
         }
+        acc.finish()
     }
 
-    #[inline]
-    fn now(value: I6F2) -> Self {
-        let secs = Mono::now().duration_since_epoch().to_secs();
-        Self::new(secs.as_(), value)
+    /// Unix time at startup, adding it to the seconds of a record gives its Unix time
+    pub const fn epoch(&self) -> Option<u32> {
+        self.epoch
+    }
+    /// Sets the epoch from the current Unix time
+    pub fn set_epoch(&mut self, unix: u32) {
+        self.epoch = Some(unix.wrapping_sub(now_secs()));
     }
 
-    #[inline]
-    pub fn now_from_temp(temp: Temperature) -> Self {
-        Self::now(temp.saturating_to_num())
+    /// Stored temperatures, for dumping with a [`Cursor`]
+    pub const fn temps(&self) -> &Log<StoredTemp, N> {
+        &self.temps
+    }
+    /// Stored per-minute aggregates, for dumping with a [`Cursor`]
+    pub const fn minutes(&self) -> &Log<StoredAggregate, MINUTE_COUNT> {
+        &self.minutes
+    }
+    /// Stored per-hour aggregates, for dumping with a [`Cursor`]
+    pub const fn hours(&self) -> &Log<StoredAggregate, HOUR_COUNT> {
+        &self.hours
+    }
+    /// Stored events, for dumping with a [`Cursor`]
+    pub const fn events(&self) -> &Log<StoredEvent, E> {
+        &self.events
+    }
+    /// Number of events dropped by the rate limits since startup
+    pub const fn dropped_events(&self) -> u32 {
+        self.dropped_events
     }
 
-    #[inline]
-    pub const fn secs(self) -> u32 {
-        u32::from_le_bytes([self.secs[0], self.secs[1], self.secs[2], 0])
+    pub fn temp_oldest(&self) -> OldestOrdered<'_, StoredTemp> {
+        self.temps.oldest_ordered()
+    }
+    pub fn temp_recent(&self) -> Option<StoredTemp> {
+        self.temps.recent().copied()
     }
 
-    #[inline]
-    pub fn value(self) -> Temperature {
-        self.value.to_num()
+    pub fn event_oldest(&self) -> OldestOrdered<'_, StoredEvent> {
+        self.events.oldest_ordered()
+    }
+    pub fn event_recent(&self) -> Option<&StoredEvent> {
+        self.events.recent()
     }
 }
 
-impl From<StoredTemp> for (u32, Temperature) {
-    fn from(value: StoredTemp) -> Self {
-        (value.secs(), value.value())
-    }
+/// Seconds since startup, as stored in records
+#[inline]
+pub fn now_secs() -> u32 {
+    Mono::now().duration_since_epoch().to_secs().as_()
 }
 
 #[derive(Debug, Clone)]
 #[repr(C)]
 pub struct StoredEvent {
-    /// Seconds since startup (LSB u24)
+    /// Seconds since startup of the first occurrence (LSB u24)
     secs: [u8; 3],
     /// Event code
     pub code: EventCode,
-    /// UTF-8 Null-terminated message
-    msg: [u8; 12],
+    /// Fields of the event, depending on the code, & the repeats of the event if the code
+    /// coalesces
+    payload: [u8; PAYLOAD_SIZE],
 }
 
 static_assertions::assert_eq_size!(StoredEvent, [u8; 16]);
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[repr(u8)]
-pub enum EventCode {
-    /// Unknown event type
-    Unknown = 0,
-    /// Temperature sensor error
-    TempSensorError,
-    /// Temperature sensor resolution changed
-    TempSensorResolutionChanged,
-    /// PID controller error
-    PidError,
-    /// PID controller target changed
-    PidTargetChanged,
-    /// PID parameters changed
-    PidParamsChanged,
-}
+/// Offset of the repeats in the payload of an event
+const REPEAT: usize = PAYLOAD_SIZE - REPEAT_SIZE;
 
 impl StoredEvent {
-    pub fn new(secs: u32, code: EventCode, msg: [u8; 12]) -> Self {
+    pub fn new(secs: u32, event: &Event) -> Self {
+        let code = event.code();
+        let mut payload = event.to_payload();
+        if code.coalesces() {
+            payload[PAYLOAD_SIZE - 1] = 1;
+        }
         Self {
             secs: secs.to_le_bytes()[..3].try_into().unwrap(),
             code,
-            msg,
+            payload,
         }
     }
 
-    pub fn new_str(secs: u32, code: EventCode, msg: &str) -> Self {
-        let mut bytes = [0u8; 12];
+    pub fn now(event: &Event) -> Self {
+        Self::new(now_secs(), event)
+    }
+
+    #[inline]
+    pub const fn secs(&self) -> u32 {
+        u32::from_le_bytes([self.secs[0], self.secs[1], self.secs[2], 0])
+    }
 
-        let len = msg.len();
-        if len >= 12 {
-            // Truncate the message
-            bytes[..12].copy_from_slice(&msg.as_bytes()[..12]);
-        } else {
-            // Copy the message and null-terminate
-            bytes[..len].copy_from_slice(msg.as_bytes());
-            bytes[len] = 0;
+    /// Seconds from the first to the last occurrence
+    const fn last_delta(&self) -> u16 {
+        if !self.code.coalesces() {
+            return 0;
         }
+        u16::from_le_bytes([self.payload[REPEAT], self.payload[REPEAT + 1]])
+    }
 
-        Self::new(secs, code, bytes)
+    /// Full seconds since startup of the last occurrence, for an event stored at or before `now`
+    pub fn full_last_secs(&self, now: u32) -> u32 {
+        self.full_secs(now) + u32::from(self.last_delta())
     }
 
-    pub fn now(code: EventCode, msg: &str) -> Self {
-        let secs = Mono::now().duration_since_epoch().to_secs();
-        Self::new_str(secs.as_(), code, msg)
+    /// Number of occurrences, up to 255
+    pub const fn count(&self) -> u8 {
+        if !self.code.coalesces() {
+            return 1;
+        }
+        self.payload[PAYLOAD_SIZE - 1]
     }
 
-    #[inline]
-    pub const fn secs(&self) -> u32 {
-        u32::from_le_bytes([self.secs[0], self.secs[1], self.secs[2], 0])
+    pub fn event(&self) -> Event {
+        Event::from_payload(self.code, &self.payload)
     }
 
-    pub fn msg(&self) -> &str {
-        let len = self.msg.iter().position(|&b| b == 0).unwrap_or(12);
-        // SAFETY: The message is always valid UTF-8
-        unsafe { core::str::from_utf8_unchecked(&self.msg[..len]) }
+    /// Whether `other` is the same event, occurring again soon enough to be counted in this
+    /// record
+    fn is_repeated_by(&self, other: &Self, now: u32) -> bool {
+        self.code.coalesces()
+            && self.code == other.code
+            && self.payload[..REPEAT] == other.payload[..REPEAT]
+            && repeat_delta(self.full_secs(now), other.full_secs(now)).is_some()
     }
-}
 
-impl EventCode {
-    pub const fn as_str(self) -> &'static str {
-        match self {
-            Self::Unknown => "Unknown",
-            Self::TempSensorError => "Temperature sensor error",
-            Self::TempSensorResolutionChanged => "Temperature sensor resolution changed",
-            Self::PidError => "PID controller error",
-            Self::PidTargetChanged => "PID controller target changed",
-            Self::PidParamsChanged => "PID parameters changed",
-        }
+    /// Counts an occurrence of the event at the time of `other`, a repeat of it
+    fn repeat(&mut self, other: &Self, now: u32) {
+        let Some(delta) = repeat_delta(self.full_secs(now), other.full_secs(now)) else {
+            return;
+        };
+        self.payload[REPEAT..REPEAT + 2].copy_from_slice(&delta.to_le_bytes());
+        self.payload[PAYLOAD_SIZE - 1] = self.count().saturating_add(1);
     }
 }
@@ -3,7 +3,8 @@
 use core::convert::Infallible;
 
 use defmt::{unreachable, *};
-use rtic::Mutex;
+use num_traits::AsPrimitive;
+use rtic::mutex_prelude::*;
 use rtic_monotonics::{
     stm32::{Tim2 as Mono, *},
     Monotonic,
@@ -11,24 +12,107 @@ use rtic_monotonics::{
 use stm32f0xx_hal::{delay::Delay, prelude::*};
 
 use crate::{
-    controller::{pid::PidController, Controller},
+    controller::{
+        pid::{PidController, PidTerms, KD, KI, KP, TARGET_TEMP},
+        Controller, CoolerMode,
+    },
+    ds18b20::Ds18b20,
+    event::SensorError,
     onewire::Error,
-    storage::{EventCode, StoredEvent},
+    panic::Task,
+    status_led::{self, State},
+    storage::{now_secs, Event, StoredEvent},
     thermometer::Temperature,
 };
 
-pub const TARGET_TEMP: Temperature = Temperature::const_from_int(5);
-const KP: Temperature = Temperature::from_bits(1 << 4);
-const KI: Temperature = Temperature::from_bits(1 << 2);
-const KD: Temperature = Temperature::from_bits(1 << 1);
+/// Counters of the controller since startup
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Counters {
+    pub ticks: u32,
+    /// Ticks the cooler was on
+    pub on_ticks: u32,
+    /// Sensor errors with a bad CRC
+    pub crc_errors: u16,
+    /// Sensor errors due to a timeout
+    pub timeouts: u16,
+    /// Any other sensor errors, e.g. a shorted bus or no sensor responding
+    pub bus_errors: u16,
+    /// Events lost because the storage was too busy to take them
+    pub lost_events: u16,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Self {
+            ticks: 0,
+            on_ticks: 0,
+            crc_errors: 0,
+            timeouts: 0,
+            bus_errors: 0,
+            lost_events: 0,
+        }
+    }
+
+    /// Counts a sensor error
+    pub fn sensor_error<E>(&mut self, e: &Error<E>) {
+        let count = match e {
+            Error::CrcMismatch => &mut self.crc_errors,
+            Error::Timeout => &mut self.timeouts,
+            _ => &mut self.bus_errors,
+        };
+        *count = count.saturating_add(1);
+    }
+
+    /// Percentage of ticks the cooler was on
+    pub fn duty(&self) -> u32 {
+        if self.ticks == 0 {
+            return 0;
+        }
+        // Widened so it can't overflow, the result is at most 100
+        (u64::from(self.on_ticks) * 100 / u64::from(self.ticks)).as_()
+    }
+}
+
+/// State of the controller after a tick, sent to the terminal for watching
+#[derive(Debug, Copy, Clone)]
+pub struct Tick {
+    /// Seconds since startup
+    pub secs: u32,
+    pub temp: Temperature,
+    pub target: Temperature,
+    pub terms: PidTerms,
+    /// Output of the controller, 0 to 255
+    pub output: u8,
+    /// Whether the cooler is on
+    pub on: bool,
+}
 
 #[cfg_attr(feature = "sizing", inline(never))]
 pub async fn temp_controller(mut cx: crate::app::temp_controller::Context<'_>, mut delay: Delay) {
     let mut now = Mono::now();
 
     let mut last_res = None;
+    // Whether the sensor responded since it was selected or lost
+    let mut found = false;
+    let mut wound_up = false;
 
     loop {
+        let sensor = cx.shared.sensor.lock(|addr| *addr);
+        if cx.local.water_temp.address() != sensor {
+            *cx.local.water_temp = Ds18b20::new(sensor);
+            // The new sensor needs to be configured
+            last_res = None;
+            found = false;
+        }
+
+        let (target, gains) = (&mut cx.shared.target, &mut cx.shared.gains).lock(|t, g| (*t, *g));
+        if cx.local.pid.get_target() != target {
+            cx.local.pid.set_target(target);
+        }
+        if cx.local.pid.gains() != gains {
+            cx.local.pid.set_gains(gains);
+        }
+
         let resolution = cx.shared.resolution.lock(|res| *res);
         if last_res != Some(resolution) {
             last_res = Some(resolution);
@@ -38,28 +122,45 @@ pub async fn temp_controller(mut cx: crate::app::temp_controller::Context<'_>, m
                     .set_resolution(cx.local.wire, &mut delay, resolution)
             {
                 error!("Error setting resolution: {}", e);
-
-                let event = StoredEvent::now(EventCode::TempSensorError, e.as_str());
-                let _ = cx.local.e_tx.send(event).await;
+                cx.shared.counters.lock(|c| c.sensor_error(&e));
+                sensor_error(&mut cx, &mut found, (&e).into());
 
                 last_res = None;
             } else {
-                let event =
-                    StoredEvent::now(EventCode::TempSensorResolutionChanged, resolution.as_str());
-                let _ = cx.local.e_tx.send(event).await;
+                let address = cx.local.water_temp.address().0;
+                log_event(
+                    &mut cx,
+                    &Event::TempSensorResolutionChanged {
+                        bits: resolution.bits(),
+                        address,
+                    },
+                );
             }
         }
 
         match temp_controller_inner(&mut cx, &mut delay).await {
-            Ok(()) => {}
+            Ok(()) => {
+                status_led::set(State::SensorFault, false);
+                if !found {
+                    found = true;
+                    let address = cx.local.water_temp.address().0;
+                    log_event(&mut cx, &Event::TempSensorFound { address });
+                }
+                // Only the start of a wind-up is an error, the rate limit covers flapping
+                let was_wound_up = core::mem::replace(&mut wound_up, cx.local.pid.is_wound_up());
+                if wound_up && !was_wound_up {
+                    warn!("PID controller wound up");
+                    log_event(&mut cx, &Event::PidError);
+                }
+            }
             Err(e) => {
                 error!("Error: {}", e);
-
-                let event = StoredEvent::now(EventCode::TempSensorError, e.as_str());
-                let _ = cx.local.e_tx.send(event).await;
+                cx.shared.counters.lock(|c| c.sensor_error(&e));
+                sensor_error(&mut cx, &mut found, (&e).into());
             }
         }
 
+        crate::heartbeat::beat(Task::TempController);
         now += 2.secs();
         Mono::delay_until(now).await;
     }
@@ -84,21 +185,86 @@ async fn temp_controller_inner<'a>(
         cooler_on
     );
 
-    cx.shared.cooler.lock(|cooler| {
-        if cooler_on > 127 {
-            cooler.set_high()
+    // The controller keeps running in manual modes, so it is up to date when switching back
+    cx.shared.output.lock(|output| *output = cooler_on);
+
+    let on = match cx.shared.cooler_mode.lock(|mode| *mode) {
+        CoolerMode::Auto => cooler_on > 127,
+        CoolerMode::On => true,
+        CoolerMode::Off => false,
+    };
+    let was_on = cx.shared.cooler.lock(|cooler| {
+        let was_on = cooler.is_set_high()?;
+        if on {
+            cooler.set_high()?;
         } else {
-            cooler.set_low()
+            cooler.set_low()?;
         }
+        Ok::<_, Infallible>(was_on)
     })?;
 
-    if cx.local.tx.send(temp).await.is_err() {
+    if cx.local.tx.send((temp, on)).await.is_err() {
         unreachable!("Receiver dropped");
     }
 
+    let duty = cx.shared.counters.lock(|c| {
+        c.ticks += 1;
+        c.on_ticks += u32::from(on);
+        c.duty()
+    });
+    if on != was_on {
+        log_event(
+            cx,
+            &Event::CoolerSwitched {
+                on,
+                duty: duty.as_(),
+            },
+        );
+    }
+
+    // Only watched by the terminal, so don't wait for it
+    let tick = Tick {
+        secs: now_secs(),
+        temp,
+        target: cx.local.pid.get_target(),
+        terms: cx.local.pid.terms(),
+        output: cooler_on,
+        on,
+    };
+    let _ = cx.local.tick_tx.try_send(tick);
+
     Ok(())
 }
 
+/// Logs a sensor error, & the loss of the sensor if it responded before
+///
+/// The status LED shows the fault until the next successful measurement.
+fn sensor_error(
+    cx: &mut crate::app::temp_controller::Context<'_>,
+    found: &mut bool,
+    error: SensorError,
+) {
+    status_led::set(State::SensorFault, true);
+    let address = cx.local.water_temp.address().0;
+    if core::mem::take(found) {
+        log_event(cx, &Event::TempSensorLost { error, address });
+    }
+    log_event(cx, &Event::TempSensorError { error, address });
+}
+
+/// Queues an event for storing, timestamped now
+///
+/// Never waits, so the controller keeps its period. The event is counted as lost if the queue is
+/// full.
+fn log_event(cx: &mut crate::app::temp_controller::Context<'_>, event: &Event) {
+    if cx.local.e_tx.try_send(StoredEvent::now(event)).is_err() {
+        warn!("Event queue full, dropping {}", event.code().as_str());
+        cx.shared
+            .counters
+            .lock(|c| c.lost_events = c.lost_events.saturating_add(1));
+    }
+}
+
 pub fn new_pid() -> PidController {
     PidController::new(TARGET_TEMP, KP, KI, KD)
 }
@@ -0,0 +1,174 @@
+//! Binary host protocol handler
+//!
+//! Answers requests of the [`fridge_core::protocol`] frames received next to the text console.
+
+use defmt::*;
+#[cfg(feature = "precise-temps")]
+use fridge_core::protocol::PRECISE_TEMPS_PER_FRAME as TEMPS_PER_FRAME;
+#[cfg(not(feature = "precise-temps"))]
+use fridge_core::protocol::TEMPS_PER_FRAME;
+use fridge_core::protocol::{
+    ErrorCode, Request, Response, AGGREGATES_PER_FRAME, MAX_ENCODED, MAX_FRAME,
+};
+use heapless::Vec;
+use rtic::mutex_prelude::*;
+
+use super::{cooler_state, set_cooler_mode, set_gains, set_target, set_time};
+use crate::{
+    app::terminal::Context,
+    ds18b20::Resolution,
+    storage::{
+        now_secs, Chunk, Log, Range, Record, Storage, StoredAggregate, Tier, EVENT_COUNT,
+        TEMP_COUNT,
+    },
+    time::{MAX_UNIX, MIN_UNIX},
+    uart,
+};
+
+/// Handles a received frame, without delimiters
+pub async fn handle(cx: &mut Context<'_>, mut frame: Vec<u8, MAX_ENCODED>) {
+    let request = match Request::from_frame(&mut frame) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Invalid frame: {}", e.as_str());
+            send(cx, &Response::Error(e.into())).await;
+            return;
+        }
+    };
+
+    let response = match request {
+        Request::GetTemp => cx.shared.storage.lock(|s| s.temp_recent()).map_or(
+            Response::Error(ErrorCode::Missing),
+            |temp| Response::Temp {
+                secs: temp.full_secs(now_secs()),
+                temp: temp.value(),
+            },
+        ),
+        Request::GetTemps => {
+            // Take a frame worth of temperatures at a time, without locking the storage while
+            // sending them
+            let mut count = 0u16;
+            let mut cursor = cx.shared.storage.lock(|s| s.temps().cursor(Range::All));
+            while !cursor.is_done() {
+                let chunk: Chunk<_, TEMPS_PER_FRAME> =
+                    cx.shared.storage.lock(|s| s.temps().read(&mut cursor));
+                if chunk.lost > 0 {
+                    warn!("{} temperatures lost while sending", chunk.lost);
+                }
+                if chunk.records.is_empty() {
+                    continue;
+                }
+                // At most TEMPS_PER_FRAME
+                #[allow(clippy::cast_possible_truncation)]
+                let len = chunk.records.len() as u16;
+                count += len;
+                #[cfg(feature = "precise-temps")]
+                send(cx, &Response::PreciseTemps(chunk.records)).await;
+                #[cfg(not(feature = "precise-temps"))]
+                send(cx, &Response::Temps(chunk.records)).await;
+            }
+            send(cx, &Response::End { count }).await;
+            return;
+        }
+        Request::GetAggregates(tier) => {
+            let count = match tier {
+                Tier::Minute => send_aggregates(cx, Storage::minutes).await,
+                Tier::Hour => send_aggregates(cx, Storage::hours).await,
+            };
+            send(cx, &Response::End { count }).await;
+            return;
+        }
+        Request::GetEvents => {
+            let mut count = 0u16;
+            let mut cursor = cx.shared.storage.lock(|s| s.events().cursor(Range::All));
+            while !cursor.is_done() {
+                let chunk: Chunk<_, 1> = cx.shared.storage.lock(|s| s.events().read(&mut cursor));
+                if chunk.lost > 0 {
+                    warn!("{} events lost while sending", chunk.lost);
+                }
+                for event in &chunk.records {
+                    let now = now_secs();
+                    let response = Response::Event {
+                        secs: event.full_secs(now),
+                        last: event.full_last_secs(now),
+                        count: event.count(),
+                        event: event.event(),
+                    };
+                    send(cx, &response).await;
+                    count += 1;
+                }
+            }
+            send(cx, &Response::End { count }).await;
+            return;
+        }
+        Request::GetTarget => Response::Target(cx.shared.target.lock(|t| *t)),
+        Request::SetTarget(target) => {
+            set_target(cx, target);
+            Response::Ok
+        }
+        Request::GetGains => Response::Gains(cx.shared.gains.lock(|g| *g)),
+        Request::SetGains(gains) => {
+            set_gains(cx, gains);
+            Response::Ok
+        }
+        Request::GetResolution => Response::Resolution(cx.shared.resolution.lock(|r| r.bits())),
+        Request::SetResolution(bits) => match Resolution::from_bits(bits) {
+            Some(res) => {
+                cx.shared.resolution.lock(|r| *r = res);
+                Response::Ok
+            }
+            None => Response::Error(ErrorCode::InvalidValue),
+        },
+        Request::GetCooler => {
+            let (mode, on) = cooler_state(cx);
+            Response::Cooler { mode, on }
+        }
+        Request::SetCooler(mode) => {
+            set_cooler_mode(cx, mode);
+            Response::Ok
+        }
+        Request::GetTime => Response::Time {
+            secs: now_secs(),
+            unix: cx.shared.rtc.lock(|rtc| rtc.now()),
+        },
+        Request::SetTime(unix) if (MIN_UNIX..MAX_UNIX).contains(&unix) => {
+            set_time(cx, unix);
+            Response::Ok
+        }
+        Request::SetTime(_) => Response::Error(ErrorCode::InvalidValue),
+    };
+
+    send(cx, &response).await;
+}
+
+/// Sends all stored aggregates of a tier, a frame at a time, returning how many were sent
+async fn send_aggregates<const N: usize>(
+    cx: &mut Context<'_>,
+    log: fn(&Storage<TEMP_COUNT, EVENT_COUNT>) -> &Log<StoredAggregate, N>,
+) -> u16 {
+    let mut count = 0u16;
+    let mut cursor = cx.shared.storage.lock(|s| log(s).cursor(Range::All));
+    while !cursor.is_done() {
+        let chunk: Chunk<_, AGGREGATES_PER_FRAME> =
+            cx.shared.storage.lock(|s| log(s).read(&mut cursor));
+        if chunk.lost > 0 {
+            warn!("{} aggregates lost while sending", chunk.lost);
+        }
+        if chunk.records.is_empty() {
+            continue;
+        }
+        // At most AGGREGATES_PER_FRAME
+        #[allow(clippy::cast_possible_truncation)]
+        let len = chunk.records.len() as u16;
+        count += len;
+        send(cx, &Response::Aggregates(chunk.records)).await;
+    }
+    count
+}
+
+/// Queues a response frame, awaiting free space
+async fn send(cx: &mut Context<'_>, response: &Response) {
+    let mut frame = [0; MAX_FRAME];
+    let len = response.to_frame(&mut frame);
+    uart::write_all(&mut cx.shared.tx, &frame[..len]).await;
+}
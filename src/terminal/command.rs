@@ -0,0 +1,460 @@
+//! Command table of the terminal
+//!
+//! Every command the terminal understands is described by a [`Command`] in [`COMMANDS`]. The
+//! table is used to validate arguments before a command is run, and to generate the help text.
+
+use heapless::Vec;
+
+use crate::{onewire::Address, thermometer::Temperature};
+
+/// Maximum number of arguments of a command
+pub const MAX_ARGS: usize = 3;
+
+/// Type of command argument
+#[derive(Debug, Copy, Clone)]
+pub enum ArgKind {
+    /// One of a fixed set of keywords
+    Enum(&'static [&'static str]),
+    /// Unsigned decimal integer
+    Int,
+    /// Temperature in degrees Celsius, e.g. `-4.25`
+    Temp,
+    /// 1-Wire device address as 16 hex digits
+    Address,
+    /// Name of a command in [`COMMANDS`]
+    Command,
+}
+
+#[derive(Debug)]
+pub struct ArgSpec {
+    /// Name of the argument, shown in help
+    pub name: &'static str,
+    pub kind: ArgKind,
+}
+
+/// A validated command argument
+#[derive(Copy, Clone)]
+pub enum Arg {
+    Enum(&'static str),
+    Int(u32),
+    Temp(Temperature),
+    Address(Address),
+    Command(&'static Command),
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub args: &'static [ArgSpec],
+    /// Number of leading arguments that must be given, the others may only be left out together
+    pub required: usize,
+    pub help: &'static str,
+    pub handler: Handler,
+}
+
+/// Handler of a command, run by the terminal once the arguments are validated
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Handler {
+    Help,
+    Mode,
+    Devices,
+    Sensor,
+    Resolution,
+    Target,
+    Pid,
+    Temp,
+    Cooler,
+    Status,
+    Stats,
+    Alarm,
+    Ack,
+    Silence,
+    Time,
+    Crashlog,
+    Watch,
+    Dump,
+    Erase,
+    Reset,
+}
+
+pub static COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        args: &[ArgSpec {
+            name: "command",
+            kind: ArgKind::Command,
+        }],
+        required: 0,
+        help: "Print all commands, or the help of a single command",
+        handler: Handler::Help,
+    },
+    Command {
+        name: "mode",
+        args: &[ArgSpec {
+            name: "mode",
+            kind: ArgKind::Enum(&["text", "json"]),
+        }],
+        required: 0,
+        help: "Get or set the output mode, human readable text or JSON lines",
+        handler: Handler::Mode,
+    },
+    Command {
+        name: "devices",
+        args: &[],
+        required: 0,
+        help: "List 1-Wire devices found on the bus",
+        handler: Handler::Devices,
+    },
+    Command {
+        name: "sensor",
+        args: &[ArgSpec {
+            name: "address",
+            kind: ArgKind::Address,
+        }],
+        required: 0,
+        help: "Get or set the address of the water thermometer",
+        handler: Handler::Sensor,
+    },
+    Command {
+        name: "resolution",
+        args: &[ArgSpec {
+            name: "bits",
+            kind: ArgKind::Enum(&["9", "10", "11", "12"]),
+        }],
+        required: 0,
+        help: "Get or set the resolution of the thermometers",
+        handler: Handler::Resolution,
+    },
+    Command {
+        name: "target",
+        args: &[ArgSpec {
+            name: "temp",
+            kind: ArgKind::Temp,
+        }],
+        required: 0,
+        help: "Get or set the target temperature",
+        handler: Handler::Target,
+    },
+    Command {
+        name: "pid",
+        args: &[
+            ArgSpec {
+                name: "kp",
+                kind: ArgKind::Temp,
+            },
+            ArgSpec {
+                name: "ki",
+                kind: ArgKind::Temp,
+            },
+            ArgSpec {
+                name: "kd",
+                kind: ArgKind::Temp,
+            },
+        ],
+        required: 0,
+        help: "Get or set the PID gains",
+        handler: Handler::Pid,
+    },
+    Command {
+        name: "temp",
+        args: &[],
+        required: 0,
+        help: "Get the current temperature",
+        handler: Handler::Temp,
+    },
+    Command {
+        name: "cooler",
+        args: &[ArgSpec {
+            name: "mode",
+            kind: ArgKind::Enum(&["auto", "on", "off"]),
+        }],
+        required: 0,
+        help: "Get the cooler state, or let the controller drive it or keep it on or off",
+        handler: Handler::Cooler,
+    },
+    Command {
+        name: "status",
+        args: &[],
+        required: 0,
+        help: "Get a snapshot of the health of the fridge",
+        handler: Handler::Status,
+    },
+    Command {
+        name: "stats",
+        args: &[ArgSpec {
+            name: "secs",
+            kind: ArgKind::Int,
+        }],
+        required: 0,
+        help: "Get statistics of the stored temperatures, or of the last n seconds",
+        handler: Handler::Stats,
+    },
+    Command {
+        name: "alarm",
+        args: &[
+            ArgSpec {
+                name: "low",
+                kind: ArgKind::Temp,
+            },
+            ArgSpec {
+                name: "high",
+                kind: ArgKind::Temp,
+            },
+            ArgSpec {
+                name: "delay-secs",
+                kind: ArgKind::Int,
+            },
+        ],
+        required: 0,
+        help: "Get the alarm state, or set the thresholds & the seconds past one before alarming",
+        handler: Handler::Alarm,
+    },
+    Command {
+        name: "ack",
+        args: &[],
+        required: 0,
+        help: "Acknowledge the raised alarm, turning the alert output off until it clears",
+        handler: Handler::Ack,
+    },
+    Command {
+        name: "silence",
+        args: &[ArgSpec {
+            name: "secs",
+            kind: ArgKind::Int,
+        }],
+        required: 1,
+        help: "Turn the alert output off for n seconds, for alarms raised meanwhile as well",
+        handler: Handler::Silence,
+    },
+    Command {
+        name: "time",
+        args: &[ArgSpec {
+            name: "unix-secs",
+            kind: ArgKind::Int,
+        }],
+        required: 0,
+        help: "Get or set the wall-clock time in seconds since 1970 (UTC)",
+        handler: Handler::Time,
+    },
+    Command {
+        name: "crashlog",
+        args: &[],
+        required: 0,
+        help: "Get the last panic or stalled task, kept until the next one or a power loss",
+        handler: Handler::Crashlog,
+    },
+    Command {
+        name: "watch",
+        args: &[
+            ArgSpec {
+                name: "what",
+                kind: ArgKind::Enum(&["temps", "events", "pid", "cooler", "status"]),
+            },
+            ArgSpec {
+                name: "every",
+                kind: ArgKind::Int,
+            },
+        ],
+        required: 1,
+        help: "Watch until a key is pressed, showing every n-th sample",
+        handler: Handler::Watch,
+    },
+    Command {
+        name: "dump",
+        args: &[
+            ArgSpec {
+                name: "what",
+                kind: ArgKind::Enum(&["temps", "minutes", "hours", "events"]),
+            },
+            ArgSpec {
+                name: "range",
+                kind: ArgKind::Enum(&["last", "since", "severity", "code"]),
+            },
+            ArgSpec {
+                name: "n",
+                kind: ArgKind::Int,
+            },
+        ],
+        required: 1,
+        help: "Dump stored records, or a range, or events of a code or of severity 0-2 & up",
+        handler: Handler::Dump,
+    },
+    Command {
+        name: "erase",
+        args: &[],
+        required: 0,
+        help: "Erase stored temperatures and events",
+        handler: Handler::Erase,
+    },
+    Command {
+        name: "reset",
+        args: &[],
+        required: 0,
+        help: "Reset the MCU",
+        handler: Handler::Reset,
+    },
+];
+
+/// Error parsing a command line
+pub enum Error<'a> {
+    UnknownCommand(&'a [u8]),
+    MissingArgument(&'static ArgSpec),
+    InvalidArgument(&'static ArgSpec, &'a [u8]),
+    TooManyArguments,
+}
+
+/// A parsed command line
+pub struct Parsed {
+    pub command: &'static Command,
+    pub args: Vec<Arg, MAX_ARGS>,
+}
+
+/// Parses & validates a command line
+///
+/// Returns `Ok(None)` if the line is empty.
+pub fn parse(line: &[u8]) -> Result<Option<Parsed>, Error<'_>> {
+    let mut words = line
+        .split(|b| super::is_whitespace(*b))
+        .filter(|w| !w.is_empty());
+
+    let Some(name) = words.next() else {
+        return Ok(None);
+    };
+    let command = find(name).ok_or(Error::UnknownCommand(name))?;
+
+    let mut args = Vec::new();
+    for spec in command.args {
+        let Some(word) = words.next() else {
+            if args.len() == command.required {
+                break;
+            }
+            return Err(Error::MissingArgument(spec));
+        };
+
+        let arg = parse_arg(spec.kind, word).ok_or(Error::InvalidArgument(spec, word))?;
+
+        // SAFETY: no command takes more than MAX_ARGS arguments
+        unsafe { args.push_unchecked(arg) };
+    }
+
+    if words.next().is_some() {
+        return Err(Error::TooManyArguments);
+    }
+
+    Ok(Some(Parsed { command, args }))
+}
+
+/// Finds a command by name
+pub fn find(name: &[u8]) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name.as_bytes() == name)
+}
+
+fn parse_arg(kind: ArgKind, word: &[u8]) -> Option<Arg> {
+    match kind {
+        ArgKind::Enum(values) => values
+            .iter()
+            .find(|v| v.as_bytes() == word)
+            .copied()
+            .map(Arg::Enum),
+        ArgKind::Int => parse_uint(word).map(Arg::Int),
+        ArgKind::Temp => parse_temp(word).map(Arg::Temp),
+        ArgKind::Address => parse_address(word).map(Arg::Address),
+        ArgKind::Command => find(word).map(Arg::Command),
+    }
+}
+
+fn parse_uint(word: &[u8]) -> Option<u32> {
+    if word.is_empty() {
+        return None;
+    }
+
+    word.iter().try_fold(0u32, |acc, b| {
+        let digit = b.checked_sub(b'0').filter(|d| *d < 10)?;
+        acc.checked_mul(10)?.checked_add(u32::from(digit))
+    })
+}
+
+/// Parses a decimal temperature, rounding to the nearest representable value
+fn parse_temp(word: &[u8]) -> Option<Temperature> {
+    const ONE: i32 = 1 << Temperature::FRAC_NBITS;
+
+    let (negative, word) = match word {
+        [b'-', rest @ ..] => (true, rest),
+        _ => (false, word),
+    };
+
+    let (int, frac) = match word.iter().position(|b| *b == b'.') {
+        Some(idx) => (&word[..idx], &word[idx + 1..]),
+        None => (word, &[][..]),
+    };
+
+    let int = i32::try_from(parse_uint(int)?).ok()?;
+
+    // Only the first 4 decimals matter for a resolution of 1/16
+    let mut num = 0;
+    let mut den = 1;
+    for b in frac.iter().take(4) {
+        let digit = b.checked_sub(b'0').filter(|d| *d < 10)?;
+        num = num * 10 + i32::from(digit);
+        den *= 10;
+    }
+    if frac.iter().skip(4).any(|b| !b.is_ascii_digit()) {
+        return None;
+    }
+
+    let bits = int
+        .checked_mul(ONE)?
+        .checked_add((num * ONE + den / 2) / den)?;
+    let bits = i16::try_from(if negative { -bits } else { bits }).ok()?;
+
+    Some(Temperature::from_bits(bits))
+}
+
+fn parse_address(word: &[u8]) -> Option<Address> {
+    if word.is_empty() || word.len() > 16 {
+        return None;
+    }
+
+    word.iter()
+        .try_fold(0u64, |acc, b| {
+            let digit = char::from(*b).to_digit(16)?;
+            Some((acc << 4) | u64::from(digit))
+        })
+        .map(Address)
+}
+
+impl Arg {
+    pub fn as_enum(self) -> &'static str {
+        match self {
+            Self::Enum(v) => v,
+            _ => defmt::unreachable!("Argument is not an enum"),
+        }
+    }
+
+    pub fn as_int(self) -> u32 {
+        match self {
+            Self::Int(v) => v,
+            _ => defmt::unreachable!("Argument is not an integer"),
+        }
+    }
+
+    pub fn as_temp(self) -> Temperature {
+        match self {
+            Self::Temp(v) => v,
+            _ => defmt::unreachable!("Argument is not a temperature"),
+        }
+    }
+
+    pub fn as_address(self) -> Address {
+        match self {
+            Self::Address(v) => v,
+            _ => defmt::unreachable!("Argument is not an address"),
+        }
+    }
+
+    pub fn as_command(self) -> &'static Command {
+        match self {
+            Self::Command(v) => v,
+            _ => defmt::unreachable!("Argument is not a command"),
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Line editing for the serial console
+//!
+//! Supports:
+//! - Backspace/Delete to remove the last character
+//! - Ctrl-U to clear the line
+//! - Ctrl-C to cancel the line
+//! - Up/Down arrows to go through previously entered lines
+//! - Tab to complete command names
+
+use defmt::*;
+use embedded_hal::serial::Write;
+use heapless::{Deque, Vec};
+
+use super::{command::COMMANDS, BUFFER_SIZE};
+
+/// Maximum length of a line, leaving room for the newline in the input buffer
+pub const LINE_SIZE: usize = BUFFER_SIZE - 1;
+/// Number of previous lines kept in the history
+const HISTORY_SIZE: usize = 4;
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const BELL: u8 = 0x07;
+const TAB: u8 = b'\t';
+const CTRL_U: u8 = 0x15;
+const ESC: u8 = 0x1B;
+const DEL: u8 = 0x7F;
+
+/// Moves the cursor to the start of the line & clears it
+const CLEAR_LINE: &[u8] = b"\r\x1b[K";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Normal,
+    /// Received `ESC`
+    Escape,
+    /// Received `ESC [`, waiting for the final byte of the control sequence
+    Csi,
+}
+
+pub struct LineEditor {
+    line: Vec<u8, LINE_SIZE>,
+    history: Deque<Vec<u8, LINE_SIZE>, HISTORY_SIZE>,
+    /// Position in the history being shown, 0 being the most recent line
+    history_idx: Option<usize>,
+    state: State,
+    /// Whether the last byte was a carriage return, to treat `\r\n` as a single newline
+    last_cr: bool,
+}
+
+impl LineEditor {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            history: Deque::new(),
+            history_idx: None,
+            state: State::Normal,
+            last_cr: false,
+        }
+    }
+
+    /// Feeds a received byte to the editor, echoing the result to `tx`.
+    ///
+    /// Completed lines are appended to `buffer`, followed by a newline.
+    ///
+    /// Returns `true` if the byte was a key press, rather than the `\n` of a `\r\n` or the rest of
+    /// an escape sequence.
+    pub fn feed<W: Write<u8>>(
+        &mut self,
+        b: u8,
+        tx: &mut W,
+        buffer: &mut Deque<u8, BUFFER_SIZE>,
+    ) -> bool {
+        let last_cr = core::mem::replace(&mut self.last_cr, b == b'\r');
+
+        match self.state {
+            State::Escape => {
+                // Only control sequences are supported, ignore anything else
+                self.state = if b == b'[' { State::Csi } else { State::Normal };
+                return false;
+            }
+            State::Csi => {
+                match b {
+                    b'A' => self.history_prev(tx),
+                    b'B' => self.history_next(tx),
+                    // Ignore parameter bytes until the final byte
+                    0x20..=0x3F => return false,
+                    _ => {}
+                }
+                self.state = State::Normal;
+                return false;
+            }
+            State::Normal => {}
+        }
+
+        match b {
+            b'\n' if last_cr => return false,
+            b'\r' | b'\n' => {
+                write_bytes(tx, b"\r\n");
+                self.submit(buffer);
+            }
+            BACKSPACE | DEL => {
+                if self.line.pop().is_some() {
+                    write_bytes(tx, b"\x08 \x08");
+                }
+            }
+            CTRL_U => self.replace_line(Vec::new(), tx),
+            CTRL_C => {
+                write_bytes(tx, b"^C\r\n");
+                self.line.clear();
+                self.history_idx = None;
+            }
+            TAB => self.complete(tx),
+            ESC => self.state = State::Escape,
+            b' '..=b'~' => self.insert(b, tx),
+            _ => trace!("Ignoring control character {=u8:#x}", b),
+        }
+
+        true
+    }
+
+    fn insert<W: Write<u8>>(&mut self, b: u8, tx: &mut W) {
+        if self.line.push(b).is_ok() {
+            write_bytes(tx, &[b]);
+        } else {
+            write_bytes(tx, &[BELL]);
+        }
+    }
+
+    /// Moves the line into the input buffer & history
+    fn submit(&mut self, buffer: &mut Deque<u8, BUFFER_SIZE>) {
+        if buffer.capacity() - buffer.len() > self.line.len() {
+            for b in &self.line {
+                // SAFETY: checked there is enough space for the line & newline
+                unsafe { buffer.push_back_unchecked(*b) };
+            }
+            // SAFETY: see above
+            unsafe { buffer.push_back_unchecked(b'\n') };
+        } else {
+            error!("Buffer overflow");
+        }
+
+        self.history_idx = None;
+        if self.line.is_empty() || self.history.back() == Some(&self.line) {
+            self.line.clear();
+            return;
+        }
+
+        if self.history.is_full() {
+            self.history.pop_front();
+        }
+        let line = core::mem::take(&mut self.line);
+        // SAFETY: made room above
+        unsafe { self.history.push_back_unchecked(line) };
+    }
+
+    /// Replaces the line with an older line from the history
+    fn history_prev<W: Write<u8>>(&mut self, tx: &mut W) {
+        let idx = self.history_idx.map_or(0, |i| i + 1);
+        if let Some(line) = self.history.iter().rev().nth(idx).cloned() {
+            self.history_idx = Some(idx);
+            self.replace_line(line, tx);
+        } else {
+            write_bytes(tx, &[BELL]);
+        }
+    }
+
+    /// Replaces the line with a newer line from the history, or an empty line
+    fn history_next<W: Write<u8>>(&mut self, tx: &mut W) {
+        match self.history_idx {
+            None => write_bytes(tx, &[BELL]),
+            Some(0) => {
+                self.history_idx = None;
+                self.replace_line(Vec::new(), tx);
+            }
+            Some(idx) => {
+                let line = self.history.iter().rev().nth(idx - 1).cloned();
+                self.history_idx = Some(idx - 1);
+                self.replace_line(line.unwrap_or_default(), tx);
+            }
+        }
+    }
+
+    fn replace_line<W: Write<u8>>(&mut self, line: Vec<u8, LINE_SIZE>, tx: &mut W) {
+        write_bytes(tx, CLEAR_LINE);
+        write_bytes(tx, &line);
+        self.line = line;
+    }
+
+    /// Completes the command name being typed
+    ///
+    /// Completes as far as all matching commands agree, and adds a space once only one is left.
+    fn complete<W: Write<u8>>(&mut self, tx: &mut W) {
+        // Only command names are completed
+        if self.line.contains(&b' ') {
+            write_bytes(tx, &[BELL]);
+            return;
+        }
+
+        let mut matches = COMMANDS
+            .iter()
+            .map(|c| c.name.as_bytes())
+            .filter(|name| name.starts_with(&self.line));
+        let Some(first) = matches.next() else {
+            write_bytes(tx, &[BELL]);
+            return;
+        };
+
+        // Length of the prefix shared by all matches
+        let mut common = first.len();
+        let mut unique = true;
+        for name in matches {
+            unique = false;
+            common = common.min(first.iter().zip(name).take_while(|(a, b)| a == b).count());
+        }
+
+        if common == self.line.len() && !unique {
+            write_bytes(tx, &[BELL]);
+            return;
+        }
+
+        for b in &first[self.line.len()..common] {
+            self.insert(*b, tx);
+        }
+        if unique {
+            self.insert(b' ', tx);
+        }
+    }
+}
+
+/// Echoes bytes, dropping them if `tx` is full as the interrupt can't wait for it
+fn write_bytes<W: Write<u8>>(tx: &mut W, bytes: &[u8]) {
+    for b in bytes {
+        if tx.write(*b).is_err() {
+            warn!("Echo dropped, transmit buffer full");
+            return;
+        }
+    }
+}
@@ -0,0 +1,986 @@
+mod binary;
+mod command;
+mod editor;
+mod output;
+
+use core::fmt::Write;
+
+use defmt::{unreachable, *};
+use fridge_core::alarm::{Alarm, AlarmConfig};
+use futures_util::{
+    future::{select, Either},
+    pin_mut,
+};
+use heapless::{Deque, String, Vec};
+use num_traits::AsPrimitive;
+use rtic::mutex_prelude::*;
+use rtic_monotonics::{
+    stm32::{Tim2 as Mono, *},
+    Monotonic,
+};
+use rtic_sync::channel::Receiver;
+use stm32f0xx_hal::prelude::*;
+
+use self::{
+    command::{Arg, ArgKind, ArgSpec, Command, Handler, Parsed, COMMANDS},
+    output::{end_stream, print_date, print_epoch, print_lost, print_str, ErrorCode, Line},
+};
+pub use self::{editor::LineEditor, output::Mode};
+use crate::{
+    app::terminal::Context,
+    controller::{pid::PidGains, CoolerMode},
+    cooler,
+    ds18b20::Resolution,
+    event::{self, Event, Severity},
+    onewire::Address,
+    stack,
+    stats::DEFAULT_BAND,
+    storage::{
+        now_secs, Chunk, Log, Range, Record, Storage, StoredAggregate, StoredEvent, StoredTemp,
+        TempRecord, DUMP_CHUNK, EVENT_COUNT, TEMP_COUNT,
+    },
+    temp_controller::Tick,
+    thermometer::Temperature,
+    time::{DateTime, MAX_UNIX, MIN_UNIX},
+    uart,
+};
+
+pub const BUFFER_SIZE: usize = 32;
+/// Size of a line of output, fitting the longest event record: `PidParamsChanged` in JSON mode
+const OUTPUT_SIZE: usize = 168;
+
+/// Terminal handler
+///
+/// Runs every complete line in the input buffer as a command from [`COMMANDS`]. Use `help` for a
+/// list of commands. Frames of the binary host protocol are answered in between lines, & alerts
+/// of the alarm task are printed as unsolicited lines.
+///
+/// Output is queued for the USART2 interrupt a line at a time, awaiting free space in between, so
+/// long output never holds any lock for longer than it takes to format a line.
+#[cfg_attr(feature = "sizing", inline(never))]
+pub async fn terminal(mut cx: Context<'_>) {
+    loop {
+        while let Ok(alert) = cx.local.alert_rx.try_recv() {
+            let mode = *cx.local.mode;
+            print(&mut cx, |tx| {
+                let now = now_secs();
+                let line = Line::labelled(tx, mode).uint("t", alert.full_secs(now));
+                event_fields(line, "alert", &alert, now).end();
+            })
+            .await;
+        }
+
+        if let Some(frame) = cx.shared.frame.lock(Option::take) {
+            binary::handle(&mut cx, frame).await;
+            continue;
+        }
+
+        let Some(line) = cx.shared.buffer.lock(get_line) else {
+            return;
+        };
+
+        match command::parse(&line) {
+            Ok(None) => trace!("Empty command"),
+            Ok(Some(Parsed { command, args })) => run(&mut cx, command, &args).await,
+            Err(e) => {
+                let mode = *cx.local.mode;
+                print(&mut cx, |tx| print_error(tx, mode, &e)).await;
+            }
+        }
+    }
+}
+
+/// A line of output being formatted
+///
+/// Text that doesn't fit marks the line as overflowed, so it's never sent truncated.
+#[derive(Default)]
+struct Output {
+    line: String<OUTPUT_SIZE>,
+    overflowed: bool,
+}
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if self.line.push_str(s).is_err() {
+            self.overflowed = true;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a line of output with `f` & queues it for transmission, awaiting free space
+///
+/// A line longer than [`OUTPUT_SIZE`] is replaced by an error, as a cut off line could be taken
+/// for a complete one.
+async fn print(cx: &mut Context<'_>, f: impl FnOnce(&mut Output)) {
+    let mut out = Output::default();
+    f(&mut out);
+    if out.overflowed {
+        error!("Output line longer than {} bytes", OUTPUT_SIZE);
+        out = Output::default();
+        Line::error(&mut out, *cx.local.mode, ErrorCode::OutputTooLong).end();
+    }
+    uart::write_all(&mut cx.shared.tx, out.line.as_bytes()).await;
+}
+
+/// Runs a command with validated arguments
+async fn run(cx: &mut Context<'_>, command: &'static Command, args: &[Arg]) {
+    let arg = args.first().copied();
+    let mode = *cx.local.mode;
+
+    match command.handler {
+        Handler::Help => match arg {
+            None => print_help(cx, mode).await,
+            Some(arg) => print(cx, |tx| print_command_help(tx, mode, arg.as_command())).await,
+        },
+        Handler::Mode => {
+            if let Some(arg) = arg {
+                let mode = if arg.as_enum() == "json" {
+                    Mode::Json
+                } else {
+                    Mode::Text
+                };
+                *cx.local.mode = mode;
+                print(cx, |tx| Line::ok(tx, mode).end()).await;
+            } else {
+                print(cx, |tx| Line::ok(tx, mode).str("mode", mode.as_str()).end()).await;
+            }
+        }
+        Handler::Devices => {
+            let devices = cx.shared.devices.lock(|devices| devices.clone());
+            for device in devices {
+                print(cx, |tx| {
+                    Line::record(tx, mode).address("address", device).end()
+                })
+                .await;
+            }
+            print(cx, |tx| end_stream(tx, mode)).await;
+        }
+        Handler::Sensor => {
+            if let Some(arg) = arg {
+                let address = arg.as_address();
+                let old = cx
+                    .shared
+                    .sensor
+                    .lock(|addr| core::mem::replace(addr, address));
+                if old != address {
+                    log_event(cx, &Event::TempSensorSelected { address: address.0 });
+                }
+                print(cx, |tx| Line::ok(tx, mode).end()).await;
+            } else {
+                let addr = cx.shared.sensor.lock(|addr| *addr);
+                print(cx, |tx| Line::ok(tx, mode).address("sensor", addr).end()).await;
+            }
+        }
+        Handler::Resolution => resolution(cx, mode, arg).await,
+        Handler::Target => {
+            if let Some(arg) = arg {
+                set_target(cx, arg.as_temp());
+                print(cx, |tx| Line::ok(tx, mode).end()).await;
+            } else {
+                let target = cx.shared.target.lock(|target| *target);
+                print(cx, |tx| Line::ok(tx, mode).temp("target", target).end()).await;
+            }
+        }
+        Handler::Pid => {
+            if let [kp, ki, kd] = *args {
+                let gains = PidGains {
+                    kp: kp.as_temp(),
+                    ki: ki.as_temp(),
+                    kd: kd.as_temp(),
+                };
+                set_gains(cx, gains);
+                print(cx, |tx| Line::ok(tx, mode).end()).await;
+            } else {
+                let gains = cx.shared.gains.lock(|g| *g);
+                print(cx, |tx| {
+                    Line::ok(tx, mode)
+                        .temp("kp", gains.kp)
+                        .temp("ki", gains.ki)
+                        .temp("kd", gains.kd)
+                        .end();
+                })
+                .await;
+            }
+        }
+        Handler::Temp => {
+            let temp = cx.shared.storage.lock(|s| s.temp_recent());
+            print(cx, |tx| {
+                if let Some(temp) = temp {
+                    Line::ok(tx, mode)
+                        .uint("t", temp.full_secs(now_secs()))
+                        .temp("temp", temp.value())
+                        .end();
+                } else {
+                    Line::error(tx, mode, ErrorCode::Missing).end();
+                }
+            })
+            .await;
+        }
+        Handler::Cooler => {
+            if let Some(arg) = arg {
+                let cooler_mode = match arg.as_enum() {
+                    "on" => CoolerMode::On,
+                    "off" => CoolerMode::Off,
+                    _ => CoolerMode::Auto,
+                };
+                set_cooler_mode(cx, cooler_mode);
+                print(cx, |tx| Line::ok(tx, mode).end()).await;
+            } else {
+                let (cooler_mode, on) = cooler_state(cx);
+                print(cx, |tx| {
+                    Line::ok(tx, mode)
+                        .str("cooler", if on { "on" } else { "off" })
+                        .str("mode", cooler_mode.as_str())
+                        .end();
+                })
+                .await;
+            }
+        }
+        Handler::Status => status(cx, mode).await,
+        Handler::Stats => stats(cx, mode, arg).await,
+        Handler::Alarm => alarm(cx, mode, args).await,
+        Handler::Ack => {
+            let kind = cx.shared.alarm.lock(Alarm::acknowledge);
+            if let Some(kind) = kind {
+                log_event(cx, &Event::AlarmAcknowledged { kind });
+                print(cx, |tx| Line::ok(tx, mode).end()).await;
+            } else {
+                print(cx, |tx| Line::error(tx, mode, ErrorCode::Missing).end()).await;
+            }
+        }
+        Handler::Silence => {
+            let secs = args[0].as_int();
+            let until = now_secs().saturating_add(secs);
+            cx.shared.alarm.lock(|alarm| alarm.silence(until));
+            log_event(cx, &Event::AlarmSilenced { secs });
+            print(cx, |tx| Line::ok(tx, mode).end()).await;
+        }
+        Handler::Time => time(cx, mode, arg).await,
+        Handler::Crashlog => {
+            let crash = cx.local.crash.clone();
+            print(cx, |tx| {
+                if let Some(crash) = crash {
+                    Line::labelled(tx, mode)
+                        .str("cause", if crash.is_stall() { "stall" } else { "panic" })
+                        .uint("uptime", crash.secs)
+                        .str("task", crash.task().as_str())
+                        .str("file", crash.file())
+                        .uint("line", crash.line)
+                        .str("msg", crash.msg())
+                        .end();
+                } else {
+                    Line::error(tx, mode, ErrorCode::Missing).end();
+                }
+            })
+            .await;
+        }
+        Handler::Watch => watch(cx, mode, args).await,
+        Handler::Dump => dump_storage(cx, mode, args).await,
+        Handler::Erase => {
+            cx.shared.storage.lock(|s| {
+                s.erase();
+                s.write_event(StoredEvent::now(&Event::StorageErased));
+            });
+            print(cx, |tx| Line::ok(tx, mode).end()).await;
+        }
+        Handler::Reset => {
+            print(cx, |tx| Line::ok(tx, mode).str("msg", "Resetting...").end()).await;
+            // Let the last bytes leave the usart before resetting
+            uart::flush(&mut cx.shared.tx).await;
+            Mono::delay(1.millis()).await;
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+    }
+}
+
+/// Gets the cooler mode & whether the cooler is on
+fn cooler_state(cx: &mut Context<'_>) -> (CoolerMode, bool) {
+    let mode = cx.shared.cooler_mode.lock(|m| *m);
+    let on = unwrap!(cx.shared.cooler.lock(|c| c.is_set_high()));
+    (mode, on)
+}
+
+/// Sets the cooler mode, switching the cooler right away in the manual modes
+fn set_cooler_mode(cx: &mut Context<'_>, mode: CoolerMode) {
+    let old = cx.shared.cooler_mode.lock(|m| core::mem::replace(m, mode));
+    let switched = unwrap!(cx.shared.cooler.lock(|c| cooler::apply_mode(c, mode)));
+    if old != mode {
+        log_event(cx, &Event::CoolerModeChanged { old, new: mode });
+    }
+    if switched {
+        let duty = cx.shared.counters.lock(|c| c.duty());
+        let on = mode == CoolerMode::On;
+        log_event(
+            cx,
+            &Event::CoolerSwitched {
+                on,
+                duty: duty.as_(),
+            },
+        );
+    }
+}
+
+fn set_target(cx: &mut Context<'_>, target: Temperature) {
+    let old = cx.shared.target.lock(|t| core::mem::replace(t, target));
+    if old != target {
+        log_event(cx, &Event::PidTargetChanged { old, new: target });
+    }
+}
+
+fn set_gains(cx: &mut Context<'_>, gains: PidGains) {
+    let old = cx.shared.gains.lock(|g| core::mem::replace(g, gains));
+    if old != gains {
+        log_event(cx, &Event::PidParamsChanged { old, new: gains });
+    }
+}
+
+/// Sets the real-time clock & places the stored records in time
+fn set_time(cx: &mut Context<'_>, unix: u32) {
+    let old = cx.shared.rtc.lock(|rtc| {
+        let old = rtc.now();
+        rtc.set(unix);
+        old
+    });
+    cx.shared.storage.lock(|s| s.set_epoch(unix));
+    let old = old.unwrap_or(0);
+    log_event(cx, &Event::ClockSet { old, new: unix });
+}
+
+/// Stores an event, timestamped now
+fn log_event(cx: &mut Context<'_>, event: &Event) {
+    cx.shared
+        .storage
+        .lock(|s| s.write_event(StoredEvent::now(event)));
+}
+
+fn get_line(buffer: &mut Deque<u8, BUFFER_SIZE>) -> Option<Vec<u8, BUFFER_SIZE>> {
+    // Find newline
+    let Some(idx) = buffer.iter().position(|b| is_newline(*b)) else {
+        // No newline found
+        return None;
+    };
+
+    // Pop line from buffer
+    let mut line = Vec::<_, BUFFER_SIZE>::new();
+    for _ in 0..=idx {
+        // SAFETY: idx is guaranteed to be valid in buffer
+        // line is guaranteed to be large enough to hold idx + 1 bytes
+        unsafe {
+            let b = buffer.pop_front_unchecked();
+            line.push_unchecked(b);
+        }
+    }
+
+    Some(line)
+}
+
+#[inline]
+pub const fn is_newline(b: u8) -> bool {
+    b == b'\n' || b == b'\r'
+}
+
+#[inline]
+pub const fn is_whitespace(b: u8) -> bool {
+    b == b' ' || b == b'\n' || b == b'\r' || b == b'\t'
+}
+
+fn print_error<W: Write>(tx: &mut W, mode: Mode, err: &command::Error) {
+    if mode == Mode::Json {
+        match err {
+            command::Error::UnknownCommand(name) => {
+                Line::error(tx, mode, ErrorCode::UnknownCommand).bytes("arg", name)
+            }
+            command::Error::MissingArgument(spec) => {
+                Line::error(tx, mode, ErrorCode::MissingArgument).str("arg", spec.name)
+            }
+            command::Error::InvalidArgument(_, arg) => {
+                Line::error(tx, mode, ErrorCode::InvalidArgument).bytes("arg", arg)
+            }
+            command::Error::TooManyArguments => Line::error(tx, mode, ErrorCode::TooManyArguments),
+        }
+        .end();
+        return;
+    }
+
+    match err {
+        command::Error::UnknownCommand(name) => {
+            print_str(tx, "Unknown command: '");
+            output::print_bytes(tx, name);
+            print_str(tx, "'");
+        }
+        command::Error::MissingArgument(spec) => {
+            print_str(tx, "Missing argument: ");
+            print_arg_spec(tx, spec);
+        }
+        command::Error::InvalidArgument(spec, arg) => {
+            print_str(tx, "Invalid argument: '");
+            output::print_bytes(tx, arg);
+            print_str(tx, "', expected ");
+            print_arg_spec(tx, spec);
+        }
+        command::Error::TooManyArguments => print_str(tx, "Too many arguments"),
+    }
+    print_str(tx, "\r\n");
+}
+
+async fn print_help(cx: &mut Context<'_>, mode: Mode) {
+    if mode == Mode::Json {
+        for command in COMMANDS {
+            print(cx, |tx| {
+                Line::record(tx, mode)
+                    .with("usage", |tx| print_usage(tx, command))
+                    .str("help", command.help)
+                    .end();
+            })
+            .await;
+        }
+        print(cx, |tx| end_stream(tx, mode)).await;
+        return;
+    }
+
+    print(cx, |tx| print_str(tx, "Commands:\r\n")).await;
+    for command in COMMANDS {
+        print(cx, |tx| {
+            print_str(tx, "    ");
+            print_usage(tx, command);
+            print_str(tx, "\r\n");
+        })
+        .await;
+    }
+}
+
+fn print_command_help<W: Write>(tx: &mut W, mode: Mode, command: &Command) {
+    if mode == Mode::Json {
+        Line::ok(tx, mode)
+            .with("usage", |tx| print_usage(tx, command))
+            .str("help", command.help)
+            .end();
+        return;
+    }
+
+    print_usage(tx, command);
+    print_str(tx, "\r\n    ");
+    print_str(tx, command.help);
+    print_str(tx, "\r\n");
+}
+
+/// Prints the usage of a command, e.g. `pid [<kp> <ki> <kd>]`
+fn print_usage<W: Write>(tx: &mut W, command: &Command) {
+    print_str(tx, command.name);
+    let optional = command.required < command.args.len();
+
+    for (i, spec) in command.args.iter().enumerate() {
+        print_str(tx, " ");
+        if optional && i == command.required {
+            print_str(tx, "[");
+        }
+        print_arg_spec(tx, spec);
+    }
+    if optional {
+        print_str(tx, "]");
+    }
+}
+
+/// Prints an argument, e.g. `<kp>` or `<on|off>`
+fn print_arg_spec<W: Write>(tx: &mut W, spec: &ArgSpec) {
+    print_str(tx, "<");
+    if let ArgKind::Enum(values) = spec.kind {
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                print_str(tx, "|");
+            }
+            print_str(tx, value);
+        }
+    } else {
+        print_str(tx, spec.name);
+    }
+    print_str(tx, ">");
+}
+
+async fn resolution(cx: &mut Context<'_>, mode: Mode, arg: Option<Arg>) {
+    let Some(arg) = arg else {
+        let res = cx.shared.resolution.lock(|res| *res);
+        print(cx, |tx| {
+            Line::ok(tx, mode)
+                .uint("resolution", u32::from(res.bits()))
+                .end();
+        })
+        .await;
+        return;
+    };
+
+    let res = match arg.as_enum() {
+        "9" => Resolution::Bits9,
+        "10" => Resolution::Bits10,
+        "11" => Resolution::Bits11,
+        _ => Resolution::Bits12,
+    };
+    cx.shared.resolution.lock(|r| *r = res);
+    print(cx, |tx| Line::ok(tx, mode).end()).await;
+}
+
+/// Dumps stored temperatures, aggregates or events, e.g. `dump hours last 24`
+async fn dump_storage(cx: &mut Context<'_>, mode: Mode, args: &[Arg]) {
+    let (range, filter) = match *args {
+        [_, kind, n] => match kind.as_enum() {
+            "last" => (Range::Last(n.as_int()), None),
+            "since" => (Range::Since(n.as_int()), None),
+            filter => (Range::All, Some((filter, n.as_int()))),
+        },
+        _ => (Range::All, None),
+    };
+    // Only events are filtered, by at least a severity or by code
+    let valid = match filter {
+        Some(("severity", n)) => {
+            args[0].as_enum() == "events"
+                && u8::try_from(n).ok().and_then(Severity::from_u8).is_some()
+        }
+        Some(_) => args[0].as_enum() == "events",
+        None => true,
+    };
+    if !valid {
+        print(cx, |tx| {
+            Line::error(tx, mode, ErrorCode::InvalidArgument).end();
+        })
+        .await;
+        return;
+    }
+    // Every record of the dump was stored by now
+    let now = now_secs();
+
+    let print_aggregate = |tx: &mut Output, aggregate: &StoredAggregate| {
+        Line::record(tx, mode)
+            .uint("t", aggregate.full_secs(now))
+            .temp("min", aggregate.min())
+            .temp("avg", aggregate.avg())
+            .temp("max", aggregate.max())
+            .end();
+    };
+
+    if let Some(epoch) = cx.shared.storage.lock(|s| s.epoch()) {
+        print(cx, |tx| print_epoch(tx, mode, epoch)).await;
+    }
+
+    match args[0].as_enum() {
+        "temps" => {
+            dump_log(cx, mode, range, Storage::temps, |tx, temp| {
+                Line::record(tx, mode)
+                    .uint("t", temp.full_secs(now))
+                    .temp("temp", temp.value())
+                    .end();
+            })
+            .await;
+        }
+        "minutes" => dump_log(cx, mode, range, Storage::minutes, print_aggregate).await,
+        "hours" => dump_log(cx, mode, range, Storage::hours, print_aggregate).await,
+        _ => {
+            dump_log(cx, mode, range, Storage::events, |tx, event| {
+                let keep = match filter {
+                    Some(("severity", n)) => event.code.severity() as u32 >= n,
+                    Some((_, n)) => event.code as u32 == n,
+                    None => true,
+                };
+                if keep {
+                    let line = Line::labelled(tx, mode).uint("t", event.full_secs(now));
+                    event_fields(line, "code", event, now).end();
+                }
+            })
+            .await;
+        }
+    }
+    print(cx, |tx| end_stream(tx, mode)).await;
+}
+
+/// Dumps a log of the storage a chunk at a time, so the storage isn't locked while sending
+async fn dump_log<T: Record, const N: usize>(
+    cx: &mut Context<'_>,
+    mode: Mode,
+    range: Range,
+    log: fn(&Storage<TEMP_COUNT, EVENT_COUNT>) -> &Log<T, N>,
+    print_record: impl Fn(&mut Output, &T),
+) {
+    let mut cursor = cx.shared.storage.lock(|s| log(s).cursor(range));
+    while !cursor.is_done() {
+        let chunk: Chunk<T, DUMP_CHUNK> = cx.shared.storage.lock(|s| log(s).read(&mut cursor));
+        if chunk.lost > 0 {
+            print(cx, |tx| print_lost(tx, mode, chunk.lost)).await;
+        }
+        for record in &chunk.records {
+            print(cx, |tx| print_record(tx, record)).await;
+        }
+    }
+}
+
+/// Prints a snapshot of the health of the fridge, as a stream of labelled records
+async fn status(cx: &mut Context<'_>, mode: Mode) {
+    let (cooler_mode, on) = cooler_state(cx);
+    let target = cx.shared.target.lock(|t| *t);
+    let output = cx.shared.output.lock(|o| *o);
+    let counters = cx.shared.counters.lock(|c| *c);
+    let resolution = cx.shared.resolution.lock(|r| r.bits());
+    let (temp, event, temps, aggregates, events) = cx.shared.storage.lock(|s| {
+        (
+            s.temp_recent(),
+            s.event_recent().cloned(),
+            (s.temps().len(), s.temps().capacity()),
+            (s.minutes().len(), s.hours().len()),
+            (s.events().len(), s.events().capacity(), s.dropped_events()),
+        )
+    });
+    let alarm = cx.shared.alarm.lock(|alarm| alarm.clone());
+    let reset_cause = *cx.local.reset_cause;
+    let boots = *cx.local.boots;
+    let now = now_secs();
+
+    print(cx, |tx| {
+        Line::labelled(tx, mode)
+            .uint("uptime", now)
+            .str("reset", reset_cause.as_str())
+            .uint("boots", boots)
+            .uint("stack_free", stack::headroom())
+            .end();
+    })
+    .await;
+    print(cx, |tx| {
+        let line = Line::labelled(tx, mode);
+        let line = match temp {
+            Some(temp) => line
+                .uint("t", temp.full_secs(now))
+                .temp("temp", temp.value()),
+            None => line.str("temp", "none"),
+        };
+        line.temp("target", target)
+            .str("controller", "pid")
+            .uint("output", u32::from(output))
+            .end();
+    })
+    .await;
+    print(cx, |tx| {
+        Line::labelled(tx, mode)
+            .str("cooler", if on { "on" } else { "off" })
+            .str("mode", cooler_mode.as_str())
+            .uint("duty", counters.duty())
+            .end();
+    })
+    .await;
+    print(cx, |tx| {
+        alarm_fields(Line::labelled(tx, mode), &alarm, now).end()
+    })
+    .await;
+    print(cx, |tx| {
+        Line::labelled(tx, mode)
+            .uint("resolution", u32::from(resolution))
+            .uint("crc_errors", u32::from(counters.crc_errors))
+            .uint("timeouts", u32::from(counters.timeouts))
+            .uint("bus_errors", u32::from(counters.bus_errors))
+            .end();
+    })
+    .await;
+    print(cx, |tx| {
+        Line::labelled(tx, mode)
+            .uint("temps", temps.0.as_())
+            .uint("temps_max", temps.1.as_())
+            .str("temp_format", StoredTemp::FORMAT.as_str())
+            .uint("minutes", aggregates.0.as_())
+            .uint("hours", aggregates.1.as_())
+            .uint("events", events.0.as_())
+            .uint("events_max", events.1.as_())
+            .uint("events_dropped", events.2 + u32::from(counters.lost_events))
+            .end();
+    })
+    .await;
+    if let Some(event) = event {
+        print(cx, |tx| {
+            let line = Line::labelled(tx, mode).uint("event_t", event.full_secs(now));
+            event_fields(line, "event", &event, now).end();
+        })
+        .await;
+    }
+    print(cx, |tx| end_stream(tx, mode)).await;
+}
+
+/// Prints statistics of the stored temperatures, of the last `<secs>` seconds if given
+///
+/// Printed as two labelled records, the spread of the temperatures & how well they were held.
+async fn stats(cx: &mut Context<'_>, mode: Mode, arg: Option<Arg>) {
+    let since = arg.map_or(0, |secs| now_secs().saturating_sub(secs.as_int()));
+    let target = cx.shared.target.lock(|t| *t);
+    let stats = cx
+        .shared
+        .storage
+        .lock(|s| s.stats(since, target, DEFAULT_BAND));
+
+    let Some(stats) = stats else {
+        print(cx, |tx| Line::error(tx, mode, ErrorCode::Missing).end()).await;
+        return;
+    };
+    print(cx, |tx| {
+        Line::labelled(tx, mode)
+            .uint("samples", stats.count)
+            .temp("min", stats.min)
+            .temp("max", stats.max)
+            .temp("mean", stats.mean)
+            .temp("stddev", stats.stddev)
+            .end();
+    })
+    .await;
+    print(cx, |tx| {
+        Line::labelled(tx, mode)
+            .temp("target", target)
+            .temp("band", DEFAULT_BAND)
+            .uint("within", u32::from(stats.within))
+            .uint("duty", u32::from(stats.duty))
+            .end();
+    })
+    .await;
+    print(cx, |tx| end_stream(tx, mode)).await;
+}
+
+/// Gets the state & thresholds of the alarms, or sets `<low> <high> <delay-secs>`
+async fn alarm(cx: &mut Context<'_>, mode: Mode, args: &[Arg]) {
+    if let [low, high, delay] = *args {
+        let config = AlarmConfig {
+            low: low.as_temp(),
+            high: high.as_temp(),
+            delay: delay.as_int(),
+        };
+        if config.low >= config.high {
+            print(cx, |tx| {
+                Line::error(tx, mode, ErrorCode::InvalidArgument).end()
+            })
+            .await;
+            return;
+        }
+        let old = cx
+            .shared
+            .alarm
+            .lock(|alarm| core::mem::replace(&mut alarm.config, config));
+        if old != config {
+            let AlarmConfig { low, high, delay } = config;
+            log_event(cx, &Event::AlarmThresholdsChanged { low, high, delay });
+        }
+        print(cx, |tx| Line::ok(tx, mode).end()).await;
+        return;
+    }
+
+    let alarm = cx.shared.alarm.lock(|alarm| alarm.clone());
+    print(cx, |tx| {
+        alarm_fields(Line::labelled(tx, mode), &alarm, now_secs()).end()
+    })
+    .await;
+    print(cx, |tx| end_stream(tx, mode)).await;
+}
+
+/// Adds the state, thresholds & silencing of the alarms to a line
+fn alarm_fields<'a, W: Write>(line: Line<'a, W>, alarm: &Alarm, now: u32) -> Line<'a, W> {
+    let state = alarm.state();
+    let line = line.str("alarm", state.as_str());
+    let line = match state.kind() {
+        Some(kind) => line.str("kind", kind.as_str()),
+        None => line,
+    };
+    line.temp("low", alarm.config.low)
+        .temp("high", alarm.config.high)
+        .uint("delay", alarm.config.delay)
+        .uint("silenced", alarm.silenced(now))
+}
+
+/// Gets the wall-clock time, or sets it to `<unix-secs>` & places the stored records in time
+async fn time(cx: &mut Context<'_>, mode: Mode, arg: Option<Arg>) {
+    if let Some(arg) = arg {
+        let unix = arg.as_int();
+        if !(MIN_UNIX..MAX_UNIX).contains(&unix) {
+            print(cx, |tx| {
+                Line::error(tx, mode, ErrorCode::InvalidArgument).end()
+            })
+            .await;
+            return;
+        }
+        set_time(cx, unix);
+        print(cx, |tx| Line::ok(tx, mode).end()).await;
+        return;
+    }
+
+    let secs = now_secs();
+    let unix = cx.shared.rtc.lock(|rtc| rtc.now());
+    print(cx, |tx| {
+        let line = Line::ok(tx, mode).uint("t", secs);
+        match unix {
+            Some(unix) => line
+                .uint("unix", unix)
+                .with("date", |tx| print_date(tx, DateTime::from_unix(unix))),
+            None => line.str("unix", "unset"),
+        }
+        .end();
+    })
+    .await;
+}
+
+/// Watches until a key is pressed, showing every `<every>`-th sample
+///
+/// Events are never skipped. The key stays in the line editor, so nothing typed is lost.
+async fn watch(cx: &mut Context<'_>, mode: Mode, args: &[Arg]) {
+    let what = args[0].as_enum();
+    let every = args.get(1).map_or(1, |n| n.as_int().max(1));
+
+    if mode == Mode::Text {
+        print(cx, |tx| print_str(tx, "Press any key to stop watching\r\n")).await;
+    }
+
+    // Only watch what happens from now on
+    while cx.local.key_rx.try_recv().is_ok() {}
+    while cx.local.rx.try_recv().is_ok() {}
+    while cx.local.event_rx.try_recv().is_ok() {}
+    while cx.local.tick_rx.try_recv().is_ok() {}
+
+    match what {
+        "temps" => {
+            let mut count = 0;
+            while let Some(temp) = recv_or_key(cx.local.rx, cx.local.key_rx).await {
+                count += 1;
+                if count % every != 0 {
+                    continue;
+                }
+                print(cx, |tx| {
+                    Line::record(tx, mode)
+                        .uint("t", temp.full_secs(now_secs()))
+                        .temp("temp", temp.value())
+                        .end();
+                })
+                .await;
+            }
+        }
+        "events" => {
+            while let Some(event) = recv_or_key(cx.local.event_rx, cx.local.key_rx).await {
+                print(cx, |tx| {
+                    let now = now_secs();
+                    let line = Line::labelled(tx, mode).uint("t", event.full_secs(now));
+                    event_fields(line, "code", &event, now).end();
+                })
+                .await;
+            }
+        }
+        _ => {
+            // Ticks & ticks the cooler was on since the last shown tick
+            let (mut ticks, mut on) = (0, 0);
+            while let Some(tick) = recv_or_key(cx.local.tick_rx, cx.local.key_rx).await {
+                ticks += 1;
+                on += u32::from(tick.on);
+                if ticks < every {
+                    continue;
+                }
+                let duty = on * 100 / ticks;
+                (ticks, on) = (0, 0);
+
+                print(cx, |tx| print_tick(tx, mode, what, &tick, duty)).await;
+            }
+        }
+    }
+
+    print(cx, |tx| end_stream(tx, mode)).await;
+}
+
+/// Receives the next value, or `None` if a key was pressed first
+async fn recv_or_key<T, const N: usize>(
+    rx: &mut Receiver<'static, T, N>,
+    key_rx: &mut Receiver<'static, (), 1>,
+) -> Option<T> {
+    let value = rx.recv();
+    let key = key_rx.recv();
+    pin_mut!(value, key);
+
+    match select(value, key).await {
+        Either::Left((Ok(value), _)) => Some(value),
+        Either::Left((Err(_), _)) => unreachable!("Sender dropped"),
+        Either::Right(_) => None,
+    }
+}
+
+/// Prints a tick of the controller for `watch <pid|cooler|status>`
+///
+/// `duty` is the percentage of ticks the cooler was on since the last printed tick.
+fn print_tick<W: Write>(tx: &mut W, mode: Mode, what: &str, tick: &Tick, duty: u32) {
+    let line = Line::record(tx, mode).uint("t", tick.secs);
+    let cooler = if tick.on { "on" } else { "off" };
+    match what {
+        "pid" => line
+            .temp("temp", tick.temp)
+            .temp("p", tick.terms.p)
+            .temp("i", tick.terms.i)
+            .temp("d", tick.terms.d)
+            .uint("output", u32::from(tick.output)),
+        "cooler" => line.str("cooler", cooler).uint("duty", duty),
+        _ => line
+            .temp("temp", tick.temp)
+            .temp("target", tick.target)
+            .uint("output", u32::from(tick.output))
+            .str("cooler", cooler)
+            .uint("duty", duty),
+    }
+    .end();
+}
+
+/// Adds the last occurrence & number of times of a repeated event, its code as `key` & the fields
+/// of its payload to a line
+fn event_fields<'a, W: Write>(
+    line: Line<'a, W>,
+    key: &str,
+    record: &StoredEvent,
+    now: u32,
+) -> Line<'a, W> {
+    let line = if record.count() > 1 {
+        line.uint("last", record.full_last_secs(now))
+            .uint("times", u32::from(record.count()))
+    } else {
+        line
+    };
+    let event = record.event();
+    let line = line.str(key, event.code().as_str());
+    match event {
+        Event::Unknown | Event::PidError | Event::StorageErased => line,
+        Event::TempSensorError { error, address } | Event::TempSensorLost { error, address } => {
+            line.str("error", error.as_str())
+                .address("sensor", Address(address))
+        }
+        Event::TempSensorResolutionChanged { bits, address } => line
+            .uint("bits", u32::from(bits))
+            .address("sensor", Address(address)),
+        Event::PidTargetChanged { old, new } => line.temp("old", old).temp("new", new),
+        Event::PidParamsChanged { old, new } => line
+            .temp("old_kp", old.kp)
+            .temp("old_ki", old.ki)
+            .temp("old_kd", old.kd)
+            .temp("kp", new.kp)
+            .temp("ki", new.ki)
+            .temp("kd", new.kd),
+        Event::Boot { count, cause } => line.uint("count", count).str("cause", cause.as_str()),
+        Event::Panic { file, line: n } => line.str("file", event::text(file)).uint("line", n),
+        Event::CoolerSwitched { on, duty } => line
+            .str("cooler", if on { "on" } else { "off" })
+            .uint("duty", u32::from(duty)),
+        Event::CoolerModeChanged { old, new } => {
+            line.str("old", old.as_str()).str("new", new.as_str())
+        }
+        Event::TempSensorFound { address } | Event::TempSensorSelected { address } => {
+            line.address("sensor", Address(address))
+        }
+        Event::ClockSet { old, new } => line.uint("old", old).uint("new", new),
+        Event::TaskStalled { task } => line.str("task", task.as_str()),
+        Event::AlarmRaised {
+            kind,
+            temp,
+            threshold,
+        } => line
+            .str("kind", kind.as_str())
+            .temp("temp", temp)
+            .temp("threshold", threshold),
+        Event::AlarmCleared { kind, temp } => line.str("kind", kind.as_str()).temp("temp", temp),
+        Event::AlarmAcknowledged { kind } => line.str("kind", kind.as_str()),
+        Event::AlarmSilenced { secs } => line.uint("secs", secs),
+        Event::AlarmThresholdsChanged { low, high, delay } => line
+            .temp("low", low)
+            .temp("high", high)
+            .uint("delay", delay),
+    }
+}
@@ -0,0 +1,384 @@
+//! Output formatting of the terminal
+//!
+//! Responses are either printed as human readable text, or as JSON lines for host scripts. Both
+//! are written through [`Line`], so every command supports both modes.
+
+use core::fmt::Write;
+
+use defmt::*;
+use num_traits::AsPrimitive;
+
+use crate::{onewire::Address, thermometer::Temperature, time::DateTime};
+
+/// Output mode of the terminal
+#[derive(Debug, Format, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Human readable text, values separated by spaces
+    Text,
+    /// A JSON object per line
+    Json,
+}
+
+impl Mode {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// Error codes reported by the terminal
+#[derive(Debug, Format, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ErrorCode {
+    UnknownCommand = 1,
+    MissingArgument,
+    InvalidArgument,
+    TooManyArguments,
+    /// The requested data isn't available (yet)
+    Missing,
+    /// A line of the response didn't fit the output buffer, a bug
+    OutputTooLong,
+}
+
+impl ErrorCode {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::UnknownCommand => "unknown command",
+            Self::MissingArgument => "missing argument",
+            Self::InvalidArgument => "invalid argument",
+            Self::TooManyArguments => "too many arguments",
+            Self::Missing => "missing",
+            Self::OutputTooLong => "output too long",
+        }
+    }
+}
+
+/// A single line of output
+///
+/// In text mode, fields are printed separated by spaces. In JSON mode, fields are members of an
+/// object, e.g. `{"status":"ok","t":1234,"temp":4.25}`.
+#[must_use = "the line must be ended with `Line::end`"]
+pub struct Line<'a, W: Write> {
+    tx: &'a mut W,
+    mode: Mode,
+    /// Whether this is a bare `ok` status
+    ok: bool,
+    /// Whether fields are labelled in text mode, e.g. `temp=4.25`
+    labels: bool,
+    fields: u8,
+}
+
+impl<'a, W: Write> Line<'a, W> {
+    /// Starts the response of a successful command
+    ///
+    /// In text mode, prints `<ok>` if the response has no fields.
+    pub fn ok(tx: &'a mut W, mode: Mode) -> Self {
+        if mode == Mode::Json {
+            print_str(tx, "{\"status\":\"ok\"");
+        }
+        Self {
+            tx,
+            mode,
+            ok: true,
+            labels: false,
+            fields: 0,
+        }
+    }
+
+    /// Starts a record of a dump or watch stream
+    pub fn record(tx: &'a mut W, mode: Mode) -> Self {
+        if mode == Mode::Json {
+            print_str(tx, "{");
+        }
+        Self {
+            tx,
+            mode,
+            ok: false,
+            labels: false,
+            fields: 0,
+        }
+    }
+
+    /// Starts a record with too many fields to tell apart by position, labelling them in text mode
+    pub fn labelled(tx: &'a mut W, mode: Mode) -> Self {
+        Self {
+            labels: true,
+            ..Self::record(tx, mode)
+        }
+    }
+
+    /// Starts the response of a failed command
+    ///
+    /// In text mode, prints the error as `<error>`.
+    pub fn error(tx: &'a mut W, mode: Mode, code: ErrorCode) -> Self {
+        match mode {
+            Mode::Text => {
+                print_str(tx, "<");
+                print_str(tx, code.as_str());
+                print_str(tx, ">");
+            }
+            Mode::Json => {
+                print_str(tx, "{\"status\":\"error\",\"code\":");
+                print_uint(tx, u32::from(code as u8));
+                print_str(tx, ",\"error\":\"");
+                print_str(tx, code.as_str());
+                print_str(tx, "\"");
+            }
+        }
+        Self {
+            tx,
+            mode,
+            ok: false,
+            labels: false,
+            fields: 1,
+        }
+    }
+
+    /// Starts a new field, printing the separator & key
+    fn key(&mut self, key: &str) {
+        match self.mode {
+            Mode::Text => {
+                if self.fields > 0 {
+                    print_str(self.tx, " ");
+                }
+                if self.labels {
+                    print_str(self.tx, key);
+                    print_str(self.tx, "=");
+                }
+            }
+            Mode::Json => {
+                if self.ok || self.fields > 0 {
+                    print_str(self.tx, ",");
+                }
+                print_str(self.tx, "\"");
+                print_str(self.tx, key);
+                print_str(self.tx, "\":");
+            }
+        }
+        self.fields += 1;
+    }
+
+    pub fn uint(mut self, key: &str, value: u32) -> Self {
+        self.key(key);
+        print_uint(self.tx, value);
+        self
+    }
+
+    pub fn temp(mut self, key: &str, value: Temperature) -> Self {
+        self.key(key);
+        print_temp(self.tx, value);
+        self
+    }
+
+    pub fn str(self, key: &str, value: &str) -> Self {
+        self.bytes(key, value.as_bytes())
+    }
+
+    /// A string field, which may contain anything the user typed
+    pub fn bytes(mut self, key: &str, value: &[u8]) -> Self {
+        self.key(key);
+        if self.mode == Mode::Text {
+            print_bytes(self.tx, value);
+        } else {
+            print_json_str(self.tx, value);
+        }
+        self
+    }
+
+    pub fn address(self, key: &str, value: Address) -> Self {
+        self.with(key, |tx| print_address(tx, value))
+    }
+
+    /// A string field printed by `f`
+    ///
+    /// `f` must not print anything that needs to be escaped in JSON.
+    pub fn with(mut self, key: &str, f: impl FnOnce(&mut W)) -> Self {
+        self.key(key);
+        let quote = self.mode == Mode::Json;
+        if quote {
+            print_str(self.tx, "\"");
+        }
+        f(self.tx);
+        if quote {
+            print_str(self.tx, "\"");
+        }
+        self
+    }
+
+    /// Ends the line
+    pub fn end(self) {
+        match self.mode {
+            Mode::Text if self.ok && self.fields == 0 => print_str(self.tx, "<ok>\r\n"),
+            Mode::Text => print_str(self.tx, "\r\n"),
+            Mode::Json => print_str(self.tx, "}\r\n"),
+        }
+    }
+}
+
+/// Ends a dump or watch stream
+///
+/// Text streams just stop, JSON streams end with an `ok` status.
+pub fn end_stream<W: Write>(tx: &mut W, mode: Mode) {
+    if mode == Mode::Json {
+        Line::ok(tx, mode).end();
+    }
+}
+
+/// Notes records of a dump that were overwritten before they could be sent
+///
+/// Printed as `<n lost>` in text mode & as a `{"lost":n}` record in JSON mode.
+pub fn print_lost<W: Write>(tx: &mut W, mode: Mode, count: u32) {
+    match mode {
+        Mode::Text => {
+            print_str(tx, "<");
+            print_uint(tx, count);
+            print_str(tx, " lost>\r\n");
+        }
+        Mode::Json => Line::record(tx, mode).uint("lost", count).end(),
+    }
+}
+
+/// Notes the Unix time at startup of the records of a dump, so they can be placed in time
+///
+/// Printed before the records as `<epoch n>` in text mode & as an `{"epoch":n}` record in JSON
+/// mode, only if the clock is set.
+pub fn print_epoch<W: Write>(tx: &mut W, mode: Mode, epoch: u32) {
+    match mode {
+        Mode::Text => {
+            print_str(tx, "<epoch ");
+            print_uint(tx, epoch);
+            print_str(tx, ">\r\n");
+        }
+        Mode::Json => Line::record(tx, mode).uint("epoch", epoch).end(),
+    }
+}
+
+pub fn print_str<W: Write>(tx: &mut W, str: &str) {
+    if tx.write_str(str).is_err() {
+        error!("Failed to write to UART");
+    }
+}
+
+pub fn print_bytes<W: Write>(tx: &mut W, bytes: &[u8]) {
+    // SAFETY: bytes may not be valid UTF-8, but we don't care cause we're just printing it
+    // Also, including UTF8 checks would add a lot to the binary size
+    print_str(tx, unsafe { core::str::from_utf8_unchecked(bytes) });
+}
+
+/// Prints bytes as a quoted JSON string
+fn print_json_str<W: Write>(tx: &mut W, bytes: &[u8]) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+
+    print_str(tx, "\"");
+    for &b in bytes {
+        match b {
+            b'"' => print_str(tx, "\\\""),
+            b'\\' => print_str(tx, "\\\\"),
+            b' '..=b'~' => print_bytes(tx, &[b]),
+            _ => print_bytes(
+                tx,
+                &[
+                    b'\\',
+                    b'u',
+                    b'0',
+                    b'0',
+                    HEX[usize::from(b >> 4)],
+                    HEX[usize::from(b & 0xF)],
+                ],
+            ),
+        }
+    }
+    print_str(tx, "\"");
+}
+
+pub fn print_temp<W: Write>(tx: &mut W, temp: Temperature) {
+    // Every multiple of 1/16 has an exact 4 digit decimal fraction
+    const FRAC_DIGITS: usize = 4;
+    const FRAC_MASK: u16 = (1 << Temperature::FRAC_NBITS) - 1;
+    // 1/16 = 0.0625
+    const FRAC_STEP: u32 = 625;
+
+    let bits = temp.to_bits().unsigned_abs();
+    let int_part = bits >> Temperature::FRAC_NBITS;
+    let mut frac_part = u32::from(bits & FRAC_MASK) * FRAC_STEP;
+
+    let mut buf = [b'0'; FRAC_DIGITS];
+    for b in buf.iter_mut().rev() {
+        let digit: u8 = (frac_part % 10).as_();
+        *b = b'0' + digit;
+        frac_part /= 10;
+    }
+    // Strip trailing zeros, but keep at least one digit
+    let len = buf.iter().rposition(|b| *b != b'0').map_or(1, |i| i + 1);
+
+    if temp.is_negative() {
+        print_str(tx, "-");
+    }
+    print_uint(tx, u32::from(int_part));
+    print_str(tx, ".");
+    print_bytes(tx, &buf[..len]);
+}
+
+pub fn print_uint<W: Write>(tx: &mut W, mut num: u32) {
+    const BUF_SIZE: usize = 10;
+
+    let mut buf = [0u8; BUF_SIZE];
+    let mut idx = 0;
+
+    loop {
+        let digit: u8 = (num % 10).as_();
+        num /= 10;
+
+        buf[BUF_SIZE - idx - 1] = b'0' + digit;
+        idx += 1;
+
+        if num == 0 {
+            break;
+        }
+    }
+
+    let buf = &buf[BUF_SIZE - idx..];
+    // SAFETY: buf is guaranteed to be valid ASCII
+    print_str(tx, unsafe { core::str::from_utf8_unchecked(buf) });
+}
+
+/// Prints a number with leading zeros, at least `width` digits
+fn print_padded<W: Write>(tx: &mut W, num: u32, width: u32) {
+    for digits in 1..width {
+        if num < 10u32.pow(digits) {
+            print_str(tx, "0");
+        }
+    }
+    print_uint(tx, num);
+}
+
+/// Prints a date & time in ISO 8601, e.g. `2024-03-01T12:00:00Z`
+pub fn print_date<W: Write>(tx: &mut W, date: DateTime) {
+    print_padded(tx, u32::from(date.year), 4);
+    print_str(tx, "-");
+    print_padded(tx, u32::from(date.month), 2);
+    print_str(tx, "-");
+    print_padded(tx, u32::from(date.day), 2);
+    print_str(tx, "T");
+    print_padded(tx, u32::from(date.hour), 2);
+    print_str(tx, ":");
+    print_padded(tx, u32::from(date.minute), 2);
+    print_str(tx, ":");
+    print_padded(tx, u32::from(date.second), 2);
+    print_str(tx, "Z");
+}
+
+/// Prints an address as 16 hex digits
+pub fn print_address<W: Write>(tx: &mut W, addr: Address) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut buf = [0u8; 16];
+    for (i, b) in buf.iter_mut().enumerate() {
+        let nibble: usize = ((addr.0 >> ((15 - i) * 4)) & 0xF).as_();
+        *b = HEX[nibble];
+    }
+
+    print_bytes(tx, &buf);
+}
@@ -0,0 +1,131 @@
+//! Buffered transmitting on USART2
+//!
+//! Writers queue bytes in the [`TxBuffer`] & pend the USART2 interrupt, which moves them to the
+//! usart whenever it is ready, using the TXE interrupt. Tasks await free space in the buffer
+//! instead of blocking on the usart, so they never hold their locks while bytes trickle out.
+
+use core::{
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+
+use heapless::Deque;
+use rtic::Mutex;
+use stm32f0xx_hal::{
+    gpio::{
+        gpioa::{PA15, PA2},
+        Alternate, AF1,
+    },
+    pac::{Interrupt, USART2},
+    prelude::*,
+    serial::{Event, Serial},
+};
+
+pub type Usart = Serial<USART2, PA2<Alternate<AF1>>, PA15<Alternate<AF1>>>;
+
+/// Number of bytes waiting for transmission
+pub const TX_BUFFER_SIZE: usize = 128;
+
+/// Bytes waiting to be transmitted by the USART2 interrupt
+pub struct TxBuffer {
+    buf: Deque<u8, TX_BUFFER_SIZE>,
+    /// Writer waiting for free space, only a single writer task is supported
+    waker: Option<Waker>,
+}
+
+impl TxBuffer {
+    pub const fn new() -> Self {
+        Self {
+            buf: Deque::new(),
+            waker: None,
+        }
+    }
+
+    /// Queues as many of `bytes` as fit, returning the number of bytes queued
+    pub fn push(&mut self, bytes: &[u8]) -> usize {
+        let len = bytes.len().min(self.buf.capacity() - self.buf.len());
+        for b in &bytes[..len] {
+            // SAFETY: checked the free space above
+            unsafe { self.buf.push_back_unchecked(*b) };
+        }
+        len
+    }
+
+    /// Moves queued bytes to the usart until it is busy, for the USART2 interrupt
+    ///
+    /// Keeps the TXE interrupt enabled as long as bytes are left & wakes the waiting writer.
+    pub fn drain(&mut self, usart: &mut Usart) {
+        let mut moved = false;
+        while let Some(&b) = self.buf.front() {
+            if usart.write(b).is_err() {
+                break;
+            }
+            self.buf.pop_front();
+            moved = true;
+        }
+
+        if self.buf.is_empty() {
+            usart.unlisten(Event::Txe);
+        } else {
+            usart.listen(Event::Txe);
+        }
+        if moved {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Echoing from the USART2 interrupt, which can't wait for free space
+impl embedded_hal::serial::Write<u8> for TxBuffer {
+    type Error = core::convert::Infallible;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.buf.push_back(word).map_err(|_| nb::Error::WouldBlock)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// Queues all of `bytes` for transmission, awaiting free space in the buffer
+pub async fn write_all(tx: &mut impl Mutex<T = TxBuffer>, mut bytes: &[u8]) {
+    poll_fn(|cx| {
+        tx.lock(|tx| {
+            let len = tx.push(bytes);
+            if len > 0 {
+                rtic::pend(Interrupt::USART2);
+            }
+            bytes = &bytes[len..];
+
+            if bytes.is_empty() {
+                Poll::Ready(())
+            } else {
+                tx.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    })
+    .await;
+}
+
+/// Waits until every queued byte was handed to the usart
+pub async fn flush(tx: &mut impl Mutex<T = TxBuffer>) {
+    poll_fn(|cx| {
+        tx.lock(|tx| {
+            if tx.buf.is_empty() {
+                Poll::Ready(())
+            } else {
+                tx.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    })
+    .await;
+}
@@ -0,0 +1,157 @@
+//! Replays a temperature dump through a controller.
+//!
+//! Takes the output of `dump temps` (or a raw or compressed binary dump of the stored records) and
+//! prints the output the controller would have produced for every sample.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context};
+use clap::{Parser, ValueEnum};
+use fridge_core::{
+    controller::pid::{self, PidController},
+    delta,
+    record::{PreciseTemp, StoredTemp, TempFormat, TempRecord},
+    replay::{self, Sample},
+    thermometer::Temperature,
+};
+
+#[derive(Debug, Parser)]
+#[command(about)]
+struct Args {
+    /// Dump to replay, or `-` for stdin
+    dump: PathBuf,
+
+    /// Format of the dump
+    #[arg(short, long, value_enum, default_value_t = Format::Auto)]
+    format: Format,
+
+    /// Controller to replay the dump through
+    #[arg(short, long, value_enum, default_value_t = ControllerKind::Pid)]
+    controller: ControllerKind,
+
+    /// Target temperature in degrees Celsius
+    #[arg(long, value_parser = parse_temp, default_value_t = pid::TARGET_TEMP)]
+    target: Temperature,
+
+    /// Proportional gain
+    #[arg(long, value_parser = parse_temp, default_value_t = pid::KP)]
+    kp: Temperature,
+
+    /// Integral gain
+    #[arg(long, value_parser = parse_temp, default_value_t = pid::KI)]
+    ki: Temperature,
+
+    /// Derivative gain
+    #[arg(long, value_parser = parse_temp, default_value_t = pid::KD)]
+    kd: Temperature,
+
+    /// Only print the summary
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum Format {
+    /// Detect the format from the contents
+    Auto,
+    /// Text output of `dump temps`
+    Text,
+    /// Raw stored temperature records
+    Binary,
+    /// Raw stored temperature records of firmware built with the `precise-temps` feature
+    PreciseBinary,
+    /// Delta-compressed temperature records, e.g. from `fridgectl dump temps --compress`
+    Compressed,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum ControllerKind {
+    Pid,
+}
+
+fn parse_temp(s: &str) -> Result<Temperature, String> {
+    s.parse().map_err(|e| format!("{e}"))
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let dump = if args.dump.as_os_str() == "-" {
+        let mut buf = Vec::new();
+        io::stdin().read_to_end(&mut buf)?;
+        buf
+    } else {
+        fs::read(&args.dump).with_context(|| format!("reading {}", args.dump.display()))?
+    };
+
+    let samples: Vec<Sample> = match args.format {
+        Format::Text => replay::parse_text(std::str::from_utf8(&dump)?).collect(),
+        Format::Binary => replay::parse_binary::<StoredTemp>(&dump).collect(),
+        Format::PreciseBinary => replay::parse_binary::<PreciseTemp>(&dump).collect(),
+        Format::Compressed => match delta::format(&dump) {
+            Some(TempFormat::Compact) => decompress::<StoredTemp>(&dump)?,
+            Some(TempFormat::Precise) => decompress::<PreciseTemp>(&dump)?,
+            None => bail!("not a compressed dump"),
+        },
+        Format::Auto => match std::str::from_utf8(&dump) {
+            Ok(text)
+                if text
+                    .bytes()
+                    .all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace()) =>
+            {
+                replay::parse_text(text).collect()
+            }
+            _ => replay::parse_binary::<StoredTemp>(&dump).collect(),
+        },
+    };
+    if samples.is_empty() {
+        bail!("no temperature records found in dump");
+    }
+
+    let controller = match args.controller {
+        ControllerKind::Pid => PidController::new(args.target, args.kp, args.ki, args.kd),
+    };
+
+    let mut out = io::stdout().lock();
+    let mut on = 0usize;
+    let mut total = 0u64;
+
+    for step in replay::replay(controller, samples.iter().copied()) {
+        let Ok(step) = step;
+
+        on += usize::from(step.cooler_on());
+        total += u64::from(step.output);
+
+        if !args.quiet {
+            writeln!(
+                out,
+                "{} {} {} {}",
+                step.secs,
+                step.temp,
+                step.output,
+                if step.cooler_on() { "on" } else { "off" }
+            )?;
+        }
+    }
+
+    let n = samples.len();
+    writeln!(out, "samples: {n}")?;
+    writeln!(out, "mean output: {}", total / n as u64)?;
+    writeln!(out, "cooler on: {}/{n} ({}%)", on, on * 100 / n)?;
+
+    Ok(())
+}
+
+/// Decodes a compressed dump of records of type `R`
+fn decompress<R: TempRecord>(dump: &[u8]) -> anyhow::Result<Vec<Sample>> {
+    let mut decoder = delta::decode::<R>(dump);
+    let samples = decoder.by_ref().map(Sample::from_record).collect();
+    if let Some(e) = decoder.error() {
+        bail!("corrupt compressed dump: {}", e.as_str());
+    }
+    Ok(samples)
+}
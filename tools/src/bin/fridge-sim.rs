@@ -0,0 +1,1140 @@
+//! Stand-in for the firmware's serial console on a pseudo terminal.
+//!
+//! Prints the path of the pseudo terminal, to point host tools like `fridgectl` at instead of a
+//! fridge. The console's commands are emulated in text & JSON mode, controlling a simulated
+//! fridge.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{Read, Write},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use clap::Parser;
+use fridge_core::{
+    alarm::{Alarm, AlarmConfig, Transition},
+    controller::{pid::PidGains, CoolerMode},
+    event::{self, Event, ResetCause, Severity},
+    record::{extend_secs, Aggregator, StoredAggregate, Tier},
+    stats::{Accumulator, DEFAULT_BAND},
+    thermometer::Temperature,
+    time::{DateTime, MAX_UNIX, MIN_UNIX},
+};
+use nix::{
+    pty::openpty,
+    sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg},
+    unistd::ttyname,
+};
+
+#[derive(Debug, Parser)]
+#[command(about)]
+struct Args {
+    /// Seconds between temperature measurements
+    #[arg(short, long, default_value_t = 2.0)]
+    interval: f64,
+
+    /// Temperature the fridge warms up to with the cooler off
+    #[arg(short, long, default_value_t = 20.0, allow_negative_numbers = true)]
+    ambient: f64,
+}
+
+/// Stored temperatures, as in the firmware
+const MAX_TEMPS: usize = 60;
+/// Stored per-minute & per-hour aggregates, as in the firmware
+const MAX_MINUTES: usize = 60;
+const MAX_HOURS: usize = 7 * 24;
+/// Stored events, as in the firmware
+const MAX_EVENTS: usize = 16;
+/// Resolution of stored temperatures
+const STORED_STEP: f64 = 0.25;
+
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const DEL: u8 = 0x7F;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    Text,
+    Json,
+}
+
+/// A field of a response line
+enum Field {
+    Int(u64),
+    Num(f64),
+    Str(String),
+}
+
+struct Fridge {
+    ambient: f64,
+    start: Instant,
+    temp: f64,
+    target: f64,
+    gains: [f64; 3],
+    resolution: u8,
+    sensor: String,
+    cooler_on: bool,
+    /// `auto`, `on` or `off`
+    cooler_mode: &'static str,
+    temps: VecDeque<(u64, f64)>,
+    /// Whether the cooler was on for each stored temperature
+    temps_on: VecDeque<bool>,
+    minutes: VecDeque<StoredAggregate>,
+    hours: VecDeque<StoredAggregate>,
+    /// Aggregates of the current minute & hour
+    minute: Aggregator,
+    hour: Aggregator,
+    events: VecDeque<LoggedEvent>,
+    /// Events dropped by the rate limits of their codes
+    dropped_events: u64,
+    /// Records of the events of the last tick, as the firmware sends them for watching
+    ticked: Vec<LoggedEvent>,
+    alarm: Alarm,
+    /// Alarms raised or cleared by the last tick, pushed to the console unsolicited
+    alerts: Vec<LoggedEvent>,
+    ticks: u64,
+    /// Ticks the cooler was on
+    on_ticks: u64,
+    /// Unix time at startup, `None` until the clock is set
+    epoch: Option<u64>,
+    /// Cause of the last reset & number of boots, as kept in flash by the firmware
+    reset: ResetCause,
+    boots: u32,
+}
+
+/// A stored event, repeats of it counted as in the firmware
+#[derive(Clone)]
+struct LoggedEvent {
+    /// Seconds of the first & last occurrences
+    t: u64,
+    last: u64,
+    times: u8,
+    event: Event,
+}
+
+struct Console {
+    out: File,
+    fridge: Fridge,
+    mode: Mode,
+    line: Vec<u8>,
+    last_cr: bool,
+    watching: Option<Watch>,
+}
+
+/// A `watch` in progress
+struct Watch {
+    what: String,
+    /// Show every n-th tick
+    every: u64,
+    ticks: u64,
+    /// Ticks the cooler was on since the last shown tick
+    on: u64,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let pty = openpty(None, None).context("opening pseudo terminal")?;
+    // Keep the console's output from being echoed back to it
+    let mut termios = tcgetattr(&pty.slave)?;
+    cfmakeraw(&mut termios);
+    tcsetattr(&pty.slave, SetArg::TCSANOW, &termios)?;
+    println!("{}", ttyname(&pty.slave)?.display());
+
+    let out = File::from(pty.master);
+    let mut input = out.try_clone()?;
+
+    // The slave end stays open, so reads block instead of failing while no tool is connected
+    let _slave = pty.slave;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 64];
+        while let Ok(n @ 1..) = input.read(&mut buf) {
+            if tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut console = Console {
+        out,
+        fridge: Fridge::new(args.ambient, ResetCause::PowerOn, 1),
+        mode: Mode::Text,
+        line: Vec::new(),
+        last_cr: false,
+        watching: None,
+    };
+
+    let interval = Duration::from_secs_f64(args.interval);
+    let mut next_tick = Instant::now() + interval;
+    loop {
+        match rx.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+            Ok(bytes) => {
+                for b in bytes {
+                    console.feed(b)?;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                next_tick += interval;
+                let temp = console.fridge.tick();
+                console.alerts()?;
+                console.watch_tick(temp)?;
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+impl Fridge {
+    fn new(ambient: f64, reset: ResetCause, boots: u32) -> Self {
+        let mut fridge = Self {
+            ambient,
+            start: Instant::now(),
+            temp: ambient,
+            target: 5.0,
+            gains: [1.0, 0.25, 0.125],
+            resolution: 12,
+            sensor: "0500000F83FB6028".into(),
+            cooler_on: false,
+            cooler_mode: "auto",
+            temps: VecDeque::new(),
+            temps_on: VecDeque::new(),
+            minutes: VecDeque::new(),
+            hours: VecDeque::new(),
+            minute: Aggregator::new(Tier::Minute),
+            hour: Aggregator::new(Tier::Hour),
+            events: VecDeque::new(),
+            dropped_events: 0,
+            ticked: Vec::new(),
+            alarm: Alarm::new(AlarmConfig::DEFAULT),
+            alerts: Vec::new(),
+            ticks: 0,
+            on_ticks: 0,
+            epoch: None,
+            reset,
+            boots,
+        };
+        fridge.event(Event::Boot {
+            count: boots,
+            cause: reset,
+        });
+        let address = fridge.address();
+        fridge.event(Event::TempSensorFound { address });
+        fridge
+    }
+
+    /// 1-Wire address of the selected thermometer
+    fn address(&self) -> u64 {
+        u64::from_str_radix(&self.sensor, 16).unwrap_or_default()
+    }
+
+    /// Percentage of ticks the cooler was on
+    fn duty(&self) -> u64 {
+        (self.on_ticks * 100).checked_div(self.ticks).unwrap_or(0)
+    }
+
+    /// Switches the cooler, logging it if it was switched
+    fn switch_cooler(&mut self, on: bool) {
+        if on != self.cooler_on {
+            self.cooler_on = on;
+            let duty = u8::try_from(self.duty()).unwrap_or(100);
+            self.event(Event::CoolerSwitched { on, duty });
+        }
+    }
+
+    fn secs(&self) -> u64 {
+        self.start.elapsed().as_secs()
+    }
+
+    /// Current Unix time, `None` if the clock isn't set
+    fn unix(&self) -> Option<u64> {
+        self.epoch.map(|epoch| epoch + self.secs())
+    }
+
+    /// Measures a temperature & runs the controller, returning the stored temperature
+    fn tick(&mut self) -> (u64, f64) {
+        self.ticked.clear();
+        self.alerts.clear();
+        let on = match self.cooler_mode {
+            "on" => true,
+            "off" => false,
+            _ => self.temp > self.target,
+        };
+
+        // The firmware logs a switch with the duty including the tick
+        self.ticks += 1;
+        self.on_ticks += u64::from(on);
+        self.switch_cooler(on);
+        if self.cooler_on {
+            self.temp -= 0.3;
+        } else {
+            self.temp += (self.ambient - self.temp) * 0.02;
+        }
+
+        let secs = self.secs();
+        let temp = Temperature::saturating_from_num(self.temp);
+        // The firmware counts seconds in 32 bits
+        let secs32 = u32::try_from(secs).unwrap_or(u32::MAX);
+        if let Some(minute) = self.minute.add(secs32, temp) {
+            push_bounded(&mut self.minutes, minute, MAX_MINUTES);
+        }
+        if let Some(hour) = self.hour.add(secs32, temp) {
+            push_bounded(&mut self.hours, hour, MAX_HOURS);
+        }
+
+        let stored = (secs, (self.temp / STORED_STEP).round() * STORED_STEP);
+        if self.temps.len() == MAX_TEMPS {
+            self.temps.pop_front();
+            self.temps_on.pop_front();
+        }
+        self.temps.push_back(stored);
+        self.temps_on.push_back(self.cooler_on);
+
+        // The firmware checks the stored temperature
+        let temp = temperature(stored.1);
+        let event = match self.alarm.update(secs32, temp) {
+            Some(Transition::Raised(kind)) => Some(Event::AlarmRaised {
+                kind,
+                temp,
+                threshold: self.alarm.threshold(kind),
+            }),
+            Some(Transition::Cleared(kind)) => Some(Event::AlarmCleared { kind, temp }),
+            None => None,
+        };
+        if let Some(event) = event {
+            self.event(event);
+            self.alerts.push(LoggedEvent {
+                t: secs,
+                last: secs,
+                times: 1,
+                event,
+            });
+        }
+        stored
+    }
+
+    /// Stores an event, counting repeats & applying the rate limits like the firmware
+    fn event(&mut self, event: Event) {
+        let now = self.secs();
+        let repeat = |e: &&mut LoggedEvent| {
+            e.event == event && event.code().coalesces() && now - e.t <= u64::from(u16::MAX)
+        };
+        if let Some(recent) = self.events.back_mut().filter(repeat) {
+            recent.last = now;
+            recent.times = recent.times.saturating_add(1);
+            self.ticked.push(recent.clone());
+            return;
+        }
+        let interval = u64::from(event.code().min_interval());
+        let limited = self
+            .events
+            .iter()
+            .rev()
+            .find(|e| e.event.code() == event.code())
+            .is_some_and(|e| now - e.t < interval);
+        if limited {
+            self.dropped_events += 1;
+            return;
+        }
+        let logged = LoggedEvent {
+            t: now,
+            last: now,
+            times: 1,
+            event,
+        };
+        self.ticked.push(logged.clone());
+        push_bounded(&mut self.events, logged, MAX_EVENTS);
+    }
+}
+
+impl Console {
+    fn write(&mut self, s: &str) -> std::io::Result<()> {
+        self.out.write_all(s.as_bytes())
+    }
+
+    /// Feeds a received byte to the line editor
+    fn feed(&mut self, b: u8) -> anyhow::Result<()> {
+        let last_cr = std::mem::replace(&mut self.last_cr, b == b'\r');
+
+        // Any key stops watching & is kept as input
+        if self.watching.is_some() && !(b == b'\n' && last_cr) {
+            self.watching = None;
+            self.end_stream()?;
+        }
+
+        match b {
+            b'\n' if last_cr => {}
+            b'\r' | b'\n' => {
+                self.write("\r\n")?;
+                let line = String::from_utf8_lossy(&std::mem::take(&mut self.line)).into_owned();
+                self.run(&line)?;
+            }
+            BACKSPACE | DEL if !self.line.is_empty() => {
+                self.line.pop();
+                self.write("\x08 \x08")?;
+            }
+            CTRL_C => {
+                self.write("^C\r\n")?;
+                self.line.clear();
+            }
+            b' '..=b'~' => {
+                self.line.push(b);
+                self.out.write_all(&[b])?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Prints a line of fields, with an `ok` status if `ok`
+    fn line(&mut self, ok: bool, fields: &[(&str, Field)]) -> anyhow::Result<()> {
+        let line = match self.mode {
+            Mode::Text if ok && fields.is_empty() => "<ok>".to_owned(),
+            Mode::Text => fields
+                .iter()
+                .map(|(_, f)| match f {
+                    Field::Int(n) => n.to_string(),
+                    Field::Num(n) => format!("{n:?}"),
+                    Field::Str(s) => s.clone(),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            Mode::Json => {
+                let status = ok.then(|| "\"status\":\"ok\"".to_owned());
+                let fields = fields.iter().map(|(k, f)| {
+                    let v = match f {
+                        Field::Int(n) => serde_json::to_string(n),
+                        Field::Num(n) => serde_json::to_string(n),
+                        Field::Str(s) => serde_json::to_string(s),
+                    };
+                    format!("\"{k}\":{}", v.unwrap_or_default())
+                });
+                format!(
+                    "{{{}}}",
+                    status
+                        .into_iter()
+                        .chain(fields)
+                        .collect::<Vec<_>>()
+                        .join(",")
+                )
+            }
+        };
+        self.write(&line)?;
+        self.write("\r\n")?;
+        Ok(())
+    }
+
+    fn ok(&mut self, fields: &[(&str, Field)]) -> anyhow::Result<()> {
+        self.line(true, fields)
+    }
+
+    fn record(&mut self, fields: &[(&str, Field)]) -> anyhow::Result<()> {
+        self.line(false, fields)
+    }
+
+    fn error(&mut self, code: u8, error: &str, arg: &str) -> anyhow::Result<()> {
+        let line = match self.mode {
+            Mode::Text => format!("{error}: '{arg}'"),
+            Mode::Json => format!(
+                "{{\"status\":\"error\",\"code\":{code},\"error\":\"{error}\",\"arg\":{}}}",
+                serde_json::to_string(arg)?
+            ),
+        };
+        self.write(&line)?;
+        self.write("\r\n")?;
+        Ok(())
+    }
+
+    /// Prints the alerts of the last tick as unsolicited lines
+    fn alerts(&mut self) -> anyhow::Result<()> {
+        for alert in self.fridge.alerts.clone() {
+            let mut fields = vec![("t", Field::Int(alert.t))];
+            fields.extend(event_fields("alert", &alert));
+            self.labelled(&fields)?;
+        }
+        Ok(())
+    }
+
+    /// Shows a tick of the fridge if it is watched
+    fn watch_tick(&mut self, (t, temp): (u64, f64)) -> anyhow::Result<()> {
+        let f = &self.fridge;
+        let Some(watch) = &mut self.watching else {
+            return Ok(());
+        };
+        // Only ticks log events while watching, as any key stops it
+        if watch.what == "events" {
+            for event in f.ticked.clone() {
+                let mut fields = vec![("t", Field::Int(event.t))];
+                fields.extend(event_fields("code", &event));
+                self.labelled(&fields)?;
+            }
+            return Ok(());
+        }
+
+        watch.ticks += 1;
+        watch.on += u64::from(f.cooler_on);
+        if watch.ticks % watch.every != 0 {
+            return Ok(());
+        }
+        let duty = watch.on * 100 / watch.every;
+        watch.on = 0;
+
+        let cooler = Field::Str(if f.cooler_on { "on" } else { "off" }.into());
+        let output = Field::Int(if f.cooler_on { 255 } else { 0 });
+        let fields = match watch.what.as_str() {
+            "temps" => vec![("t", Field::Int(t)), ("temp", Field::Num(temp))],
+            // The simulated fridge is bang-bang, so only show a proportional term
+            "pid" => vec![
+                ("t", Field::Int(t)),
+                ("temp", Field::Num(f.temp)),
+                (
+                    "p",
+                    Field::Num((f.gains[0] * (f.target - f.temp)).clamp(-128.0, 128.0)),
+                ),
+                ("i", Field::Num(0.0)),
+                ("d", Field::Num(0.0)),
+                ("output", output),
+            ],
+            "cooler" => vec![
+                ("t", Field::Int(t)),
+                ("cooler", cooler),
+                ("duty", Field::Int(duty)),
+            ],
+            _ => vec![
+                ("t", Field::Int(t)),
+                ("temp", Field::Num(f.temp)),
+                ("target", Field::Num(f.target)),
+                ("output", output),
+                ("cooler", cooler),
+                ("duty", Field::Int(duty)),
+            ],
+        };
+        self.record(&fields)
+    }
+
+    /// Prints a record, labelling the fields in text mode
+    fn labelled(&mut self, fields: &[(&str, Field)]) -> anyhow::Result<()> {
+        if self.mode == Mode::Json {
+            return self.record(fields);
+        }
+        let line = fields
+            .iter()
+            .map(|(k, f)| match f {
+                Field::Int(n) => format!("{k}={n}"),
+                Field::Num(n) => format!("{k}={n:?}"),
+                Field::Str(s) => format!("{k}={s}"),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.write(&line)?;
+        self.write("\r\n")?;
+        Ok(())
+    }
+
+    /// Notes the Unix time at startup before the records of a dump, if the clock is set
+    fn epoch_note(&mut self) -> anyhow::Result<()> {
+        let Some(epoch) = self.fridge.epoch else {
+            return Ok(());
+        };
+        match self.mode {
+            Mode::Text => {
+                self.write(&format!("<epoch {epoch}>\r\n"))?;
+                Ok(())
+            }
+            Mode::Json => self.record(&[("epoch", Field::Int(epoch))]),
+        }
+    }
+
+    fn end_stream(&mut self) -> anyhow::Result<()> {
+        if self.mode == Mode::Json {
+            self.ok(&[])?;
+        }
+        Ok(())
+    }
+
+    /// Runs a command line
+    fn run(&mut self, line: &str) -> anyhow::Result<()> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Ok(());
+        };
+        let nums: Option<Vec<f64>> = args.iter().map(|a| a.parse().ok()).collect();
+        let f = &mut self.fridge;
+
+        match (name, args, nums.as_deref()) {
+            ("help", _, _) => {
+                for usage in [
+                    "help [<command>]",
+                    "mode [<text|json>]",
+                    "devices",
+                    "sensor [<address>]",
+                    "resolution [<9|10|11|12>]",
+                    "target [<temp>]",
+                    "pid [<kp> <ki> <kd>]",
+                    "temp",
+                    "cooler [<auto|on|off>]",
+                    "status",
+                    "stats [<secs>]",
+                    "alarm [<low> <high> <delay-secs>]",
+                    "ack",
+                    "silence <secs>",
+                    "time [<unix-secs>]",
+                    "crashlog",
+                    "watch <temps|events|pid|cooler|status> [<every>]",
+                    "dump <temps|minutes|hours|events> [<last|since|severity|code> <n>]",
+                    "erase",
+                    "reset",
+                ] {
+                    self.record(&[("usage", Field::Str(usage.into()))])?;
+                }
+                self.end_stream()
+            }
+            ("mode", [], _) => {
+                let mode = match self.mode {
+                    Mode::Text => "text",
+                    Mode::Json => "json",
+                };
+                self.ok(&[("mode", Field::Str(mode.into()))])
+            }
+            ("mode", ["text" | "json"], _) => {
+                self.mode = if args[0] == "json" {
+                    Mode::Json
+                } else {
+                    Mode::Text
+                };
+                self.ok(&[])
+            }
+            ("devices", [], _) => {
+                let sensor = f.sensor.clone();
+                self.record(&[("address", Field::Str(sensor))])?;
+                self.end_stream()
+            }
+            ("sensor", [], _) => {
+                let sensor = f.sensor.clone();
+                self.ok(&[("sensor", Field::Str(sensor))])
+            }
+            ("sensor", [addr], _) if addr.len() <= 16 && u64::from_str_radix(addr, 16).is_ok() => {
+                let sensor = format!("{:016X}", u64::from_str_radix(addr, 16)?);
+                if sensor != f.sensor {
+                    f.sensor = sensor;
+                    let address = f.address();
+                    f.event(Event::TempSensorSelected { address });
+                }
+                self.ok(&[])
+            }
+            ("resolution", [], _) => {
+                let res = f.resolution;
+                self.ok(&[("resolution", Field::Int(res.into()))])
+            }
+            ("resolution", ["9" | "10" | "11" | "12"], _) => {
+                f.resolution = args[0].parse()?;
+                f.event(Event::TempSensorResolutionChanged {
+                    bits: f.resolution,
+                    address: f.address(),
+                });
+                self.ok(&[])
+            }
+            ("target", [], _) => {
+                let target = f.target;
+                self.ok(&[("target", Field::Num(target))])
+            }
+            ("target", [_], Some(&[target])) => {
+                let (old, new) = (temperature(f.target), temperature(target));
+                f.target = target;
+                if old != new {
+                    f.event(Event::PidTargetChanged { old, new });
+                }
+                self.ok(&[])
+            }
+            ("pid", [], _) => {
+                let [kp, ki, kd] = f.gains;
+                self.ok(&[
+                    ("kp", Field::Num(kp)),
+                    ("ki", Field::Num(ki)),
+                    ("kd", Field::Num(kd)),
+                ])
+            }
+            ("pid", [_, _, _], Some(&[kp, ki, kd])) => {
+                let old = gains(f.gains);
+                f.gains = [kp, ki, kd];
+                let new = gains(f.gains);
+                if old != new {
+                    f.event(Event::PidParamsChanged { old, new });
+                }
+                self.ok(&[])
+            }
+            ("temp", [], _) => match f.temps.back().copied() {
+                Some((t, temp)) => self.ok(&[("t", Field::Int(t)), ("temp", Field::Num(temp))]),
+                None => self.error(5, "missing", ""),
+            },
+            ("status", [], _) => {
+                let f = &self.fridge;
+                let mut temp = match f.temps.back() {
+                    Some(&(t, temp)) => vec![("t", Field::Int(t)), ("temp", Field::Num(temp))],
+                    None => vec![("temp", Field::Str("none".into()))],
+                };
+                temp.extend([
+                    ("target", Field::Num(f.target)),
+                    ("controller", Field::Str("bang-bang".into())),
+                    ("output", Field::Int(if f.cooler_on { 255 } else { 0 })),
+                ]);
+                let lines = vec![
+                    vec![
+                        ("uptime", Field::Int(f.secs())),
+                        ("reset", Field::Str(f.reset.as_str().into())),
+                        ("boots", Field::Int(f.boots.into())),
+                        ("stack_free", Field::Int(1024)),
+                    ],
+                    temp,
+                    vec![
+                        (
+                            "cooler",
+                            Field::Str(if f.cooler_on { "on" } else { "off" }.into()),
+                        ),
+                        ("mode", Field::Str(f.cooler_mode.into())),
+                        ("duty", Field::Int(f.duty())),
+                    ],
+                    vec![
+                        ("resolution", Field::Int(f.resolution.into())),
+                        ("crc_errors", Field::Int(0)),
+                        ("timeouts", Field::Int(0)),
+                        ("bus_errors", Field::Int(0)),
+                    ],
+                    vec![
+                        ("temps", Field::Int(f.temps.len() as u64)),
+                        ("temps_max", Field::Int(MAX_TEMPS as u64)),
+                        ("temp_format", Field::Str("compact".into())),
+                        ("minutes", Field::Int(f.minutes.len() as u64)),
+                        ("hours", Field::Int(f.hours.len() as u64)),
+                        ("events", Field::Int(f.events.len() as u64)),
+                        ("events_max", Field::Int(MAX_EVENTS as u64)),
+                        ("events_dropped", Field::Int(f.dropped_events)),
+                    ],
+                ];
+                let alarm = alarm_fields(&f.alarm, f.secs());
+                let event = f.events.back().cloned();
+                for line in lines {
+                    self.labelled(&line)?;
+                }
+                self.labelled(&alarm)?;
+                if let Some(event) = event {
+                    let mut fields = vec![("event_t", Field::Int(event.t))];
+                    fields.extend(event_fields("event", &event));
+                    self.labelled(&fields)?;
+                }
+                self.end_stream()
+            }
+            ("stats", secs, _) if secs.len() <= 1 => {
+                let since = match secs.first().map(|n| n.parse::<u64>()) {
+                    None => 0,
+                    Some(Ok(n)) => f.secs().saturating_sub(n),
+                    Some(Err(_)) => return self.error(3, "invalid argument", secs[0]),
+                };
+                let target = Temperature::saturating_from_num(f.target);
+                let mut acc = Accumulator::new(target, DEFAULT_BAND);
+                for (&(t, temp), &on) in f.temps.iter().zip(&f.temps_on) {
+                    if t >= since {
+                        acc.add(Temperature::saturating_from_num(temp), on);
+                    }
+                }
+                let Some(stats) = acc.finish() else {
+                    return self.error(5, "missing", "");
+                };
+                let num = |temp: Temperature| Field::Num(temp.to_num());
+                self.labelled(&[
+                    ("samples", Field::Int(stats.count.into())),
+                    ("min", num(stats.min)),
+                    ("max", num(stats.max)),
+                    ("mean", num(stats.mean)),
+                    ("stddev", num(stats.stddev)),
+                ])?;
+                self.labelled(&[
+                    ("target", num(target)),
+                    ("band", num(DEFAULT_BAND)),
+                    ("within", Field::Int(stats.within.into())),
+                    ("duty", Field::Int(stats.duty.into())),
+                ])?;
+                self.end_stream()
+            }
+            ("alarm", [], _) => {
+                let fields = alarm_fields(&f.alarm, f.secs());
+                self.labelled(&fields)?;
+                self.end_stream()
+            }
+            ("alarm", [_, _, delay], Some(&[low, high, _]))
+                if low < high && delay.parse::<u32>().is_ok() =>
+            {
+                let config = AlarmConfig {
+                    low: temperature(low),
+                    high: temperature(high),
+                    delay: delay.parse()?,
+                };
+                if config != f.alarm.config {
+                    f.alarm.config = config;
+                    let AlarmConfig { low, high, delay } = config;
+                    f.event(Event::AlarmThresholdsChanged { low, high, delay });
+                }
+                self.ok(&[])
+            }
+            ("ack", [], _) => match f.alarm.acknowledge() {
+                Some(kind) => {
+                    f.event(Event::AlarmAcknowledged { kind });
+                    self.ok(&[])
+                }
+                None => self.error(5, "missing", ""),
+            },
+            ("silence", [secs], _) if secs.parse::<u32>().is_ok() => {
+                let secs = secs.parse()?;
+                let now = u32::try_from(f.secs()).unwrap_or(u32::MAX);
+                f.alarm.silence(now.saturating_add(secs));
+                f.event(Event::AlarmSilenced { secs });
+                self.ok(&[])
+            }
+            // The simulator never panics
+            ("crashlog", [], _) => self.error(5, "missing", ""),
+            ("time", [], _) => {
+                let t = Field::Int(f.secs());
+                match f.unix() {
+                    Some(unix) => {
+                        let date = DateTime::from_unix(u32::try_from(unix)?);
+                        self.ok(&[
+                            ("t", t),
+                            ("unix", Field::Int(unix)),
+                            ("date", Field::Str(iso_date(date))),
+                        ])
+                    }
+                    None => self.ok(&[("t", t), ("unix", Field::Str("unset".into()))]),
+                }
+            }
+            ("time", [unix], _)
+                if unix
+                    .parse::<u32>()
+                    .is_ok_and(|unix| (MIN_UNIX..MAX_UNIX).contains(&unix)) =>
+            {
+                let old = f
+                    .unix()
+                    .map_or(0, |old| u32::try_from(old).unwrap_or(u32::MAX));
+                let new = unix.parse()?;
+                f.epoch = Some(u64::from(new) - f.secs());
+                f.event(Event::ClockSet { old, new });
+                self.ok(&[])
+            }
+            ("cooler", [], _) => {
+                let on = if f.cooler_on { "on" } else { "off" };
+                let mode = f.cooler_mode;
+                self.ok(&[
+                    ("cooler", Field::Str(on.into())),
+                    ("mode", Field::Str(mode.into())),
+                ])
+            }
+            ("cooler", [mode @ ("auto" | "on" | "off")], _) => {
+                let old = cooler_mode(f.cooler_mode);
+                f.cooler_mode = match *mode {
+                    "on" => "on",
+                    "off" => "off",
+                    _ => "auto",
+                };
+                let new = cooler_mode(f.cooler_mode);
+                if old != new {
+                    f.event(Event::CoolerModeChanged { old, new });
+                }
+                if f.cooler_mode != "auto" {
+                    f.switch_cooler(f.cooler_mode == "on");
+                }
+                self.ok(&[])
+            }
+            (
+                "watch",
+                [what @ ("temps" | "events" | "pid" | "cooler" | "status"), every @ ..],
+                _,
+            ) if every.len() <= 1 => {
+                let every = match every.first().map(|n| n.parse::<u64>()) {
+                    None => 1,
+                    Some(Ok(n)) => n.max(1),
+                    Some(Err(_)) => return self.error(3, "invalid argument", every[0]),
+                };
+                if self.mode == Mode::Text {
+                    self.write("Press any key to stop watching\r\n")?;
+                }
+                self.watching = Some(Watch {
+                    what: (*what).to_owned(),
+                    every,
+                    ticks: 0,
+                    on: 0,
+                });
+                Ok(())
+            }
+            ("dump", ["temps", range @ ..], _) if parse_range(range).is_some() => {
+                let range = parse_range(range).flatten();
+                let temps = select(f.temps.iter().copied().collect(), range, |r| r.0);
+                self.epoch_note()?;
+                for (t, temp) in temps {
+                    self.record(&[("t", Field::Int(t)), ("temp", Field::Num(temp))])?;
+                }
+                self.end_stream()
+            }
+            ("dump", [tier @ ("minutes" | "hours"), range @ ..], _)
+                if parse_range(range).is_some() =>
+            {
+                let range = parse_range(range).flatten();
+                let log = if *tier == "minutes" {
+                    &f.minutes
+                } else {
+                    &f.hours
+                };
+                // Aggregates keep 24 bit timestamps, like the firmware
+                let now = u32::try_from(f.secs()).unwrap_or(u32::MAX);
+                let secs = |r: &StoredAggregate| u64::from(extend_secs(r.secs(), now));
+                let aggregates = select(log.iter().copied().collect(), range, secs);
+                self.epoch_note()?;
+                let num = |temp: Temperature| Field::Num(temp.to_num());
+                for aggregate in aggregates {
+                    self.record(&[
+                        ("t", Field::Int(secs(&aggregate))),
+                        ("min", num(aggregate.min())),
+                        ("avg", num(aggregate.avg())),
+                        ("max", num(aggregate.max())),
+                    ])?;
+                }
+                self.end_stream()
+            }
+            ("dump", ["events", range @ ..], _) if parse_event_range(range).is_some() => {
+                let (range, filter) = parse_event_range(range).unwrap_or_default();
+                let events = select(f.events.iter().cloned().collect(), range, |r| r.t);
+                self.epoch_note()?;
+                let keep = |e: &LoggedEvent| match filter {
+                    Some(("severity", n)) => e.event.code().severity() as u64 >= n,
+                    Some((_, n)) => e.event.code() as u64 == n,
+                    None => true,
+                };
+                for event in events.into_iter().filter(keep) {
+                    let mut fields = vec![("t", Field::Int(event.t))];
+                    fields.extend(event_fields("code", &event));
+                    self.labelled(&fields)?;
+                }
+                self.end_stream()
+            }
+            ("erase", [], _) => {
+                f.temps.clear();
+                f.temps_on.clear();
+                f.minutes.clear();
+                f.hours.clear();
+                f.minute.clear();
+                f.hour.clear();
+                f.events.clear();
+                f.event(Event::StorageErased);
+                self.ok(&[])
+            }
+            ("reset", [], _) => {
+                self.ok(&[("msg", Field::Str("Resetting...".into()))])?;
+                // The real-time clock keeps running through a reset
+                let unix = self.fridge.unix();
+                self.fridge = Fridge::new(
+                    self.fridge.ambient,
+                    ResetCause::Software,
+                    self.fridge.boots + 1,
+                );
+                self.fridge.epoch = unix;
+                self.mode = Mode::Text;
+                Ok(())
+            }
+            (
+                "mode" | "devices" | "sensor" | "resolution" | "target" | "pid" | "temp" | "cooler"
+                | "status" | "stats" | "alarm" | "ack" | "silence" | "time" | "crashlog" | "watch"
+                | "dump" | "erase" | "reset",
+                _,
+                _,
+            ) => self.error(3, "invalid argument", line),
+            _ => self.error(1, "unknown command", name),
+        }
+    }
+}
+
+/// Formats a date & time in ISO 8601, as the firmware does
+fn iso_date(date: DateTime) -> String {
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    )
+}
+
+/// Last occurrence & number of times of a repeated event, its code as `key` & the fields of its
+/// payload, as the firmware prints them
+fn event_fields(key: &'static str, logged: &LoggedEvent) -> Vec<(&'static str, Field)> {
+    let temp = |t: Temperature| Field::Num(t.to_num());
+    let mut fields = Vec::new();
+    if logged.times > 1 {
+        fields.extend([
+            ("last", Field::Int(logged.last)),
+            ("times", Field::Int(logged.times.into())),
+        ]);
+    }
+    let event = &logged.event;
+    fields.push((key, Field::Str(event.code().as_str().into())));
+    match *event {
+        Event::Unknown | Event::PidError | Event::StorageErased => {}
+        Event::TempSensorError { error, address } | Event::TempSensorLost { error, address } => {
+            fields.extend([
+                ("error", Field::Str(error.as_str().into())),
+                ("sensor", Field::Str(format!("{address:016X}"))),
+            ]);
+        }
+        Event::TempSensorResolutionChanged { bits, address } => fields.extend([
+            ("bits", Field::Int(bits.into())),
+            ("sensor", Field::Str(format!("{address:016X}"))),
+        ]),
+        Event::PidTargetChanged { old, new } => {
+            fields.extend([("old", temp(old)), ("new", temp(new))]);
+        }
+        Event::PidParamsChanged { old, new } => fields.extend([
+            ("old_kp", temp(old.kp)),
+            ("old_ki", temp(old.ki)),
+            ("old_kd", temp(old.kd)),
+            ("kp", temp(new.kp)),
+            ("ki", temp(new.ki)),
+            ("kd", temp(new.kd)),
+        ]),
+        Event::Boot { count, cause } => fields.extend([
+            ("count", Field::Int(count.into())),
+            ("cause", Field::Str(cause.as_str().into())),
+        ]),
+        Event::Panic { ref file, line } => fields.extend([
+            ("file", Field::Str(event::text(file).into())),
+            ("line", Field::Int(line.into())),
+        ]),
+        Event::CoolerSwitched { on, duty } => fields.extend([
+            ("cooler", Field::Str(if on { "on" } else { "off" }.into())),
+            ("duty", Field::Int(duty.into())),
+        ]),
+        Event::CoolerModeChanged { old, new } => fields.extend([
+            ("old", Field::Str(old.as_str().into())),
+            ("new", Field::Str(new.as_str().into())),
+        ]),
+        Event::TempSensorFound { address } | Event::TempSensorSelected { address } => {
+            fields.push(("sensor", Field::Str(format!("{address:016X}"))));
+        }
+        Event::ClockSet { old, new } => {
+            fields.extend([
+                ("old", Field::Int(old.into())),
+                ("new", Field::Int(new.into())),
+            ]);
+        }
+        Event::TaskStalled { task } => fields.push(("task", Field::Str(task.as_str().into()))),
+        Event::AlarmRaised {
+            kind,
+            temp: t,
+            threshold,
+        } => fields.extend([
+            ("kind", Field::Str(kind.as_str().into())),
+            ("temp", temp(t)),
+            ("threshold", temp(threshold)),
+        ]),
+        Event::AlarmCleared { kind, temp: t } => {
+            fields.extend([
+                ("kind", Field::Str(kind.as_str().into())),
+                ("temp", temp(t)),
+            ]);
+        }
+        Event::AlarmAcknowledged { kind } => {
+            fields.push(("kind", Field::Str(kind.as_str().into())))
+        }
+        Event::AlarmSilenced { secs } => fields.push(("secs", Field::Int(secs.into()))),
+        Event::AlarmThresholdsChanged { low, high, delay } => fields.extend([
+            ("low", temp(low)),
+            ("high", temp(high)),
+            ("delay", Field::Int(delay.into())),
+        ]),
+    }
+    fields
+}
+
+/// Fields of the state, thresholds & silencing of the alarms, as the firmware prints them
+fn alarm_fields(alarm: &Alarm, secs: u64) -> Vec<(&'static str, Field)> {
+    let num = |temp: Temperature| Field::Num(temp.to_num());
+    let state = alarm.state();
+    let mut fields = vec![("alarm", Field::Str(state.as_str().into()))];
+    if let Some(kind) = state.kind() {
+        fields.push(("kind", Field::Str(kind.as_str().into())));
+    }
+    let now = u32::try_from(secs).unwrap_or(u32::MAX);
+    fields.extend([
+        ("low", num(alarm.config.low)),
+        ("high", num(alarm.config.high)),
+        ("delay", Field::Int(alarm.config.delay.into())),
+        ("silenced", Field::Int(alarm.silenced(now).into())),
+    ]);
+    fields
+}
+
+/// A temperature as the firmware keeps it
+fn temperature(temp: f64) -> Temperature {
+    Temperature::saturating_from_num(temp)
+}
+
+fn gains([kp, ki, kd]: [f64; 3]) -> PidGains {
+    PidGains {
+        kp: temperature(kp),
+        ki: temperature(ki),
+        kd: temperature(kd),
+    }
+}
+
+fn cooler_mode(mode: &str) -> CoolerMode {
+    match mode {
+        "on" => CoolerMode::On,
+        "off" => CoolerMode::Off,
+        _ => CoolerMode::Auto,
+    }
+}
+
+/// Appends a record, dropping the oldest one if there are `max` already
+fn push_bounded<T>(records: &mut VecDeque<T>, record: T, max: usize) {
+    if records.len() == max {
+        records.pop_front();
+    }
+    records.push_back(record);
+}
+
+/// Range of a dump, `None` if invalid & `Some(None)` for all records
+fn parse_range<'a>(args: &[&'a str]) -> Option<Option<(&'a str, u64)>> {
+    match *args {
+        [] => Some(None),
+        [range @ ("last" | "since"), n] => n.parse().ok().map(|n| Some((range, n))),
+        _ => None,
+    }
+}
+
+/// Parses the range of an event dump, or its filter by at least a severity or by code
+#[allow(clippy::type_complexity)]
+fn parse_event_range<'a>(
+    args: &[&'a str],
+) -> Option<(Option<(&'a str, u64)>, Option<(&'a str, u64)>)> {
+    match *args {
+        ["severity", n] => n
+            .parse()
+            .ok()
+            .filter(|n| Severity::from_u8(*n).is_some())
+            .map(|n| (None, Some(("severity", n.into())))),
+        ["code", n] => n.parse().ok().map(|n| (None, Some(("code", n)))),
+        _ => parse_range(args).map(|range| (range, None)),
+    }
+}
+
+/// Selects the records of a dump, `secs` being the timestamp of a record
+fn select<T>(records: Vec<T>, range: Option<(&str, u64)>, secs: impl Fn(&T) -> u64) -> Vec<T> {
+    match range {
+        None => records,
+        Some(("last", n)) => {
+            let skip = records
+                .len()
+                .saturating_sub(usize::try_from(n).unwrap_or(usize::MAX));
+            records.into_iter().skip(skip).collect()
+        }
+        Some((_, since)) => records.into_iter().filter(|r| secs(r) >= since).collect(),
+    }
+}
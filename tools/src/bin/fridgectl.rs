@@ -0,0 +1,558 @@
+//! Controls the fridge over its serial console.
+//!
+//! Wraps the commands of the console, see `help` on the console for what they do.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand, ValueEnum};
+use fridge_core::{delta::Encoder, record::PreciseTemp, thermometer::Temperature};
+use fridge_tools::console::{get_f64, get_str, Console, Record};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Parser)]
+#[command(about)]
+struct Args {
+    /// Serial device or pseudo terminal of the console
+    #[arg(short, long)]
+    port: String,
+
+    /// Baud rate of the serial device
+    #[arg(short, long, default_value_t = 115_200)]
+    baud: u32,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Get the current temperature
+    Temp,
+    /// Get or set the target temperature
+    Target {
+        /// Target temperature in degrees Celsius
+        #[arg(allow_negative_numbers = true)]
+        temp: Option<f64>,
+    },
+    /// Get or set the PID gains
+    Pid {
+        /// Proportional, integral & derivative gains
+        #[arg(num_args = 3, value_names = ["KP", "KI", "KD"], allow_negative_numbers = true)]
+        gains: Option<Vec<f64>>,
+    },
+    /// Show a snapshot of the health of the fridge
+    Status,
+    /// Show statistics of the stored temperatures, to compare fridges
+    Stats {
+        /// Only the last SECS seconds
+        #[arg(long, value_name = "SECS")]
+        last: Option<u32>,
+    },
+    /// Show the state of the temperature alarms, or set their thresholds
+    Alarm {
+        /// Low & high thresholds in degrees Celsius
+        #[arg(num_args = 2, value_names = ["LOW", "HIGH"], allow_negative_numbers = true)]
+        thresholds: Option<Vec<f64>>,
+
+        /// Seconds the temperature has to stay past a threshold before alarming
+        #[arg(long, value_name = "SECS", default_value_t = 300)]
+        delay: u32,
+    },
+    /// Acknowledge the raised alarm, turning the alert output off until it clears
+    Ack,
+    /// Turn the alert output off for a while
+    Silence {
+        /// Seconds to keep it off, 0 to turn it back on
+        secs: u32,
+    },
+    /// Get the wall-clock time of the fridge, or set it to the time of this computer
+    Time {
+        /// Set the clock of the fridge to the current time
+        #[arg(long)]
+        sync: bool,
+    },
+    /// Show the last panic of the fridge
+    Crashlog,
+    /// Download stored temperatures, aggregates or events as CSV
+    Dump {
+        what: Log,
+
+        /// Only the last N records
+        #[arg(long, value_name = "N", conflicts_with = "since")]
+        last: Option<u32>,
+
+        /// Only records stored at or after SECS seconds since startup
+        #[arg(long, value_name = "SECS")]
+        since: Option<u32>,
+
+        /// Only events of at least a severity
+        #[arg(long, conflicts_with_all = ["last", "since", "code"])]
+        severity: Option<Severity>,
+
+        /// Only events of a code, e.g. 1 for temperature sensor errors
+        #[arg(long, conflicts_with_all = ["last", "since"])]
+        code: Option<u8>,
+
+        /// Write temperatures delta-compressed instead of as CSV, see `fridge-replay`
+        #[arg(long)]
+        compress: bool,
+
+        /// Write Unix times instead of seconds since startup, needs the clock to be set
+        #[arg(long, conflicts_with = "compress")]
+        absolute: bool,
+
+        /// File to write, stdout if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Plot temperatures live
+    Watch {
+        /// Number of temperatures to plot, until interrupted if not given
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
+    },
+    /// Back up the configuration to a JSON file
+    Backup {
+        /// File to write, stdout if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Restore the configuration from a backup
+    Restore { backup: PathBuf },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Log {
+    Temps,
+    /// Per-minute minimum, average & maximum temperatures
+    Minutes,
+    /// Per-hour minimum, average & maximum temperatures
+    Hours,
+    Events,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Configuration kept in a backup
+#[derive(Debug, Serialize, Deserialize)]
+struct Config {
+    target: f64,
+    kp: f64,
+    ki: f64,
+    kd: f64,
+    resolution: u64,
+    sensor: String,
+    /// Cooler mode, `auto`, `on` or `off`
+    cooler: String,
+    /// Alarm thresholds
+    low: f64,
+    high: f64,
+    /// Seconds past a threshold before raising its alarm
+    delay: u64,
+}
+
+/// Width of the plot in characters
+const PLOT_WIDTH: usize = 60;
+/// Seconds between stored temperatures of the firmware
+const CONTROL_PERIOD: u32 = 2;
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let mut console = Console::open(&args.port, args.baud)?;
+
+    match args.command {
+        Command::Temp => {
+            let r = console.command("temp")?;
+            println!("{} {}", get_f64(&r, "t")?, get_f64(&r, "temp")?);
+        }
+        Command::Target { temp: Some(temp) } => {
+            console.command(&format!("target {temp}"))?;
+        }
+        Command::Target { temp: None } => {
+            println!("{}", get_f64(&console.command("target")?, "target")?);
+        }
+        Command::Pid { gains: Some(gains) } => {
+            console.command(&format!("pid {} {} {}", gains[0], gains[1], gains[2]))?;
+        }
+        Command::Pid { gains: None } => {
+            let r = console.command("pid")?;
+            println!(
+                "{} {} {}",
+                get_f64(&r, "kp")?,
+                get_f64(&r, "ki")?,
+                get_f64(&r, "kd")?
+            );
+        }
+        Command::Status => print_labelled(&mut console, "status")?,
+        Command::Crashlog => print_labelled(&mut console, "crashlog")?,
+        Command::Stats { last } => {
+            let command = match last {
+                Some(secs) => format!("stats {secs}"),
+                None => "stats".to_owned(),
+            };
+            print_labelled(&mut console, &command)?;
+        }
+        Command::Alarm {
+            thresholds: Some(thresholds),
+            delay,
+        } => {
+            let (low, high) = (thresholds[0], thresholds[1]);
+            if low >= high {
+                bail!("the low threshold must be below the high one");
+            }
+            console.command(&format!("alarm {low} {high} {delay}"))?;
+        }
+        Command::Alarm {
+            thresholds: None, ..
+        } => print_labelled(&mut console, "alarm")?,
+        Command::Ack => {
+            console.command("ack").context("no alarm to acknowledge")?;
+        }
+        Command::Silence { secs } => {
+            console.command(&format!("silence {secs}"))?;
+        }
+        Command::Time { sync: true } => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            console.command(&format!("time {now}"))?;
+        }
+        Command::Time { sync: false } => {
+            let r = console.command("time")?;
+            match r.get("date") {
+                Some(_) => println!("{} {}", get_f64(&r, "unix")?, get_str(&r, "date")?),
+                None => println!("not set, see `fridgectl time --sync`"),
+            }
+        }
+        Command::Dump {
+            what,
+            last,
+            since,
+            severity,
+            code,
+            compress,
+            absolute,
+            output,
+        } => {
+            if compress && what != Log::Temps {
+                bail!("only temperatures can be compressed");
+            }
+            if (severity.is_some() || code.is_some()) && what != Log::Events {
+                bail!("only events can be filtered by severity or code");
+            }
+            let mut out: Box<dyn Write> = match &output {
+                Some(path) => Box::new(BufWriter::new(
+                    File::create(path).with_context(|| format!("creating {}", path.display()))?,
+                )),
+                None => Box::new(io::stdout().lock()),
+            };
+            let range = match (last, since, severity, code) {
+                (Some(n), ..) => format!(" last {n}"),
+                (None, Some(secs), ..) => format!(" since {secs}"),
+                (None, None, Some(severity), _) => format!(" severity {}", severity as u8),
+                (None, None, None, Some(code)) => format!(" code {code}"),
+                (None, None, None, None) => String::new(),
+            };
+            if compress {
+                dump_compressed(&mut console, &range, &mut out)?;
+            } else {
+                let mut notes = Notes::new(absolute);
+                dump(&mut console, what, &range, &mut notes, &mut out)?;
+            }
+            out.flush()?;
+        }
+        Command::Watch { count } => watch(&mut console, count)?,
+        Command::Backup { output } => {
+            let config = backup(&mut console)?;
+            let json = serde_json::to_string_pretty(&config)?;
+            match output {
+                Some(path) => fs::write(&path, json + "\n")
+                    .with_context(|| format!("writing {}", path.display()))?,
+                None => println!("{json}"),
+            }
+        }
+        Command::Restore { backup } => {
+            let json = fs::read_to_string(&backup)
+                .with_context(|| format!("reading {}", backup.display()))?;
+            let config: Config = serde_json::from_str(&json)
+                .with_context(|| format!("parsing {}", backup.display()))?;
+            restore(&mut console, &config)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the labelled records of a command as `key value` lines
+fn print_labelled<P: io::Read + Write>(
+    console: &mut Console<P>,
+    command: &str,
+) -> anyhow::Result<()> {
+    let mut out = io::stdout().lock();
+    console.stream(command, |r| {
+        for (key, value) in r {
+            match value.as_str() {
+                Some(s) => writeln!(out, "{key:<12} {s}")?,
+                None => writeln!(out, "{key:<12} {value}")?,
+            }
+        }
+        Ok(true)
+    })
+}
+
+fn dump<P: io::Read + Write>(
+    console: &mut Console<P>,
+    what: Log,
+    range: &str,
+    notes: &mut Notes,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let t = notes.column();
+    match what {
+        Log::Temps => {
+            writeln!(out, "{t},temp")?;
+            console.stream(&format!("dump temps{range}"), |r| {
+                if !notes.skip(r) {
+                    writeln!(out, "{},{}", notes.time(r)?, get_f64(r, "temp")?)?;
+                }
+                Ok(true)
+            })
+        }
+        Log::Minutes | Log::Hours => {
+            let tier = if what == Log::Minutes {
+                "minutes"
+            } else {
+                "hours"
+            };
+            writeln!(out, "{t},min,avg,max")?;
+            console.stream(&format!("dump {tier}{range}"), |r| {
+                if !notes.skip(r) {
+                    writeln!(
+                        out,
+                        "{},{},{},{}",
+                        notes.time(r)?,
+                        get_f64(r, "min")?,
+                        get_f64(r, "avg")?,
+                        get_f64(r, "max")?
+                    )?;
+                }
+                Ok(true)
+            })
+        }
+        Log::Events => {
+            writeln!(out, "{t},last,times,code,fields")?;
+            console.stream(&format!("dump events{range}"), |r| {
+                if notes.skip(r) {
+                    return Ok(true);
+                }
+                // The fields depend on the code, so they share a column as `key=value` pairs
+                let fields = r
+                    .iter()
+                    .filter(|(key, _)| !matches!(key.as_str(), "t" | "last" | "times" | "code"))
+                    .map(|(key, value)| match value.as_str() {
+                        Some(s) => format!("{key}={s}"),
+                        None => format!("{key}={value}"),
+                    })
+                    .collect::<Vec<_>>();
+                // A single occurrence has no separate last time
+                let t = notes.time(r)?;
+                let last = match r.get("last").and_then(serde_json::Value::as_f64) {
+                    Some(last) => notes.convert(last)?,
+                    None => t,
+                };
+                let times = r
+                    .get("times")
+                    .and_then(serde_json::Value::as_u64)
+                    .unwrap_or(1);
+                writeln!(
+                    out,
+                    "{t},{last},{times},{},{}",
+                    csv_field(get_str(r, "code")?),
+                    csv_field(&fields.join(" "))
+                )?;
+                Ok(true)
+            })
+        }
+    }
+}
+
+/// Dumps temperatures in the delta-compressed format of [`fridge_core::delta`]
+fn dump_compressed<P: io::Read + Write>(
+    console: &mut Console<P>,
+    range: &str,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let mut bytes = Vec::new();
+    // The text dump doesn't tell the format of the fridge, the precise one holds either
+    let mut encoder = Encoder::<PreciseTemp>::new(CONTROL_PERIOD);
+    let mut notes = Notes::new(false);
+    console.stream(&format!("dump temps{range}"), |r| {
+        if !notes.skip(r) {
+            let secs = u32::try_from(get_f64(r, "t")? as u64)?;
+            let temp = Temperature::saturating_from_num(get_f64(r, "temp")?);
+            encoder.push(PreciseTemp::from_temp(secs, temp), &mut |b| bytes.push(b));
+        }
+        Ok(true)
+    })?;
+    encoder.flush(&mut |b| bytes.push(b));
+    out.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Notes the fridge prints between the records of a dump
+struct Notes {
+    /// Whether to convert timestamps to Unix times
+    absolute: bool,
+    /// Unix time at startup of the fridge, if its clock is set
+    epoch: Option<f64>,
+}
+
+impl Notes {
+    const fn new(absolute: bool) -> Self {
+        Self {
+            absolute,
+            epoch: None,
+        }
+    }
+
+    /// Name of the timestamp column
+    const fn column(&self) -> &'static str {
+        if self.absolute {
+            "unix"
+        } else {
+            "t"
+        }
+    }
+
+    /// Handles a note, returns whether `r` was a note rather than a record
+    ///
+    /// Warns about records the fridge overwrote during the dump & keeps the epoch of the
+    /// timestamps.
+    fn skip(&mut self, r: &Record) -> bool {
+        if let Some(lost) = r.get("lost") {
+            eprintln!("warning: {lost} records were overwritten during the dump");
+            return true;
+        }
+        if let Some(epoch) = r.get("epoch") {
+            self.epoch = epoch.as_f64();
+            return true;
+        }
+        false
+    }
+
+    /// Timestamp of a record, as a Unix time if absolute
+    fn time(&self, r: &Record) -> anyhow::Result<f64> {
+        self.convert(get_f64(r, "t")?)
+    }
+
+    /// Seconds since startup, as a Unix time if absolute
+    fn convert(&self, t: f64) -> anyhow::Result<f64> {
+        if !self.absolute {
+            return Ok(t);
+        }
+        let epoch = self
+            .epoch
+            .context("the clock of the fridge isn't set, see `fridgectl time --sync`")?;
+        Ok(epoch + t)
+    }
+}
+
+/// Quotes a CSV field if needed
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Plots temperatures as they come in, with the target as a reference line
+fn watch<P: io::Read + Write>(
+    console: &mut Console<P>,
+    count: Option<usize>,
+) -> anyhow::Result<()> {
+    let target = get_f64(&console.command("target")?, "target")?;
+    let mut min = target - 1.0;
+    let mut max = target + 1.0;
+    let mut seen = 0;
+
+    let mut out = io::stdout().lock();
+    console.stream("watch temps", |r| {
+        let t = get_f64(r, "t")?;
+        let temp = get_f64(r, "temp")?;
+        // Only ever grow the scale, so earlier lines stay comparable
+        min = min.min(temp);
+        max = max.max(temp);
+
+        let col = |v: f64| {
+            // Scaled into the plot, truncation is intended
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let col = ((v - min) / (max - min) * (PLOT_WIDTH - 1) as f64).round() as usize;
+            col.min(PLOT_WIDTH - 1)
+        };
+        let mut plot = [b' '; PLOT_WIDTH];
+        plot[col(target)] = b':';
+        plot[col(temp)] = b'*';
+
+        writeln!(
+            out,
+            "{t:>8} {temp:>8} |{}| {min}..{max}",
+            String::from_utf8_lossy(&plot)
+        )?;
+        out.flush()?;
+
+        seen += 1;
+        Ok(count.is_none_or(|count| seen < count))
+    })
+}
+
+fn backup<P: io::Read + Write>(console: &mut Console<P>) -> anyhow::Result<Config> {
+    let pid = console.command("pid")?;
+    let mut alarm = None;
+    console.stream("alarm", |r| {
+        alarm = Some(r.clone());
+        Ok(true)
+    })?;
+    let alarm = alarm.context("missing alarm in response")?;
+    Ok(Config {
+        target: get_f64(&console.command("target")?, "target")?,
+        kp: get_f64(&pid, "kp")?,
+        ki: get_f64(&pid, "ki")?,
+        kd: get_f64(&pid, "kd")?,
+        resolution: console
+            .command("resolution")?
+            .get("resolution")
+            .and_then(serde_json::Value::as_u64)
+            .context("missing resolution in response")?,
+        sensor: get_str(&console.command("sensor")?, "sensor")?.to_owned(),
+        cooler: get_str(&console.command("cooler")?, "mode")?.to_owned(),
+        low: get_f64(&alarm, "low")?,
+        high: get_f64(&alarm, "high")?,
+        delay: alarm
+            .get("delay")
+            .and_then(serde_json::Value::as_u64)
+            .context("missing delay in response")?,
+    })
+}
+
+fn restore<P: io::Read + Write>(console: &mut Console<P>, config: &Config) -> anyhow::Result<()> {
+    console.command(&format!("resolution {}", config.resolution))?;
+    console.command(&format!("sensor {}", config.sensor))?;
+    console.command(&format!("pid {} {} {}", config.kp, config.ki, config.kd))?;
+    console.command(&format!("target {}", config.target))?;
+    console.command(&format!("cooler {}", config.cooler))?;
+    console.command(&format!(
+        "alarm {} {} {}",
+        config.low, config.high, config.delay
+    ))?;
+    Ok(())
+}
@@ -0,0 +1,206 @@
+//! Client of the firmware's serial console
+//!
+//! Switches the console to JSON lines & runs its commands, see `help` on the console for the
+//! command set.
+
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use serde_json::{Map, Value};
+use serialport::SerialPort;
+
+/// A single JSON line printed by the console
+pub type Record = Map<String, Value>;
+
+/// Time to wait for a line from the console
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Time to wait for the console to switch to JSON lines before retrying
+const SYNC_TIMEOUT: Duration = Duration::from_secs(1);
+/// Number of attempts to switch the console to JSON lines
+const SYNC_ATTEMPTS: usize = 5;
+
+const CTRL_C: &[u8] = b"\x03";
+
+/// Console of the fridge on a serial port or pseudo terminal
+pub struct Console<P> {
+    port: BufReader<P>,
+    /// Partial line left by a timed out read
+    line: Vec<u8>,
+}
+
+impl Console<Box<dyn SerialPort>> {
+    /// Opens the console on a serial device
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device can't be opened or the console doesn't respond.
+    pub fn open(path: &str, baud: u32) -> anyhow::Result<Self> {
+        let port = serialport::new(path, baud)
+            .timeout(SYNC_TIMEOUT)
+            .open()
+            .with_context(|| format!("opening {path}"))?;
+        let mut console = Self::new(port)?;
+        console.port.get_mut().set_timeout(TIMEOUT)?;
+        Ok(console)
+    }
+}
+
+impl<P: Read + Write> Console<P> {
+    /// Takes over the console on `port` & switches it to JSON lines
+    ///
+    /// `port` should time out reads, or this blocks forever if the console doesn't respond.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the console doesn't respond.
+    pub fn new(port: P) -> anyhow::Result<Self> {
+        let mut console = Self {
+            port: BufReader::new(port),
+            line: Vec::new(),
+        };
+
+        for _ in 0..SYNC_ATTEMPTS {
+            // Cancel anything in progress, e.g. a half typed line or a watch left running. A
+            // running watch only stops at the next temperature & drops any input until then, so
+            // keep asking until the console answers in JSON.
+            console.send(CTRL_C)?;
+            console.send_line("mode json")?;
+            console.send_line("mode")?;
+
+            loop {
+                match console.read_record() {
+                    Ok(record) if record.get("mode").and_then(Value::as_str) == Some("json") => {
+                        return Ok(console);
+                    }
+                    Ok(_) => {}
+                    Err(e) if e.kind() == ErrorKind::TimedOut => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        bail!("console not responding")
+    }
+
+    /// Runs a command, returning its response
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command fails or the console doesn't respond.
+    pub fn command(&mut self, line: &str) -> anyhow::Result<Record> {
+        self.send_line(line)?;
+        loop {
+            let record = self.read_record()?;
+            if record.contains_key("status") {
+                return check_status(record).with_context(|| format!("running `{line}`"));
+            }
+        }
+    }
+
+    /// Runs a command answered with a stream of records
+    ///
+    /// `f` is called for every record until the stream ends or `f` returns `false`, which
+    /// cancels the command.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command or `f` fails, or the console doesn't respond.
+    pub fn stream(
+        &mut self,
+        line: &str,
+        mut f: impl FnMut(&Record) -> anyhow::Result<bool>,
+    ) -> anyhow::Result<()> {
+        self.send_line(line)?;
+        let mut cancelled = false;
+        loop {
+            let record = self.read_record()?;
+            if record.contains_key("status") {
+                check_status(record).with_context(|| format!("running `{line}`"))?;
+                return Ok(());
+            }
+            if !cancelled && !f(&record)? {
+                self.send(CTRL_C)?;
+                cancelled = true;
+            }
+        }
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let port = self.port.get_mut();
+        port.write_all(bytes)?;
+        port.flush()
+    }
+
+    fn send_line(&mut self, line: &str) -> io::Result<()> {
+        self.send(format!("{line}\r").as_bytes())
+    }
+
+    /// Reads the next JSON line, skipping the echo of typed commands
+    ///
+    /// Unsolicited alerts of the alarms may arrive in between, they are shown on stderr.
+    fn read_record(&mut self) -> io::Result<Record> {
+        loop {
+            self.port.read_until(b'\n', &mut self.line)?;
+            if self.line.last() != Some(&b'\n') {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+
+            let line = std::mem::take(&mut self.line);
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if !line.starts_with('{') {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(Value::Object(record)) if record.contains_key("alert") => {
+                    eprintln!("{line}");
+                }
+                Ok(Value::Object(record)) => return Ok(record),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Turns an error status into an error
+fn check_status(record: Record) -> anyhow::Result<Record> {
+    if record.get("status").and_then(Value::as_str) == Some("ok") {
+        return Ok(record);
+    }
+
+    let error = record
+        .get("error")
+        .and_then(Value::as_str)
+        .unwrap_or("error");
+    match record.get("arg").and_then(Value::as_str) {
+        Some(arg) => Err(anyhow!("{error}: {arg}")),
+        None => Err(anyhow!("{error}")),
+    }
+}
+
+/// Gets a number field of a record
+///
+/// # Errors
+///
+/// Returns an error if the field is missing or not a number.
+pub fn get_f64(record: &Record, key: &str) -> anyhow::Result<f64> {
+    record
+        .get(key)
+        .and_then(Value::as_f64)
+        .ok_or_else(|| anyhow!("missing number `{key}` in response"))
+}
+
+/// Gets a string field of a record
+///
+/// # Errors
+///
+/// Returns an error if the field is missing or not a string.
+pub fn get_str<'a>(record: &'a Record, key: &str) -> anyhow::Result<&'a str> {
+    record
+        .get(key)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("missing string `{key}` in response"))
+}
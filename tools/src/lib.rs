@@ -0,0 +1,3 @@
+//! Host side helpers shared by the fridge tools.
+
+pub mod console;